@@ -3,16 +3,14 @@
 // license that can be found in the LICENSE file.
 
 use gain::stream::Write;
-use gain::task::block_on;
 use gain::{catalog, origin};
 
-fn main() {
-    block_on(async {
-        origin::accept()
-            .await
-            .unwrap()
-            .write_all(&catalog::json().await.as_bytes())
-            .await
-            .unwrap()
-    })
+#[gain::main]
+async fn main() {
+    origin::accept()
+        .await
+        .unwrap()
+        .write_all(catalog::json().await.as_bytes())
+        .await
+        .unwrap()
 }
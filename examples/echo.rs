@@ -5,24 +5,21 @@
 use gain::origin;
 use gain::stream::buf::{Read, ReadWriteStream, DEFAULT_READ_CAPACITY};
 use gain::stream::Write;
-use gain::task::block_on;
-use std::process::exit;
 
-fn main() {
-    exit(block_on(async {
-        let mut c = ReadWriteStream::new(origin::accept().await.unwrap());
-        let mut b = [0; DEFAULT_READ_CAPACITY];
+#[gain::main]
+async fn main() -> i32 {
+    let mut c = ReadWriteStream::new(origin::accept().await.unwrap());
+    let mut b = [0; DEFAULT_READ_CAPACITY];
 
-        loop {
-            let n = match c.read(&mut b[..]).await {
-                Ok(n) => n,
-                Err(_) => return 0,
-            };
+    loop {
+        let n = match c.read(&mut b[..]).await {
+            Ok(n) => n,
+            Err(_) => return 0,
+        };
 
-            match c.write_all(&b[..n]).await {
-                Ok(_) => {}
-                Err(_) => return 1,
-            }
+        match c.write_all(&b[..n]).await {
+            Ok(_) => {}
+            Err(_) => return 1,
         }
-    }))
+    }
 }
@@ -5,7 +5,7 @@
 #![cfg_attr(target_os = "unknown", no_main)]
 
 use gain::stream::{Close, Write};
-use gain::task::{block_on, spawn};
+use gain::task::spawn;
 use gain::{identity, origin};
 
 async fn do_stuff() {
@@ -31,26 +31,24 @@ async fn do_stuff() {
     println!("Origin connection dropped");
 }
 
-fn hello() -> i32 {
-    block_on(async {
-        let handle = spawn(async {
-            println!("In another task");
-            0 // Code for success.
-        });
-        do_stuff().await;
-        handle.await.unwrap()
-    })
+async fn hello() -> i32 {
+    let handle = spawn(async {
+        println!("In another task");
+        0 // Code for success.
+    });
+    do_stuff().await;
+    handle.await.unwrap()
 }
 
 // Invoked by the implicit entry function of a one-shot wasm32-wasi program.
-#[cfg(target_os = "wasi")]
-fn main() {
-    std::process::exit(hello());
+#[gain::main]
+async fn main() -> i32 {
+    hello().await
 }
 
 // An explicit entry function for a reentrant wasm32-unknown-unknown program.
 #[cfg(target_os = "unknown")]
-#[no_mangle]
-pub fn greet() -> i32 {
-    hello()
+#[gain::export]
+async fn greet() -> i32 {
+    hello().await
 }
@@ -4,21 +4,20 @@
 
 use gain::origin;
 use gain::stream::Write;
-use gain::task::{block_on, spawn_local};
+use gain::task::spawn_local;
 use gain_localhost as localhost;
 
-fn main() {
-    block_on(async {
-        let mut tasks = Vec::new();
+#[gain::main]
+async fn main() {
+    let mut tasks = Vec::new();
 
-        tasks.push(spawn_local(handle("/robots.txt")));
-        tasks.push(spawn_local(handle("/nonexistent")));
-        tasks.push(spawn_local(handle_output("/")));
+    tasks.push(spawn_local(handle("/robots.txt")));
+    tasks.push(spawn_local(handle("/nonexistent")));
+    tasks.push(spawn_local(handle_output("/")));
 
-        for t in tasks {
-            t.await;
-        }
-    });
+    for t in tasks {
+        t.await;
+    }
 }
 
 async fn handle(uri: &str) {
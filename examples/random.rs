@@ -3,15 +3,14 @@
 // license that can be found in the LICENSE file.
 
 use gain::random::random;
-use gain::task::{block_on, spawn_local, yield_now};
+use gain::task::{spawn_local, yield_now};
 
-fn main() {
-    block_on(async {
-        let h1 = spawn_local(f1());
-        let h2 = spawn_local(f2());
-        h1.await.unwrap();
-        h2.await.unwrap();
-    })
+#[gain::main]
+async fn main() {
+    let h1 = spawn_local(f1());
+    let h2 = spawn_local(f2());
+    h1.await.unwrap();
+    h2.await.unwrap();
 }
 
 async fn f1() {
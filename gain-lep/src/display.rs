@@ -7,7 +7,7 @@
 use lep::Obj;
 
 use crate::future::{Fut, FutState};
-use crate::stream::{ReadStreamRef, ReadWriteStreamRef, WriteStreamRef};
+use crate::stream::StreamRef;
 
 /// Stringify Lep and Gain object types.
 ///
@@ -51,16 +51,8 @@ pub fn stringify_ex<F: Fn(&Obj) -> Option<String> + Clone>(x: &Obj, f: F) -> Opt
             });
         }
 
-        if x.is::<ReadWriteStreamRef>() {
-            return Some("ReadWriteStream".to_string());
-        }
-
-        if x.is::<ReadStreamRef>() {
-            return Some("ReadStream".to_string());
-        }
-
-        if x.is::<WriteStreamRef>() {
-            return Some("WriteStream".to_string());
+        if let Some(stream) = x.downcast_ref::<StreamRef>() {
+            return Some(stream.type_name().to_string());
         }
 
         if let Some(data) = x.downcast_ref::<Vec<u8>>() {
@@ -4,7 +4,6 @@
 
 //! Origin service bindings.
 
-use std::cell::RefCell;
 use std::rc::Rc;
 
 use gain::origin;
@@ -12,6 +11,7 @@ use gain::stream::buf::ReadWriteStream;
 use lep::{Domain, Obj, Res};
 
 use crate::future::future_obj;
+use crate::stream::StreamRef;
 
 /// Register all origin functions.
 pub fn register(d: &mut Domain) {
@@ -23,7 +23,7 @@ pub fn accept(args: &Obj) -> Res {
     if args.is::<()>() {
         Ok(future_obj(async {
             match origin::accept().await {
-                Ok(conn) => Ok(Rc::new(RefCell::new(ReadWriteStream::new(conn))) as Obj),
+                Ok(conn) => Ok(Rc::new(StreamRef::read_write(ReadWriteStream::new(conn))) as Obj),
                 Err(e) => Err(format!("origin/accept: {}", e)),
             }
         }))
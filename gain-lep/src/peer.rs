@@ -4,7 +4,6 @@
 
 //! Peer service bindings.
 
-use std::cell::RefCell;
 use std::rc::Rc;
 
 use gain::peer;
@@ -12,6 +11,7 @@ use gain::stream::buf::ReadWriteStream;
 use lep::{Domain, Obj, Pair, Res};
 
 use crate::future::future_obj;
+use crate::stream::StreamRef;
 
 /// Register all peer functions.
 pub fn register(d: &mut Domain) {
@@ -42,7 +42,7 @@ pub fn connect(args: &Obj) -> Res {
                     .await
                     {
                         Ok((conn, _)) => {
-                            Ok(Rc::new(RefCell::new(ReadWriteStream::new(conn))) as Obj)
+                            Ok(Rc::new(StreamRef::read_write(ReadWriteStream::new(conn))) as Obj)
                         }
                         Err(e) => Err(format!("peer/connect: {}", e)),
                     }
@@ -2,12 +2,16 @@
 // Use of this source code is governed by the MIT
 // license that can be found in the LICENSE file.
 
-use gain::stream::buf::{Buf, Read, ReadWriteStream};
+use std::str::Utf8Error;
+
+use gain::stream::buf::{ReadExt, ReadWriteStream};
 use gain::stream::{RecvWriteStream, Write};
 use lep::{eval_stmt, obj, Domain, State};
 
 use crate::{obj_future, stringify};
 
+const MAX_LINE_LEN: usize = 65536;
+
 /// Read, evaluate and print in a loop.
 pub async fn repl(conn: RecvWriteStream, domain: Domain<'_>, state: State) -> (Domain<'_>, State) {
     repl_default(conn, domain, state, || vec![b'\r']).await
@@ -24,52 +28,28 @@ pub async fn repl_default<DefaultFn: Fn() -> Vec<u8>>(
 ) -> (Domain<'_>, State) {
     let mut conn = ReadWriteStream::new(conn);
 
-    let mut buf = Vec::new();
-    let mut eof = false;
-    let mut read = true;
-
     loop {
-        if !eof && read {
-            eof = match conn
-                .buf_read(1, |b: &mut Buf| {
-                    buf.extend_from_slice(b.as_slice());
-                    b.consume_all();
-                    true
-                })
-                .await
-            {
-                Ok(not_eof) => !not_eof,
-                Err(e) => {
-                    println!("receive error: {}", e);
-                    return (domain, state);
-                }
-            };
-
-            read = false;
-        }
-
-        let mut input: Option<String> = None;
+        let mut line = String::new();
         let mut output = String::new();
-        for i in 0..buf.len() {
-            if buf[i] == b'\n' {
-                let tail = buf.split_off(i + 1);
-                buf.resize(i, 0);
-
-                match String::from_utf8(buf) {
-                    Ok(s) => input = Some(s),
-                    Err(e) => output = format!("parse error: {}\n", e),
-                }
 
-                buf = tail;
-                break;
+        let input = match conn.read_line(&mut line, MAX_LINE_LEN).await {
+            Ok(0) => return (domain, state),
+            Ok(_) => Some(line.trim_end_matches('\n')),
+            Err(e) if e.get_ref().is_some_and(|e| e.is::<Utf8Error>()) => {
+                output = format!("parse error: {}\n", e);
+                None
             }
-        }
+            Err(e) => {
+                println!("receive error: {}", e);
+                return (domain, state);
+            }
+        };
 
         if let Some(input) = input {
             if input.trim_start().is_empty() {
                 output = String::from_utf8_lossy(default_reply().as_slice()).to_string();
             } else {
-                match eval_stmt(&mut domain, state.clone(), &input) {
+                match eval_stmt(&mut domain, state.clone(), input) {
                     Ok(new_state) => {
                         let value = &new_state.result.value;
                         let name = &new_state.result.name;
@@ -99,10 +79,6 @@ pub async fn repl_default<DefaultFn: Fn() -> Vec<u8>>(
                     Err(e) => output = format!("error: {}\n", e),
                 }
             }
-        } else if eof {
-            return (domain, state);
-        } else {
-            read = true;
         }
 
         if !output.is_empty() {
@@ -7,20 +7,61 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use gain::stream::buf::{Buf, Read, ReadStream, ReadWriteStream};
-use gain::stream::{Close, Write, WriteStream};
+use gain::stream::buf::Buf;
+use gain::stream::{DynClose, DynRead, DynWrite};
 use lep::{obj, Domain, Obj, Pair, Res};
 
 use crate::future::future_obj;
 
-/// Bidirectional stream object.
-pub type ReadWriteStreamRef = RefCell<ReadWriteStream>;
+/// Stream object.
+///
+/// Wraps any kind of stream which supports buffered reading and/or writing.
+pub struct StreamRef {
+    read: Option<Rc<RefCell<dyn DynRead>>>,
+    write: Option<Rc<RefCell<dyn DynWrite>>>,
+    close: Rc<RefCell<dyn DynClose>>,
+}
 
-/// Input stream object.
-pub type ReadStreamRef = RefCell<ReadStream>;
+impl StreamRef {
+    /// Bidirectional stream object.
+    pub fn read_write<T: DynRead + DynWrite + DynClose + 'static>(stream: T) -> Self {
+        let stream = Rc::new(RefCell::new(stream));
+        Self {
+            read: Some(stream.clone()),
+            write: Some(stream.clone()),
+            close: stream,
+        }
+    }
 
-/// Output stream object.
-pub type WriteStreamRef = RefCell<WriteStream>;
+    /// Input stream object.
+    pub fn read<T: DynRead + DynClose + 'static>(stream: T) -> Self {
+        let stream = Rc::new(RefCell::new(stream));
+        Self {
+            read: Some(stream.clone()),
+            write: None,
+            close: stream,
+        }
+    }
+
+    /// Output stream object.
+    pub fn write<T: DynWrite + DynClose + 'static>(stream: T) -> Self {
+        let stream = Rc::new(RefCell::new(stream));
+        Self {
+            read: None,
+            write: Some(stream.clone()),
+            close: stream,
+        }
+    }
+
+    /// Type name for display purposes.
+    pub fn type_name(&self) -> &'static str {
+        match (self.read.is_some(), self.write.is_some()) {
+            (true, true) => "ReadWriteStream",
+            (true, false) => "ReadStream",
+            _ => "WriteStream",
+        }
+    }
+}
 
 /// Register all stream function.
 pub fn register(d: &mut Domain) {
@@ -31,35 +72,24 @@ pub fn register(d: &mut Domain) {
 
 /// The `<-` function.
 pub fn receive(args: &Obj) -> Res {
-    async fn read_vec<T: Read>(stream: &RefCell<T>) -> Res {
-        let mut v = Vec::new();
-        match stream
-            .borrow_mut()
-            .buf_read(1, |b: &mut Buf| v.extend_from_slice(b.as_slice()))
-            .await
-        {
-            Ok(()) => Ok(Rc::new(v) as Obj),
-            Err(e) => Err(format!("read: {}", e)),
-        }
-    }
-
     if let Some(pair) = args.downcast_ref::<Pair>() {
         if pair.1.is::<()>() {
-            let arg = pair.0.clone();
-
-            if arg.is::<ReadWriteStreamRef>() {
-                return Ok(future_obj(async move {
-                    read_vec(arg.downcast_ref::<ReadWriteStreamRef>().unwrap()).await
-                }));
-            }
-
-            if arg.is::<ReadStreamRef>() {
-                return Ok(future_obj(async move {
-                    read_vec(arg.downcast_ref::<ReadStreamRef>().unwrap()).await
-                }));
-            }
-
-            return Err("not an input stream".to_string());
+            let stream = match pair.0.downcast_ref::<StreamRef>() {
+                Some(StreamRef {
+                    read: Some(stream), ..
+                }) => stream.clone(),
+                _ => return Err("not an input stream".to_string()),
+            };
+
+            return Ok(future_obj(async move {
+                let mut v = Vec::new();
+                let mut receptor = |b: &mut Buf| v.extend_from_slice(b.as_slice());
+                let result = stream.borrow_mut().dyn_buf_read(1, &mut receptor).await;
+                match result {
+                    Ok(()) => Ok(Rc::new(v) as Obj),
+                    Err(e) => Err(format!("read: {}", e)),
+                }
+            }));
         }
     }
 
@@ -68,38 +98,30 @@ pub fn receive(args: &Obj) -> Res {
 
 /// The `->` function.
 pub fn send(args: &Obj) -> Res {
-    async fn write_all<T: Write>(stream: &RefCell<T>, data: &String) -> Res {
-        match stream.borrow_mut().write_all(data.as_bytes()).await {
-            Ok(_) => Ok(obj::nil()),
-            Err(e) => Err(format!("write: {}", e)),
-        }
-    }
-
     if let Some(pair0) = args.downcast_ref::<Pair>() {
         if let Some(pair1) = pair0.1.downcast_ref::<Pair>() {
             if pair1.1.is::<()>() {
-                let arg0 = pair0.0.clone();
-                let arg1 = pair1.0.clone();
+                let stream = match pair0.0.downcast_ref::<StreamRef>() {
+                    Some(StreamRef {
+                        write: Some(stream),
+                        ..
+                    }) => stream.clone(),
+                    _ => return Err("not an output stream".to_string()),
+                };
 
+                let arg1 = pair1.0.clone();
                 if !arg1.is::<String>() {
                     return Err("not a string".to_string());
                 }
 
-                if arg0.is::<ReadWriteStreamRef>() {
-                    return Ok(future_obj(async move {
-                        let data = arg1.downcast_ref::<String>().unwrap();
-                        write_all(arg0.downcast_ref::<ReadWriteStreamRef>().unwrap(), data).await
-                    }));
-                }
-
-                if arg0.is::<WriteStreamRef>() {
-                    return Ok(future_obj(async move {
-                        let data = arg1.downcast_ref::<String>().unwrap();
-                        write_all(arg0.downcast_ref::<WriteStreamRef>().unwrap(), data).await
-                    }));
-                }
-
-                return Err("not an output stream".to_string());
+                return Ok(future_obj(async move {
+                    let data = arg1.downcast_ref::<String>().unwrap();
+                    let result = stream.borrow_mut().dyn_write_all(data.as_bytes()).await;
+                    match result {
+                        Ok(()) => Ok(obj::nil()),
+                        Err(e) => Err(format!("write: {}", e)),
+                    }
+                }));
             }
         }
     }
@@ -109,34 +131,18 @@ pub fn send(args: &Obj) -> Res {
 
 /// The `close` function.
 pub fn close(args: &Obj) -> Res {
-    async fn close<T: Close>(stream: &RefCell<T>) -> Res {
-        stream.borrow_mut().close().await;
-        Ok(obj::nil())
-    }
-
     if let Some(pair) = args.downcast_ref::<Pair>() {
         if pair.1.is::<()>() {
-            let arg = pair.0.clone();
-
-            if arg.is::<ReadWriteStreamRef>() {
-                return Ok(future_obj(async move {
-                    close(arg.downcast_ref::<ReadWriteStreamRef>().unwrap()).await
-                }));
-            }
-
-            if arg.is::<ReadStreamRef>() {
-                return Ok(future_obj(async move {
-                    close(arg.downcast_ref::<ReadStreamRef>().unwrap()).await
-                }));
-            }
-
-            if arg.is::<WriteStreamRef>() {
-                return Ok(future_obj(async move {
-                    close(arg.downcast_ref::<WriteStreamRef>().unwrap()).await
-                }));
-            }
-
-            return Err("not a stream".to_string());
+            let stream = match pair.0.downcast_ref::<StreamRef>() {
+                Some(s) => s.close.clone(),
+                None => return Err("not a stream".to_string()),
+            };
+
+            return Ok(future_obj(async move {
+                let future = stream.borrow_mut().dyn_close();
+                future.await;
+                Ok(obj::nil())
+            }));
         }
     }
 
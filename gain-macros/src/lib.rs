@@ -0,0 +1,196 @@
+// Copyright (c) 2026 Timo Savola.
+// Use of this source code is governed by the MIT
+// license that can be found in the LICENSE file.
+
+//! Procedural macros for the Gain framework.
+//!
+//! The macros are re-exported by the `gain` crate; use them through it.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{parse_macro_input, DeriveInput, Error, ItemFn};
+
+mod typed;
+
+/// Run an async function as the program's main function.
+///
+/// The function is executed using `gain::task::block_on`, and its return
+/// value is converted into the exit status using the `gain::task::ReturnCode`
+/// trait.
+///
+/// ```ignore
+/// #[gain::main]
+/// async fn main() {
+///     do_something().await;
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn main(args: TokenStream, item: TokenStream) -> TokenStream {
+    let f = parse_macro_input!(item as ItemFn);
+    match expand_main(args.into(), f) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// Export an async function as an entry function.
+///
+/// The function is exported with an unmangled name and the `i32` return type,
+/// so that it can be invoked by the runtime.  It is executed using
+/// `gain::task::block_on`, and its return value is converted using the
+/// `gain::task::ReturnCode` trait.
+///
+/// A reentrant wasm32-unknown-unknown program has no main function, so the
+/// crate must declare `#![cfg_attr(target_os = "unknown", no_main)]`.  (An
+/// attribute macro cannot do it on behalf of the crate.)  A `#[gain::main]`
+/// function may still be defined for other targets.  Other attributes of the
+/// function are kept, so the export can be restricted with `#[cfg]`.
+///
+/// ```ignore
+/// #![cfg_attr(target_os = "unknown", no_main)]
+///
+/// #[gain::main]
+/// async fn main() -> i32 {
+///     hello().await
+/// }
+///
+/// #[cfg(target_os = "unknown")]
+/// #[gain::export]
+/// async fn greet() -> i32 {
+///     hello().await
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn export(args: TokenStream, item: TokenStream) -> TokenStream {
+    let f = parse_macro_input!(item as ItemFn);
+    match expand_export(args.into(), f) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// Derive `gain::service::typed::Encode` for a struct.
+///
+/// Fields are encoded in order.  Strings and vectors are prefixed by their
+/// length as `u8`, unless the field is annotated with `#[gain(prefix = u16)]`
+/// (or `u32`), or `#[gain(rest)]` which leaves out the prefix.  Only the last
+/// field can be `rest`.
+#[proc_macro_derive(Encode, attributes(gain))]
+pub fn derive_encode(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as DeriveInput);
+    match typed::expand_encode(input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// Derive `gain::service::typed::Decode` for a struct.
+///
+/// The field attributes are the same as with [`Encode`](derive@Encode).  A
+/// `rest` field consumes the remainder of the reply.
+#[proc_macro_derive(Decode, attributes(gain))]
+pub fn derive_decode(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as DeriveInput);
+    match typed::expand_decode(input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// Derive `gain::service::typed::ServiceCall` for a struct.
+///
+/// The reply type is specified with `#[gain(reply = Type)]`.
+#[proc_macro_derive(ServiceCall, attributes(gain))]
+pub fn derive_service_call(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as DeriveInput);
+    match typed::expand_service_call(input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand_main(args: proc_macro2::TokenStream, f: ItemFn) -> syn::Result<proc_macro2::TokenStream> {
+    check_signature(&args, &f, "main")?;
+
+    let attrs = &f.attrs;
+    let vis = &f.vis;
+    let ident = &f.sig.ident;
+    let inner = inner_fn(&f);
+
+    // The function is unused in a no_main crate, which is the case when only
+    // explicit entry functions are used on wasm32-unknown-unknown.
+    Ok(quote! {
+        #(#attrs)*
+        #[allow(dead_code)]
+        #vis fn #ident() {
+            #inner
+            ::std::process::exit(::gain::task::ReturnCode::return_code(
+                ::gain::task::block_on(__gain_entry()),
+            ))
+        }
+    })
+}
+
+fn expand_export(
+    args: proc_macro2::TokenStream,
+    f: ItemFn,
+) -> syn::Result<proc_macro2::TokenStream> {
+    check_signature(&args, &f, "export")?;
+
+    let attrs = &f.attrs;
+    let ident = &f.sig.ident;
+    let inner = inner_fn(&f);
+
+    Ok(quote! {
+        #(#attrs)*
+        #[no_mangle]
+        pub fn #ident() -> i32 {
+            #inner
+            ::gain::task::ReturnCode::return_code(::gain::task::block_on(__gain_entry()))
+        }
+    })
+}
+
+fn check_signature(args: &proc_macro2::TokenStream, f: &ItemFn, name: &str) -> syn::Result<()> {
+    if !args.is_empty() {
+        return Err(Error::new(
+            Span::call_site(),
+            format!("#[gain::{}] does not take arguments", name),
+        ));
+    }
+
+    if f.sig.asyncness.is_none() {
+        return Err(Error::new_spanned(
+            f.sig.fn_token,
+            format!("#[gain::{}] requires an async function", name),
+        ));
+    }
+
+    if !f.sig.inputs.is_empty() {
+        return Err(Error::new_spanned(
+            &f.sig.inputs,
+            format!("#[gain::{}] function cannot take parameters", name),
+        ));
+    }
+
+    if !f.sig.generics.params.is_empty() || f.sig.generics.where_clause.is_some() {
+        return Err(Error::new_spanned(
+            &f.sig.generics,
+            format!("#[gain::{}] function cannot be generic", name),
+        ));
+    }
+
+    Ok(())
+}
+
+// The original function is kept intact (apart from its name) so that its
+// return type drives type inference within the body.
+fn inner_fn(f: &ItemFn) -> proc_macro2::TokenStream {
+    let output = &f.sig.output;
+    let block = &f.block;
+
+    quote! {
+        async fn __gain_entry() #output #block
+    }
+}
@@ -0,0 +1,175 @@
+// Copyright (c) 2026 Timo Savola.
+// Use of this source code is governed by the MIT
+// license that can be found in the LICENSE file.
+
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote};
+use syn::{Data, DeriveInput, Error, Fields, Index, Member, Type};
+
+enum Layout {
+    Plain,
+    Prefixed(Box<Type>),
+    Rest,
+}
+
+struct Field {
+    member: Member,
+    layout: Layout,
+}
+
+pub fn expand_encode(input: DeriveInput) -> syn::Result<TokenStream> {
+    let fields = parse_fields(&input)?;
+
+    let encodes = fields.iter().map(|f| {
+        let m = &f.member;
+        match &f.layout {
+            Layout::Plain => quote! {
+                ::gain::service::typed::Encode::encode(&self.#m, buf)?;
+            },
+            Layout::Prefixed(p) => quote! {
+                ::gain::service::typed::EncodeSeq::encode_prefixed::<#p>(&self.#m, buf)?;
+            },
+            Layout::Rest => quote! {
+                ::gain::service::typed::EncodeSeq::encode_rest(&self.#m, buf)?;
+            },
+        }
+    });
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::gain::service::typed::Encode for #ident #ty_generics #where_clause {
+            fn encode(
+                &self,
+                buf: &mut ::std::vec::Vec<u8>,
+            ) -> ::std::result::Result<(), ::gain::service::typed::CallError> {
+                #(#encodes)*
+                ::std::result::Result::Ok(())
+            }
+        }
+    })
+}
+
+pub fn expand_decode(input: DeriveInput) -> syn::Result<TokenStream> {
+    let fields = parse_fields(&input)?;
+
+    let vars: Vec<_> = (0..fields.len())
+        .map(|i| format_ident!("__field{}", i))
+        .collect();
+
+    let decodes = fields.iter().zip(&vars).map(|(f, v)| match &f.layout {
+        Layout::Plain => quote! {
+            let #v = ::gain::service::typed::Decode::decode(r)?;
+        },
+        Layout::Prefixed(p) => quote! {
+            let #v = ::gain::service::typed::DecodeSeq::decode_prefixed::<#p>(r)?;
+        },
+        Layout::Rest => quote! {
+            let #v = ::gain::service::typed::DecodeSeq::decode_rest(r)?;
+        },
+    });
+
+    let members = fields.iter().map(|f| &f.member);
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::gain::service::typed::Decode for #ident #ty_generics #where_clause {
+            fn decode(
+                r: &mut ::gain::service::typed::Reader,
+            ) -> ::std::result::Result<Self, ::gain::service::typed::CallError> {
+                #(#decodes)*
+                ::std::result::Result::Ok(Self { #(#members: #vars),* })
+            }
+        }
+    })
+}
+
+pub fn expand_service_call(input: DeriveInput) -> syn::Result<TokenStream> {
+    let mut reply = None;
+
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("gain")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("reply") {
+                reply = Some(meta.value()?.parse::<Type>()?);
+                Ok(())
+            } else {
+                Err(meta.error("unsupported gain attribute"))
+            }
+        })?;
+    }
+
+    let reply = reply.ok_or_else(|| {
+        Error::new(
+            Span::call_site(),
+            "ServiceCall derive requires #[gain(reply = Type)]",
+        )
+    })?;
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::gain::service::typed::ServiceCall for #ident #ty_generics #where_clause {
+            type Reply = #reply;
+        }
+    })
+}
+
+fn parse_fields(input: &DeriveInput) -> syn::Result<Vec<Field>> {
+    let data = match &input.data {
+        Data::Struct(data) => data,
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "only structs can be derived",
+            ))
+        }
+    };
+
+    let iter: Box<dyn Iterator<Item = &syn::Field>> = match &data.fields {
+        Fields::Named(f) => Box::new(f.named.iter()),
+        Fields::Unnamed(f) => Box::new(f.unnamed.iter()),
+        Fields::Unit => Box::new(std::iter::empty()),
+    };
+
+    let mut fields: Vec<Field> = Vec::new();
+
+    for (i, f) in iter.enumerate() {
+        if let Some(prev) = fields.last() {
+            if let Layout::Rest = prev.layout {
+                return Err(Error::new_spanned(
+                    f,
+                    "#[gain(rest)] field must be the last one",
+                ));
+            }
+        }
+
+        let member = match &f.ident {
+            Some(ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(Index::from(i)),
+        };
+
+        let mut layout = Layout::Plain;
+
+        for attr in f.attrs.iter().filter(|a| a.path().is_ident("gain")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rest") {
+                    layout = Layout::Rest;
+                    Ok(())
+                } else if meta.path.is_ident("prefix") {
+                    layout = Layout::Prefixed(Box::new(meta.value()?.parse()?));
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported gain attribute"))
+                }
+            })?;
+        }
+
+        fields.push(Field { member, layout });
+    }
+
+    Ok(fields)
+}
@@ -9,29 +9,42 @@ extern crate lazy_static;
 
 use std::fmt;
 
-use gain::service::Service;
+use gain::service::typed::{Decode, Encode, ServiceCall};
+use gain::service::{Service, ServiceError};
 use gain::stream::RecvStream;
 
 lazy_static! {
     static ref SERVICE: Service = Service::register("gate.computer/shell");
 }
 
+#[derive(Encode, ServiceCall)]
+#[gain(reply = SpawnReply)]
+struct SpawnCall<'a> {
+    #[gain(rest)]
+    command: &'a str,
+}
+
+#[derive(Decode)]
+struct SpawnReply {
+    error: i16,
+    _pad: [u8; 2],
+    id: i32,
+}
+
 pub async fn spawn(command: &str) -> Result<RecvStream, Error> {
-    SERVICE
-        .call(command.as_bytes(), |reply: &[u8]| {
-            let error = i16::from_le_bytes(reply[..2].try_into().unwrap());
-            let id = i32::from_le_bytes(reply[4..8].try_into().unwrap());
-
-            if id >= 0 {
-                let stream = SERVICE.input_stream(id);
-                if error == 0 {
-                    return Ok(stream);
-                }
-            }
-
-            Err(Error::new(error))
-        })
+    let reply = SERVICE
+        .call_typed(&SpawnCall { command })
         .await
+        .map_err(ServiceError::from)?;
+
+    if reply.id >= 0 {
+        let stream = SERVICE.try_input_stream(reply.id);
+        if reply.error == 0 {
+            return Ok(stream.map_err(ServiceError::from)?);
+        }
+    }
+
+    Err(Error::new(reply.error))
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -42,19 +55,28 @@ pub enum ErrorKind {
     User,
     WorkDir,
     Executable,
+    Service,
 }
 
 #[derive(Debug)]
 pub struct Error {
     code: i16,
+    service: Option<ServiceError>,
 }
 
 impl Error {
     fn new(code: i16) -> Self {
-        Self { code }
+        Self {
+            code,
+            service: None,
+        }
     }
 
     pub fn kind(&self) -> ErrorKind {
+        if self.service.is_some() {
+            return ErrorKind::Service;
+        }
+
         match self.code {
             1 => ErrorKind::Scope,
             2 => ErrorKind::Quota,
@@ -65,13 +87,33 @@ impl Error {
         }
     }
 
+    /// The error code reported by the service.  Zero for errors of kind
+    /// `Service`.
     pub fn as_i16(&self) -> i16 {
         self.code
     }
+
+    /// The program-side error of kind `Service`.
+    pub fn service_error(&self) -> Option<&ServiceError> {
+        self.service.as_ref()
+    }
+}
+
+impl From<ServiceError> for Error {
+    fn from(e: ServiceError) -> Self {
+        Self {
+            code: 0,
+            service: Some(e),
+        }
+    }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        if let Some(e) = &self.service {
+            return e.fmt(f);
+        }
+
         match self.kind() {
             ErrorKind::Scope => f.write_str("restricted scope"),
             ErrorKind::Quota => f.write_str("not enough quota"),
@@ -12,9 +12,6 @@ lazy_static! {
 
 /// Get a JSON document describing available services.
 pub async fn json() -> String {
-    SERVICE
-        .call("json".as_bytes(), |reply: &[u8]| {
-            String::from_utf8_lossy(reply).to_string()
-        })
-        .await
+    let reply = SERVICE.call_vec(b"json").await;
+    String::from_utf8_lossy(&reply).into_owned()
 }
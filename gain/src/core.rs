@@ -3,15 +3,17 @@
 // license that can be found in the LICENSE file.
 
 use std::cell::RefCell;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::convert::Infallible;
 use std::error;
 use std::fmt;
 use std::future::Future;
 use std::io::{self, Error, ErrorKind};
 use std::marker::PhantomData;
-use std::mem::take;
 use std::mem::transmute;
+use std::mem::{replace, take};
 use std::num::NonZeroI32;
+use std::ops::ControlFlow;
 use std::pin::Pin;
 use std::process::exit;
 use std::ptr::NonNull;
@@ -20,12 +22,15 @@ use std::slice;
 use std::task::{Context, Poll, Waker};
 use std::time::Duration;
 
+use futures_util::future::LocalBoxFuture;
+
 use crate::gate::{self, Ciovec, Iovec, MAX_RECV_SIZE};
 use crate::packet::{
     self, Code, StreamId, ALIGNMENT, CODE_SERVICES, DATA_HEADER_SIZE, DOMAIN_CALL, DOMAIN_DATA,
     DOMAIN_FLOW, DOMAIN_INFO, FLOW_SIZE, HEADER_SIZE, SERVICES_HEADER_SIZE, SERVICE_STATE_AVAIL,
 };
-use crate::service::RegistrationError;
+use crate::service::{RegistrationError, StreamInitError, TimedOut};
+use crate::stream::RateLimiter;
 use crate::task::spawn_local;
 use crate::threadunsafe::ThreadUnsafeRefCell;
 
@@ -37,12 +42,15 @@ lazy_static! {
     static ref STREAMS: ThreadUnsafeRefCell<HashMap<(Code, StreamId), Stream>> = Default::default();
     static ref SEND_LIST: ThreadUnsafeRefCell<SendList> = Default::default();
     static ref RECV_BUF: ThreadUnsafeRefCell<RecvBuf> = Default::default();
+    static ref TIMERS: ThreadUnsafeRefCell<Timers> = Default::default();
 }
 
 struct ServiceState {
     avail_or_blocked: SendList,
     replies: SendList,
     info_recv: Recv,
+    stats: StreamStats, // Aggregated over the service's streams.
+    rate_limiter: Option<RateLimiter>,
 }
 
 impl ServiceState {
@@ -51,6 +59,8 @@ impl ServiceState {
             avail_or_blocked: SendList::default(),
             replies: SendList::default(),
             info_recv: Recv::None,
+            stats: StreamStats::default(),
+            rate_limiter: None,
         }
     }
 
@@ -165,6 +175,23 @@ impl Default for Recv {
 
 pub type Stream = Rc<RefCell<StreamState>>;
 
+/// Counterpart of an in-process stream.  Packets written to a local stream
+/// are passed to its peer instead of the runtime.  The peer delivers packets
+/// in the other direction using `receive_local_data` and `receive_local_flow`.
+pub(crate) trait LocalPeer {
+    /// Data packet.  Empty data closes the direction; the note is the
+    /// closing note in that case.
+    fn send_data(&self, id: StreamId, data: &[u8], note: i32);
+
+    /// Flow increment.  Zero closes the direction; a negative increment
+    /// conveys an error.
+    fn send_flow(&self, id: StreamId, increment: i32);
+
+    /// Both directions of the stream have been closed.  This may be called
+    /// more than once.
+    fn closed(&self, id: StreamId);
+}
+
 pub struct StreamState {
     code: Code,
     id: StreamId,
@@ -175,12 +202,31 @@ pub struct StreamState {
     writable: usize,
     writer: Option<Waker>,
     write_err: i32,
+    unsent: usize,
+    flusher: Option<Waker>,
     closers: Vec<Waker>,
 
     close_flow_share: Share,
-    close_flow_packet: [u8; HEADER_SIZE + FLOW_SIZE],
+    close_flow_packet: [u8; HEADER_SIZE + 2 * FLOW_SIZE],
     close_data_share: Share,
     close_data_packet: [u8; DATA_HEADER_SIZE],
+    close_note: i32,
+    close_flow_err: i32,
+
+    write_timeout: Option<Duration>,
+    write_deadline: Option<Deadline>,
+
+    rate_limiter: Option<RateLimiter>,
+    rate_deadline: Option<Deadline>,
+
+    stats: StreamStats,
+    blocked_since: Option<u64>, // Monotonic clock time.
+
+    recv_unreceived: i32,        // Subscribed by a stopped reception.
+    recv_queue: VecDeque<Chunk>, // Copied out of RECV_BUF, or sent by a local peer.
+
+    local: Option<Rc<dyn LocalPeer>>,
+    local_credit: u64, // Granted to the local peer, but not received yet.
 }
 
 impl StreamState {
@@ -195,12 +241,157 @@ impl StreamState {
             writable: 0,
             writer: None,
             write_err: 0,
+            unsent: 0,
+            flusher: None,
             closers: Vec::new(),
 
             close_flow_share: Share::default(),
-            close_flow_packet: [0; HEADER_SIZE + FLOW_SIZE],
+            close_flow_packet: [0; HEADER_SIZE + 2 * FLOW_SIZE],
             close_data_share: Share::default(),
             close_data_packet: [0; DATA_HEADER_SIZE],
+            close_note: 0,
+            close_flow_err: 0,
+
+            write_timeout: None,
+            write_deadline: None,
+
+            rate_limiter: None,
+            rate_deadline: None,
+
+            stats: StreamStats::default(),
+            blocked_since: None,
+
+            recv_unreceived: 0,
+            recv_queue: VecDeque::new(),
+
+            local: None,
+            local_credit: 0,
+        }
+    }
+
+    /// Ready when a writer has been waiting for flow credit for longer than
+    /// the write timeout.  The waiting time is counted as blocked.
+    fn poll_write_timeout(&mut self, cx: &mut Context) -> Poll<()> {
+        if self.blocked_since.is_none() {
+            self.blocked_since = Some(gate::clock_monotonic());
+        }
+
+        if let Some(timeout) = self.write_timeout {
+            let deadline = self
+                .write_deadline
+                .get_or_insert_with(|| Deadline::after(timeout));
+
+            if deadline.poll(cx).is_ready() {
+                self.write_deadline = None;
+                self.unblocked();
+                return Poll::Ready(());
+            }
+        }
+
+        Poll::Pending
+    }
+
+    /// Limit a write size by the rate limiters of the stream and its
+    /// service, and consume the tokens.  Pending until at least one token is
+    /// available.
+    fn poll_rate_limit(&mut self, cx: &mut Context, n: usize) -> Poll<usize> {
+        let service_limiter = if self.local.is_none() {
+            SERVICE_STATES.borrow()[self.code as usize]
+                .rate_limiter
+                .clone()
+        } else {
+            None
+        };
+
+        if n == 0 || (self.rate_limiter.is_none() && service_limiter.is_none()) {
+            return Poll::Ready(n);
+        }
+
+        let limiters = self.rate_limiter.iter().chain(service_limiter.iter());
+
+        let mut avail = n as u64;
+        let mut delay = Some(Duration::ZERO);
+
+        for limiter in limiters.clone() {
+            let a = limiter.available();
+            if a == 0 {
+                delay = match (delay, limiter.delay()) {
+                    (Some(x), Some(y)) => Some(std::cmp::max(x, y)),
+                    _ => None,
+                };
+                limiter.wait(cx.waker());
+            }
+            avail = std::cmp::min(avail, a);
+        }
+
+        if avail > 0 {
+            self.rate_deadline = None;
+            for limiter in limiters {
+                limiter.take(avail);
+            }
+            return Poll::Ready(avail as usize);
+        }
+
+        // Recreate the deadline in case a limiter has been adjusted.
+        self.rate_deadline = delay.map(Deadline::after);
+        if let Some(deadline) = &mut self.rate_deadline {
+            if deadline.poll(cx).is_ready() {
+                cx.waker().wake_by_ref();
+            }
+        }
+
+        self.writer = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    /// Stop counting blocked time.
+    fn unblocked(&mut self) {
+        if let Some(since) = self.blocked_since.take() {
+            let nanos = gate::clock_monotonic().saturating_sub(since);
+            self.count(|x| x.blocked += Duration::from_nanos(nanos));
+        }
+    }
+
+    /// Update the counters of the stream and its service.
+    fn count<F: Fn(&mut StreamStats)>(&mut self, f: F) {
+        f(&mut self.stats);
+        if self.local.is_none() {
+            f(&mut SERVICE_STATES.borrow_mut()[self.code as usize].stats);
+        }
+    }
+
+    /// Account for written data.
+    fn count_sent(&mut self, n: usize) {
+        if n > 0 {
+            self.count(|x| {
+                x.bytes_sent += n as u64;
+                x.packets_sent += 1;
+            });
+        }
+    }
+
+    /// Account for received data.
+    fn count_received(&mut self, n: usize) {
+        if n > 0 {
+            self.count(|x| {
+                x.bytes_received += n as u64;
+                x.packets_received += 1;
+            });
+        }
+    }
+
+    /// Account for flow credit granted to the peer.
+    fn count_granted(&mut self, increment: i32) {
+        if increment > 0 {
+            self.count(|x| x.credit_granted += increment as u64);
+        }
+    }
+
+    /// Register a task to be woken up when the peer closes something.  A
+    /// future which is polled repeatedly is registered only once.
+    fn add_closer(&mut self, waker: &Waker) {
+        if !self.closers.iter().any(|w| w.will_wake(waker)) {
+            self.closers.push(waker.clone());
         }
     }
 
@@ -217,27 +408,62 @@ impl StreamState {
             panic!("stream state still contains closing flags when sending packet");
         }
 
+        if let Some(peer) = &self.local {
+            if (how & STREAM_SELF_FLOW) != 0 {
+                if self.close_flow_err < 0 {
+                    peer.send_flow(self.id, self.close_flow_err);
+                }
+                peer.send_flow(self.id, 0);
+            }
+            if (how & STREAM_SELF_DATA) != 0 {
+                peer.send_data(self.id, &[], self.close_note);
+            }
+            return;
+        }
+
         let mut send_list = SEND_LIST.borrow_mut();
 
         if (how & STREAM_SELF_FLOW) != 0 {
-            let len = self.close_flow_packet.len();
-            packet::header_into(&mut self.close_flow_packet, len, self.code, DOMAIN_FLOW);
-            packet::flow_into(&mut self.close_flow_packet, 0, self.id, 0);
-            self.close_flow_share.send[0] = Ciovec::new(&self.close_flow_packet);
+            // Error is conveyed as negative increment before closing.
+            let count = if self.close_flow_err < 0 { 2 } else { 1 };
+            let len = HEADER_SIZE + count * FLOW_SIZE;
+            let p = &mut self.close_flow_packet[..len];
+            packet::header_into(p, len, self.code, DOMAIN_FLOW);
+            if count == 2 {
+                packet::flow_into(p, 0, self.id, self.close_flow_err);
+            }
+            packet::flow_into(p, count - 1, self.id, 0);
+            self.close_flow_share.send[0] = Ciovec::new(p);
             send_list.push_back(SendLink::new(&mut self.close_flow_share));
         }
 
         if (how & STREAM_SELF_DATA) != 0 {
             let len = self.close_data_packet.len();
-            packet::data_header_into(&mut self.close_data_packet, len, self.code, self.id, 0);
+            let note = self.close_note;
+            packet::data_header_into(&mut self.close_data_packet, len, self.code, self.id, note);
             self.close_data_share.send[0] = Ciovec::new(&self.close_data_packet);
             send_list.push_back(SendLink::new(&mut self.close_data_share));
         }
     }
 
+    /// Copy an unhandled packet out of RECV_BUF to the reception queue.
+    fn queue_received(&mut self) {
+        if let Recv::Some(offset) = take(&mut self.recv) {
+            let mut recv_buf = RECV_BUF.borrow_mut();
+            let p = recv_buf.consume(offset);
+            let data = p[DATA_HEADER_SIZE..].to_vec();
+            self.recv_queue.push_back((data, packet::data_note(p)));
+        }
+    }
+
     fn detach_closed(&self) {
         if self.flags == 0 {
-            STREAMS.borrow_mut().remove(&(self.code, self.id));
+            match &self.local {
+                Some(peer) => peer.closed(self.id),
+                None => {
+                    STREAMS.borrow_mut().remove(&(self.code, self.id));
+                }
+            }
         }
     }
 }
@@ -426,6 +652,10 @@ impl Share {
         unsafe { slice::from_raw_parts(self.send[0].buf, self.send[0].buf_len) }
     }
 
+    fn data(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.send[1].buf, self.send[1].buf_len) }
+    }
+
     fn code(&self) -> Code {
         packet::code(self.header())
     }
@@ -540,19 +770,7 @@ where
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
         if !self.polling {
             self.share.send[0] = Ciovec::new(&self.header);
-            let mut link = Some(SendLink::new(&mut self.share));
-
-            let code = self.share.code();
-            if code >= 0 {
-                let mut service_states = SERVICE_STATES.borrow_mut();
-                if let Some(list) = service_states[code as usize].blocked() {
-                    list.push_back(link.take().unwrap());
-                }
-            }
-
-            if let Some(link) = link.take() {
-                SEND_LIST.borrow_mut().push_back(link);
-            }
+            send_call(&mut self.share);
         } else if let Some(offset) = self.share.reply.offset() {
             let mut recv_buf = RECV_BUF.borrow_mut();
             let x = (self.receptor.take().unwrap())(&recv_buf.consume(offset)[HEADER_SIZE..]);
@@ -579,12 +797,177 @@ where
     }
 }
 
+/// Queue a call packet for sending, unless the service is blocked.
+fn send_call(share: &mut Share) {
+    let mut link = Some(SendLink::new(share));
+
+    let code = share.code();
+    if code >= 0 {
+        let mut service_states = SERVICE_STATES.borrow_mut();
+        if let Some(list) = service_states[code as usize].blocked() {
+            list.push_back(link.take().unwrap());
+        }
+    }
+
+    if let Some(link) = link.take() {
+        SEND_LIST.borrow_mut().push_back(link);
+    }
+}
+
+/// Call packet with owned content.
+struct OwnedCall<B> {
+    share: Share,
+    header: [u8; HEADER_SIZE],
+    content: B,
+}
+
+/// Asynchronous call with owned content.  The future may be dropped or
+/// spawned: the content is kept alive until the call has been sent, and the
+/// reply is discarded if the future is dropped before it arrives.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct CallOwnedFuture<B: AsRef<[u8]> + 'static = Vec<u8>> {
+    p: Option<Box<OwnedCall<B>>>,
+    polling: bool,
+}
+
+impl<B: AsRef<[u8]> + 'static> CallOwnedFuture<B> {
+    pub(crate) fn new(code: Code, content: B) -> Self {
+        let mut p = Box::new(OwnedCall {
+            share: Share::default(),
+            header: [0; HEADER_SIZE],
+            content,
+        });
+        let len = p.header.len() + p.content.as_ref().len();
+        packet::header_into(&mut p.header, len, code, DOMAIN_CALL);
+        p.share.send[0] = Ciovec::new(&p.header);
+        p.share.send[1] = Ciovec::new(p.content.as_ref());
+        p.share.reply = Reply::expected();
+
+        Self {
+            p: Some(p),
+            polling: false,
+        }
+    }
+}
+
+impl<B: AsRef<[u8]> + 'static> Future for CallOwnedFuture<B> {
+    type Output = Vec<u8>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let p = this
+            .p
+            .as_mut()
+            .expect("call future polled after completion");
+
+        if !this.polling {
+            send_call(&mut p.share);
+        } else if let Some(offset) = p.share.reply.offset() {
+            let x = RECV_BUF.borrow_mut().consume(offset)[HEADER_SIZE..].to_vec();
+            this.p = None;
+            this.polling = false;
+            return Poll::Ready(x);
+        }
+
+        p.share.waker = Some(cx.waker().clone());
+        this.polling = true;
+        Poll::Pending
+    }
+}
+
+impl<B: AsRef<[u8]> + 'static> Drop for CallOwnedFuture<B> {
+    fn drop(&mut self) {
+        if self.polling {
+            if let Some(p) = self.p.take() {
+                spawn_local(DiscardReplyFuture(p));
+            }
+        }
+    }
+}
+
+/// Waits for the reply of an abandoned call, and discards it.
+struct DiscardReplyFuture<B>(Box<OwnedCall<B>>);
+
+impl<B> Future for DiscardReplyFuture<B> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if let Some(offset) = self.0.share.reply.offset() {
+            RECV_BUF.borrow_mut().consume(offset);
+            return Poll::Ready(());
+        }
+
+        self.0.share.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// Asynchronous call with a time limit.  The future may be dropped.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct CallTimeoutFuture<B: AsRef<[u8]> + 'static = Vec<u8>> {
+    call: Option<CallOwnedFuture<B>>,
+    deadline: Deadline,
+}
+
+impl<B: AsRef<[u8]> + 'static> CallTimeoutFuture<B> {
+    pub(crate) fn new(code: Code, content: B, timeout: Duration) -> Self {
+        Self {
+            call: Some(CallOwnedFuture::new(code, content)),
+            deadline: Deadline::after(timeout),
+        }
+    }
+}
+
+impl<B: AsRef<[u8]> + 'static> Future for CallTimeoutFuture<B> {
+    type Output = Result<Vec<u8>, TimedOut>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let call = this
+            .call
+            .as_mut()
+            .expect("call future polled after completion");
+
+        if let Poll::Ready(x) = Pin::new(call).poll(cx) {
+            this.call = None;
+            return Poll::Ready(Ok(x));
+        }
+
+        if this.deadline.poll(cx).is_ready() {
+            this.call = None; // Late reply will be discarded.
+            return Poll::Ready(Err(TimedOut));
+        }
+
+        Poll::Pending
+    }
+}
+
+/// Receive info packets until the receptor breaks the loop.
+fn poll_info_recv<B>(
+    code: Code,
+    cx: &mut Context,
+    receptor: impl FnOnce(&[u8]) -> ControlFlow<B>,
+) -> Poll<B> {
+    let mut service_states = SERVICE_STATES.borrow_mut();
+    let service = &mut service_states[code as usize];
+
+    if let Recv::Some(offset) = take(&mut service.info_recv) {
+        let mut recv_buf = RECV_BUF.borrow_mut();
+        if let ControlFlow::Break(x) = receptor(&recv_buf.consume(offset)[HEADER_SIZE..]) {
+            return Poll::Ready(x);
+        }
+    }
+
+    service.info_recv = Recv::Wake(cx.waker().clone());
+    Poll::Pending
+}
+
 /// Asynchronous info packet reception.  Must be polled to completion once
 /// started.
 #[must_use = "futures do nothing unless you `.await` or poll them"]
 pub struct InfoRecvFuture<R>
 where
-    R: Fn(&[u8]) + Unpin,
+    R: FnMut(&[u8]) + Unpin,
 {
     code: Code,
     receptor: R,
@@ -592,7 +975,7 @@ where
 
 impl<R> InfoRecvFuture<R>
 where
-    R: Fn(&[u8]) + Unpin,
+    R: FnMut(&[u8]) + Unpin,
 {
     pub(crate) fn new(code: Code, receptor: R) -> Self {
         Self { code, receptor }
@@ -601,21 +984,48 @@ where
 
 impl<R> Future for InfoRecvFuture<R>
 where
-    R: Fn(&[u8]) + Unpin,
+    R: FnMut(&[u8]) + Unpin,
 {
     type Output = ();
 
-    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
-        let mut service_states = SERVICE_STATES.borrow_mut();
-        let service = &mut service_states[self.code as usize];
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        poll_info_recv(this.code, cx, |b| {
+            (this.receptor)(b);
+            ControlFlow::Continue(())
+        })
+    }
+}
 
-        if let Recv::Some(offset) = take(&mut service.info_recv) {
-            let mut recv_buf = RECV_BUF.borrow_mut();
-            (self.receptor)(&recv_buf.consume(offset)[HEADER_SIZE..]);
-        }
+/// Asynchronous info packet reception which can be stopped by the receptor.
+/// Must be polled to completion once started.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct InfoRecvUntilFuture<R, B>
+where
+    R: FnMut(&[u8]) -> ControlFlow<B> + Unpin,
+{
+    code: Code,
+    receptor: R,
+}
 
-        service.info_recv = Recv::Wake(cx.waker().clone());
-        Poll::Pending
+impl<R, B> InfoRecvUntilFuture<R, B>
+where
+    R: FnMut(&[u8]) -> ControlFlow<B> + Unpin,
+{
+    pub(crate) fn new(code: Code, receptor: R) -> Self {
+        Self { code, receptor }
+    }
+}
+
+impl<R, B> Future for InfoRecvUntilFuture<R, B>
+where
+    R: FnMut(&[u8]) -> ControlFlow<B> + Unpin,
+{
+    type Output = B;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        poll_info_recv(this.code, cx, &mut this.receptor)
     }
 }
 
@@ -684,30 +1094,62 @@ impl Drop for InfoSendFuture<'_> {
     }
 }
 
-/// Asynchronous reception.  Must be polled to completion once started.
-#[must_use = "futures do nothing unless you `.await` or poll them"]
-pub struct StreamRecvFuture<'a, R>
+/// Data receptor which may stop the reception loop.
+pub(crate) trait Receptor {
+    type Break;
+
+    fn receive(&mut self, data: &[u8], note: i32) -> ControlFlow<Self::Break, usize>;
+}
+
+/// Receptor which never stops the loop.
+pub(crate) struct Subscriber<R>(R);
+
+impl<R> Receptor for Subscriber<R>
+where
+    R: FnMut(&[u8], i32) -> usize,
+{
+    type Break = Infallible;
+
+    fn receive(&mut self, data: &[u8], note: i32) -> ControlFlow<Infallible, usize> {
+        ControlFlow::Continue((self.0)(data, note))
+    }
+}
+
+/// Receptor which returns a control value.
+pub(crate) struct Controller<R, B>(R, PhantomData<fn() -> B>);
+
+impl<R, B> Receptor for Controller<R, B>
 where
-    R: Fn(&[u8], i32) -> usize + Unpin,
+    R: FnMut(&[u8], i32) -> ControlFlow<B, usize>,
 {
+    type Break = B;
+
+    fn receive(&mut self, data: &[u8], note: i32) -> ControlFlow<B, usize> {
+        (self.0)(data, note)
+    }
+}
+
+/// Reception loop shared by the reception futures.
+struct RecvLoop<'a, R: Receptor> {
     s: &'a Option<Stream>,
     receptor: R,
     unsubscribed: u64, // Requested by caller, but flow packet not sent yet.
     unreceived: i32,   // Flow packet sent, but data not received yet.
+    started: bool,
+    stopped: Option<R::Break>,
     flow_share: Share,
     flow_packet: [u8; HEADER_SIZE + FLOW_SIZE],
 }
 
-impl<'a, R> StreamRecvFuture<'a, R>
-where
-    R: Fn(&[u8], i32) -> usize + Unpin,
-{
-    pub(crate) fn new(s: &'a Option<Stream>, cap: usize, receptor: R) -> Self {
+impl<'a, R: Receptor> RecvLoop<'a, R> {
+    fn new(s: &'a Option<Stream>, cap: usize, receptor: R) -> Self {
         Self {
             s,
             receptor,
             unsubscribed: cap as u64,
             unreceived: 0,
+            started: false,
+            stopped: None,
             flow_share: Share::default(),
             flow_packet: [0; HEADER_SIZE + FLOW_SIZE],
         }
@@ -719,91 +1161,325 @@ where
     }
 
     fn can_send_flow_packet(&self) -> bool {
-        self.flow_increment() > 0 && self.flow_share.is_sent()
+        self.stopped.is_none() && self.flow_increment() > 0 && self.flow_share.is_sent()
     }
 
-    fn send_flow_packet(&mut self, id: StreamId) {
+    fn send_flow_packet(&mut self, s: &mut StreamState) {
+        let id = s.id;
         let increment = self.flow_increment();
+        s.count_granted(increment);
         self.unsubscribed -= increment as u64;
         self.unreceived += increment;
+        let len = self.flow_packet.len();
+        packet::header_into(&mut self.flow_packet, len, s.code, DOMAIN_FLOW);
         packet::flow_into(&mut self.flow_packet, 0, id, increment);
 
+        self.flow_share.send[0] = Ciovec::new(&self.flow_packet);
         self.flow_share.sent = 0;
         SEND_LIST
             .borrow_mut()
             .push_back(SendLink::new(&mut self.flow_share));
     }
-}
 
-impl<R> Future for StreamRecvFuture<'_, R>
-where
-    R: Fn(&[u8], i32) -> usize + Unpin,
-{
-    type Output = Option<i32>;
+    fn receive(&mut self, data: &[u8], note: i32) {
+        if data.len() > self.unreceived as usize {
+            panic!("received data exceeds subscription");
+        }
+        self.unreceived -= data.len() as i32;
 
-    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        match self.receptor.receive(data, note) {
+            ControlFlow::Continue(n) => {
+                if let Some(n) = self.unsubscribed.checked_add(n as u64) {
+                    self.unsubscribed = n;
+                } else {
+                    panic!("reception capacity out of bounds");
+                }
+            }
+            ControlFlow::Break(x) => self.stopped = Some(x),
+        }
+    }
+
+    /// Grant flow credit and handle queued packets of a local stream.
+    fn receive_local(&mut self, s: &mut StreamState, peer: &dyn LocalPeer) {
+        while self.stopped.is_none() {
+            let increment = self.flow_increment();
+            if increment > 0 {
+                self.unsubscribed -= increment as u64;
+                self.unreceived += increment;
+                s.count_granted(increment);
+                s.local_credit += increment as u64;
+                peer.send_flow(s.id, increment);
+            }
+
+            match s.recv_queue.pop_front() {
+                Some((data, note)) => self.receive(&data, note),
+                None => break,
+            }
+        }
+    }
+
+    fn poll_loop(&mut self, cx: &mut Context) -> Poll<ControlFlow<R::Break, Option<i32>>> {
         if let Some(s) = self.s {
             let mut s = s.borrow_mut();
 
-            if self.can_send_flow_packet() {
-                let len = self.flow_packet.len();
-                packet::header_into(&mut self.flow_packet, len, s.code, DOMAIN_FLOW);
-                self.flow_share.send[0] = Ciovec::new(&self.flow_packet);
-                self.send_flow_packet(s.id);
+            if !self.started {
+                self.started = true;
+                self.unreceived = take(&mut s.recv_unreceived);
             }
 
-            if let Recv::Some(offset) = take(&mut s.recv) {
-                let mut recv_buf = RECV_BUF.borrow_mut();
-                let p = recv_buf.consume(offset);
-                let note = packet::data_note(p);
-                let data = &p[DATA_HEADER_SIZE..];
+            if self.stopped.is_none() {
+                if let Some(peer) = s.local.clone() {
+                    self.receive_local(&mut s, &*peer);
+                } else {
+                    if self.can_send_flow_packet() {
+                        self.send_flow_packet(&mut s);
+                    }
 
-                if data.len() > self.unreceived as usize {
-                    panic!("received data exceeds subscription");
-                }
-                self.unreceived -= data.len() as i32;
+                    while self.stopped.is_none() {
+                        match s.recv_queue.pop_front() {
+                            Some((data, note)) => self.receive(&data, note),
+                            None => break,
+                        }
+                    }
 
-                if let Some(n) = self
-                    .unsubscribed
-                    .checked_add((self.receptor)(data, note) as u64)
-                {
-                    self.unsubscribed = n;
-                } else {
-                    panic!("reception capacity out of bounds");
+                    if self.stopped.is_none() {
+                        if let Recv::Some(offset) = take(&mut s.recv) {
+                            let mut recv_buf = RECV_BUF.borrow_mut();
+                            let p = recv_buf.consume(offset);
+                            self.receive(&p[DATA_HEADER_SIZE..], packet::data_note(p));
+                        }
+                    }
+
+                    if self.can_send_flow_packet() {
+                        self.send_flow_packet(&mut s);
+                    }
                 }
+            }
 
-                if self.can_send_flow_packet() {
-                    self.send_flow_packet(s.id);
+            if self.stopped.is_some() {
+                // The flow packet must not be dropped while it's being sent.
+                if !self.flow_share.is_sent() {
+                    self.flow_share.waker = Some(cx.waker().clone());
+                    return Poll::Pending;
                 }
+
+                // Data which the peer may still send is queued for the next
+                // reception, so that it doesn't hold up the receive buffer.
+                s.queue_received();
+                s.recv_unreceived = self.unreceived;
+                return Poll::Ready(ControlFlow::Break(self.stopped.take().unwrap()));
             }
 
             if (s.flags & STREAM_PEER_DATA) == 0 {
-                return Poll::Ready(Some(s.recv_err)); // Closed.
+                return Poll::Ready(ControlFlow::Continue(Some(s.recv_err))); // Closed.
             }
 
             if self.unsubscribed == 0 && self.unreceived == 0 {
-                return Poll::Ready(None); // Kept open.
+                return Poll::Ready(ControlFlow::Continue(None)); // Kept open.
             }
 
             s.recv = Recv::Wake(cx.waker().clone());
             return Poll::Pending;
         }
 
-        Poll::Ready(Some(0)) // Closed; default note.
+        Poll::Ready(ControlFlow::Continue(Some(0))) // Closed; default note.
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+/// Asynchronous reception.  Must be polled to completion once started.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct StreamRecvFuture<'a, R>
+where
+    R: FnMut(&[u8], i32) -> usize + Unpin,
+{
+    inner: RecvLoop<'a, Subscriber<R>>,
+}
+
+impl<'a, R> StreamRecvFuture<'a, R>
+where
+    R: FnMut(&[u8], i32) -> usize + Unpin,
+{
+    pub(crate) fn new(s: &'a Option<Stream>, cap: usize, receptor: R) -> Self {
+        Self {
+            inner: RecvLoop::new(s, cap, Subscriber(receptor)),
+        }
+    }
+}
+
+impl<R> Future for StreamRecvFuture<'_, R>
+where
+    R: FnMut(&[u8], i32) -> usize + Unpin,
+{
+    type Output = Option<i32>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = unsafe { self.as_mut().get_unchecked_mut() }; // See pin module doc.
+
+        match this.inner.poll_loop(cx) {
+            Poll::Ready(ControlFlow::Continue(x)) => Poll::Ready(x),
+            Poll::Ready(ControlFlow::Break(x)) => match x {},
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Asynchronous reception which can be stopped by the receptor.  Must be
+/// polled to completion once started.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct StreamRecvUntilFuture<'a, R, B>
+where
+    R: FnMut(&[u8], i32) -> ControlFlow<B, usize> + Unpin,
+{
+    inner: RecvLoop<'a, Controller<R, B>>,
+}
+
+impl<'a, R, B> StreamRecvUntilFuture<'a, R, B>
+where
+    R: FnMut(&[u8], i32) -> ControlFlow<B, usize> + Unpin,
+{
+    pub(crate) fn new(s: &'a Option<Stream>, cap: usize, receptor: R) -> Self {
+        Self {
+            inner: RecvLoop::new(s, cap, Controller(receptor, PhantomData)),
+        }
+    }
+}
+
+impl<R, B> Future for StreamRecvUntilFuture<'_, R, B>
+where
+    R: FnMut(&[u8], i32) -> ControlFlow<B, usize> + Unpin,
+{
+    type Output = ControlFlow<B, Option<i32>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = unsafe { self.as_mut().get_unchecked_mut() }; // See pin module doc.
+        this.inner.poll_loop(cx)
+    }
+}
+
+/// Known stream error codes.  The codes are negative so that they can be
+/// conveyed both as data packet notes and as flow increments.
+///
+/// | Code | Kind               | `io::ErrorKind`     |
+/// |------|--------------------|---------------------|
+/// | -1   | `Aborted`          | `ConnectionAborted` |
+/// | -2   | `Reset`            | `ConnectionReset`   |
+/// | -3   | `TimedOut`         | `TimedOut`          |
+/// | -4   | `InvalidData`      | `InvalidData`       |
+/// | -5   | `PermissionDenied` | `PermissionDenied`  |
+/// | -6   | `Unsupported`      | `Unsupported`       |
+///
+/// Other codes are of kind `Other`, and map to `io::ErrorKind::Other`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StreamErrorKind {
+    Other,
+    Aborted,
+    Reset,
+    TimedOut,
+    InvalidData,
+    PermissionDenied,
+    Unsupported,
+}
+
+impl StreamErrorKind {
+    /// Returns `None` for `Other`.
+    pub fn code(self) -> Option<StreamErrorCode> {
+        let n = match self {
+            Self::Other => return None,
+            Self::Aborted => -1,
+            Self::Reset => -2,
+            Self::TimedOut => -3,
+            Self::InvalidData => -4,
+            Self::PermissionDenied => -5,
+            Self::Unsupported => -6,
+        };
+        StreamErrorCode::new(n)
+    }
+}
+
+impl From<StreamErrorKind> for ErrorKind {
+    fn from(kind: StreamErrorKind) -> Self {
+        match kind {
+            StreamErrorKind::Other => ErrorKind::Other,
+            StreamErrorKind::Aborted => ErrorKind::ConnectionAborted,
+            StreamErrorKind::Reset => ErrorKind::ConnectionReset,
+            StreamErrorKind::TimedOut => ErrorKind::TimedOut,
+            StreamErrorKind::InvalidData => ErrorKind::InvalidData,
+            StreamErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+            StreamErrorKind::Unsupported => ErrorKind::Unsupported,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct StreamErrorCode(pub NonZeroI32);
 
+impl StreamErrorCode {
+    pub fn new(code: i32) -> Option<Self> {
+        NonZeroI32::new(code).map(Self)
+    }
+
+    pub fn kind(&self) -> StreamErrorKind {
+        match self.0.get() {
+            -1 => StreamErrorKind::Aborted,
+            -2 => StreamErrorKind::Reset,
+            -3 => StreamErrorKind::TimedOut,
+            -4 => StreamErrorKind::InvalidData,
+            -5 => StreamErrorKind::PermissionDenied,
+            -6 => StreamErrorKind::Unsupported,
+            _ => StreamErrorKind::Other,
+        }
+    }
+
+    pub fn as_i32(&self) -> i32 {
+        self.0.get()
+    }
+}
+
 impl error::Error for StreamErrorCode {}
 
 impl fmt::Display for StreamErrorCode {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        self.0.fmt(f)
+        match self.kind() {
+            StreamErrorKind::Aborted => f.write_str("stream aborted"),
+            StreamErrorKind::Reset => f.write_str("stream reset"),
+            StreamErrorKind::TimedOut => f.write_str("stream timed out"),
+            StreamErrorKind::InvalidData => f.write_str("invalid stream data"),
+            StreamErrorKind::PermissionDenied => f.write_str("stream permission denied"),
+            StreamErrorKind::Unsupported => f.write_str("unsupported stream operation"),
+            StreamErrorKind::Other => write!(f, "stream error {}", self.0),
+        }
     }
 }
 
+impl From<StreamErrorCode> for Error {
+    fn from(code: StreamErrorCode) -> Self {
+        Error::new(code.kind().into(), code)
+    }
+}
+
+/// Stream traffic counters.
+///
+/// Data is counted when it's accepted for sending or when it's received from
+/// the peer.  Flow credit is consumed by data, so the credit counters exceed
+/// the corresponding byte counters by the amount of unused credit.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct StreamStats {
+    /// Bytes written.
+    pub bytes_sent: u64,
+    /// Non-empty data packets written.
+    pub packets_sent: u64,
+    /// Bytes received from the peer.
+    pub bytes_received: u64,
+    /// Non-empty data packets received from the peer.
+    pub packets_received: u64,
+    /// Flow credit granted to the peer.
+    pub credit_granted: u64,
+    /// Flow credit granted by the peer.
+    pub credit_received: u64,
+    /// Time spent by writers waiting for flow credit.
+    pub blocked: Duration,
+}
+
 /// Asynchronous write.  Must be polled to completion once started.
 #[must_use = "futures do nothing unless you `.await` or poll them"]
 pub struct StreamWriteFuture<'a> {
@@ -813,10 +1489,13 @@ pub struct StreamWriteFuture<'a> {
     _data: PhantomData<&'a [u8]>,
     note: i32,
     writing: bool,
+    boxed: Option<LocalBoxFuture<'a, io::Result<usize>>>,
 }
 
 impl<'a> StreamWriteFuture<'a> {
     pub(crate) fn new(s: &'a Option<Stream>, data: &'a [u8], note: i32) -> Self {
+        restart_write_timeout(s);
+
         let mut this = Self {
             s,
             share: Share::default(),
@@ -824,36 +1503,64 @@ impl<'a> StreamWriteFuture<'a> {
             _data: PhantomData,
             note,
             writing: false,
+            boxed: None,
         };
         this.share.send[1] = Ciovec::new(data);
         this
     }
+
+    /// Write operation implemented by a stream adapter.
+    pub(crate) fn boxed(f: LocalBoxFuture<'a, io::Result<usize>>) -> Self {
+        let mut this = Self::new(&None, &[], 0);
+        this.boxed = Some(f);
+        this
+    }
 }
 
 impl Future for StreamWriteFuture<'_> {
     type Output = io::Result<usize>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(f) = &mut self.boxed {
+            return f.as_mut().poll(cx);
+        }
+
         if let Some(s) = self.s {
             if !self.writing {
                 let mut s = s.borrow_mut();
 
-                if (s.flags & STREAM_PEER_FLOW) == 0 {
+                if (s.flags & STREAM_PEER_FLOW) == 0 || s.write_err != 0 {
                     return Poll::Ready(match NonZeroI32::new(s.write_err) {
                         None => Ok(0),
-                        Some(n) => Err(io::Error::new(io::ErrorKind::Other, StreamErrorCode(n))),
+                        Some(n) => Err(StreamErrorCode(n).into()),
                     });
                 }
 
                 if s.writable == 0 {
+                    if s.poll_write_timeout(cx).is_ready() {
+                        return Poll::Ready(Err(write_timed_out()));
+                    }
                     s.writer = Some(cx.waker().clone());
                     return Poll::Pending;
                 }
-
-                if s.writable < self.share.send[1].buf_len {
-                    self.share.send[1].buf_len = s.writable;
+                s.write_deadline = None;
+
+                let n = std::cmp::min(s.writable, self.share.send[1].buf_len);
+                let n = match s.poll_rate_limit(cx, n) {
+                    Poll::Ready(n) => n,
+                    Poll::Pending => return Poll::Pending,
+                };
+                self.share.send[1].buf_len = n;
+                s.writable -= n;
+                s.count_sent(n);
+
+                if let Some(peer) = &s.local {
+                    let data = self.share.data();
+                    if !data.is_empty() {
+                        peer.send_data(s.id, data, self.note);
+                    }
+                    return Poll::Ready(Ok(data.len()));
                 }
-                s.writable -= self.share.send[1].buf_len;
 
                 let len = self.header.len() + self.share.send[1].buf_len;
                 let note = self.note;
@@ -891,6 +1598,7 @@ impl Drop for StreamWriteFuture<'_> {
 pub struct StreamWriteAllFuture<'a> {
     inner: StreamWriteFuture<'a>,
     pending: &'a [u8],
+    boxed: Option<LocalBoxFuture<'a, io::Result<()>>>,
 }
 
 impl<'a> StreamWriteAllFuture<'a> {
@@ -898,14 +1606,26 @@ impl<'a> StreamWriteAllFuture<'a> {
         Self {
             inner: StreamWriteFuture::new(s, data, 0),
             pending: data,
+            boxed: None,
         }
     }
+
+    /// Write operation implemented by a stream adapter.
+    pub(crate) fn boxed(f: LocalBoxFuture<'a, io::Result<()>>) -> Self {
+        let mut this = Self::new(&None, &[]);
+        this.boxed = Some(f);
+        this
+    }
 }
 
 impl Future for StreamWriteAllFuture<'_> {
     type Output = io::Result<()>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(f) = &mut self.boxed {
+            return f.as_mut().poll(cx);
+        }
+
         loop {
             let n = match unsafe { Pin::new_unchecked(&mut self.inner) }.poll(cx) {
                 Poll::Ready(Ok(n)) => n,
@@ -914,10 +1634,7 @@ impl Future for StreamWriteAllFuture<'_> {
             };
 
             if n == 0 {
-                break Poll::Ready(Err(Error::new(
-                    ErrorKind::WriteZero,
-                    "stream closed".to_string(),
-                )));
+                break Poll::Ready(Err(stream_closed()));
             }
 
             self.pending = &self.pending[n..];
@@ -931,17 +1648,201 @@ impl Future for StreamWriteAllFuture<'_> {
     }
 }
 
+/// Data packet whose content is a range of an owned buffer.
+struct OwnedWrite<B> {
+    share: Share,
+    header: [u8; DATA_HEADER_SIZE],
+    data: B,
+}
+
+/// Asynchronous write of an owned buffer.  The future may be dropped or
+/// spawned: the buffer is kept alive until the packet which is being sent has
+/// been sent.  If the future is dropped, the rest of the buffer isn't written.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct StreamWriteOwnedFuture<B: AsRef<[u8]> + 'static = Vec<u8>> {
+    s: Option<Stream>,
+    p: Option<Box<OwnedWrite<B>>>,
+    offset: usize,
+    writing: bool,
+}
+
+impl<B: AsRef<[u8]> + 'static> StreamWriteOwnedFuture<B> {
+    pub(crate) fn new(s: &Option<Stream>, data: B) -> Self {
+        restart_write_timeout(s);
+
+        Self {
+            s: s.clone(),
+            p: Some(Box::new(OwnedWrite {
+                share: Share::default(),
+                header: [0; DATA_HEADER_SIZE],
+                data,
+            })),
+            offset: 0,
+            writing: false,
+        }
+    }
+}
+
+impl<B: AsRef<[u8]> + 'static> Future for StreamWriteOwnedFuture<B> {
+    type Output = io::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+
+        let rc = match &this.s {
+            Some(rc) => rc,
+            None => return Poll::Ready(Err(stream_closed())),
+        };
+
+        let p = match &mut this.p {
+            Some(p) => p,
+            None => return Poll::Ready(Ok(())),
+        };
+
+        loop {
+            if this.writing {
+                if !p.share.is_sent() {
+                    p.share.waker = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+
+                this.writing = false;
+                this.offset += p.share.send[1].buf_len;
+                packet_sent(rc);
+            }
+
+            let total = p.data.as_ref().len();
+            if this.offset == total {
+                this.p = None;
+                return Poll::Ready(Ok(()));
+            }
+
+            let mut s = rc.borrow_mut();
+
+            if (s.flags & STREAM_PEER_FLOW) == 0 || s.write_err != 0 {
+                return Poll::Ready(Err(match NonZeroI32::new(s.write_err) {
+                    None => stream_closed(),
+                    Some(n) => StreamErrorCode(n).into(),
+                }));
+            }
+
+            if s.writable == 0 {
+                if s.poll_write_timeout(cx).is_ready() {
+                    return Poll::Ready(Err(write_timed_out()));
+                }
+                s.writer = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+            s.write_deadline = None;
+
+            let n = std::cmp::min(s.writable, total - this.offset);
+            let n = match s.poll_rate_limit(cx, n) {
+                Poll::Ready(n) => n,
+                Poll::Pending => return Poll::Pending,
+            };
+            s.writable -= n;
+            s.count_sent(n);
+
+            let data = &p.data.as_ref()[this.offset..this.offset + n];
+
+            if let Some(peer) = &s.local {
+                peer.send_data(s.id, data, 0);
+                this.offset += n;
+                continue;
+            }
+
+            s.unsent += 1;
+
+            packet::data_header_into(&mut p.header, DATA_HEADER_SIZE + n, s.code, s.id, 0);
+            p.share.send[0] = Ciovec::new(&p.header);
+            p.share.send[1] = Ciovec::new(data);
+            p.share.sent = 0;
+            SEND_LIST
+                .borrow_mut()
+                .push_back(SendLink::new(&mut p.share));
+            this.writing = true;
+        }
+    }
+}
+
+impl<B: AsRef<[u8]> + 'static> Drop for StreamWriteOwnedFuture<B> {
+    fn drop(&mut self) {
+        if self.writing {
+            if let (Some(s), Some(p)) = (self.s.take(), self.p.take()) {
+                spawn_local(OwnedWriteFuture { s, p });
+            }
+        }
+    }
+}
+
+/// Waits until an abandoned owned write has been sent.
+struct OwnedWriteFuture<B> {
+    s: Stream,
+    p: Box<OwnedWrite<B>>,
+}
+
+impl<B> Future for OwnedWriteFuture<B> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if self.p.share.is_sent() {
+            packet_sent(&self.s);
+            return Poll::Ready(());
+        }
+
+        self.p.share.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
 /// Asynchronous closure.  Must be polled to completion once started.
 #[must_use = "futures do nothing unless you `.await` or poll them"]
 pub struct StreamCloseFuture {
     s: Option<Stream>,
     how: StreamFlags,
     wait: StreamFlags,
+    boxed: Option<LocalBoxFuture<'static, ()>>,
 }
 
 impl StreamCloseFuture {
     pub(crate) fn new(s: Option<Stream>, how: StreamFlags, wait: StreamFlags) -> Self {
-        Self { s, how, wait }
+        Self {
+            s,
+            how,
+            wait,
+            boxed: None,
+        }
+    }
+
+    /// Closing operation implemented by a stream adapter.
+    pub(crate) fn boxed(f: LocalBoxFuture<'static, ()>) -> Self {
+        Self {
+            s: None,
+            how: 0,
+            wait: 0,
+            boxed: Some(f),
+        }
+    }
+
+    /// The note is attached to the data packet which closes the output
+    /// direction.  A negative note is also sent as a flow increment before
+    /// closing the input direction.
+    pub(crate) fn with_note(
+        s: Option<Stream>,
+        how: StreamFlags,
+        wait: StreamFlags,
+        note: i32,
+    ) -> Self {
+        if let Some(s) = &s {
+            let mut s = s.borrow_mut();
+            if (s.flags & how & STREAM_SELF_DATA) != 0 {
+                s.close_note = note;
+            }
+            if (s.flags & how & STREAM_SELF_FLOW) != 0 && note < 0 {
+                s.close_flow_err = note;
+            }
+        }
+        Self::new(s, how, wait)
     }
 }
 
@@ -949,38 +1850,208 @@ impl Future for StreamCloseFuture {
     type Output = ();
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
-        let how = self.how;
-        self.how = 0;
+        if let Some(f) = &mut self.boxed {
+            return f.as_mut().poll(cx);
+        }
+
+        let (how, wait) = (self.how, self.wait);
+        poll_close_stream(&mut self.s, how, wait, cx) // Clears s for drop implementation.
+    }
+}
+
+impl Drop for StreamCloseFuture {
+    fn drop(&mut self) {
+        let this = unsafe { Pin::new_unchecked(self) }; // See pin module doc.
+
+        if this.s.is_some() {
+            die("close future dropped before completion");
+        }
+    }
+}
 
-        if let Some(ref s) = self.s {
+/// Waits until the peer has closed the stream.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct StreamClosedFuture<'a> {
+    s: &'a Option<Stream>,
+}
+
+impl<'a> StreamClosedFuture<'a> {
+    pub(crate) fn new(s: &'a Option<Stream>) -> Self {
+        Self { s }
+    }
+}
+
+impl Future for StreamClosedFuture<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if let Some(s) = self.s {
             let mut s = s.borrow_mut();
 
-            if how != 0 {
-                s.clear_flags(how);
-                s.send_close_packets(how);
+            if (s.flags & (STREAM_PEER_DATA | STREAM_PEER_FLOW)) != 0 {
+                s.add_closer(cx.waker());
+                return Poll::Pending;
             }
+        }
 
-            if (s.flags & self.wait) != 0 {
-                s.closers.push(cx.waker().clone());
-                return Poll::Pending;
+        Poll::Ready(())
+    }
+}
+
+/// Data packet which owns its content.
+struct OwnedPacket {
+    s: Stream,
+    share: Share,
+    header: [u8; DATA_HEADER_SIZE],
+    data: Vec<u8>,
+}
+
+/// Waits until an owned packet has been sent.
+struct OwnedPacketFuture(Box<OwnedPacket>);
+
+impl Future for OwnedPacketFuture {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let p = &mut self.0;
+
+        if p.share.is_sent() {
+            packet_sent(&p.s);
+            return Poll::Ready(());
+        }
+
+        p.share.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// Account for a background data packet which has been sent.
+fn packet_sent(s: &Stream) {
+    let mut s = s.borrow_mut();
+    s.unsent -= 1;
+    if s.unsent == 0 {
+        if let Some(w) = s.flusher.take() {
+            w.wake();
+        }
+    }
+}
+
+/// Data packet content and note.
+pub type Chunk = (Vec<u8>, i32);
+
+/// Write part of a byte slice without borrowing it beyond the call.  As much
+/// data is accepted as the current flow credit allows; it is copied and sent
+/// in the background.  The note is attached only if all data is accepted.
+pub fn poll_write_stream(
+    s: &Option<Stream>,
+    cx: &mut Context,
+    data: &[u8],
+    note: i32,
+) -> Poll<io::Result<usize>> {
+    if let Some(rc) = s {
+        let mut s = rc.borrow_mut();
+
+        if (s.flags & STREAM_PEER_FLOW) == 0 || s.write_err != 0 {
+            return Poll::Ready(match NonZeroI32::new(s.write_err) {
+                None => Ok(0),
+                Some(n) => Err(StreamErrorCode(n).into()),
+            });
+        }
+
+        if data.is_empty() {
+            return Poll::Ready(Ok(0)); // Empty data packet would close the stream.
+        }
+
+        if s.writable == 0 {
+            if s.poll_write_timeout(cx).is_ready() {
+                return Poll::Ready(Err(write_timed_out()));
             }
+            s.writer = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        s.write_deadline = None;
+
+        let n = std::cmp::min(s.writable, data.len());
+        let n = match s.poll_rate_limit(cx, n) {
+            Poll::Ready(n) => n,
+            Poll::Pending => return Poll::Pending,
+        };
+        s.writable -= n;
+        s.count_sent(n);
+        let note = if n == data.len() { note } else { 0 };
 
-            s.detach_closed();
+        if let Some(peer) = &s.local {
+            peer.send_data(s.id, &data[..n], note);
+            return Poll::Ready(Ok(n));
         }
 
-        self.s = None; // For drop implementation.
-        Poll::Ready(())
+        s.unsent += 1;
+
+        let mut p = Box::new(OwnedPacket {
+            s: rc.clone(),
+            share: Share::default(),
+            header: [0; DATA_HEADER_SIZE],
+            data: data[..n].to_vec(),
+        });
+
+        packet::data_header_into(&mut p.header, DATA_HEADER_SIZE + n, s.code, s.id, note);
+        p.share.send[0] = Ciovec::new(&p.header);
+        p.share.send[1] = Ciovec::new(&p.data);
+
+        // Link immediately to retain ordering with subsequent writes.
+        SEND_LIST
+            .borrow_mut()
+            .push_back(SendLink::new(&mut p.share));
+
+        spawn_local(OwnedPacketFuture(p));
+        Poll::Ready(Ok(n))
+    } else {
+        Poll::Ready(Ok(0))
     }
 }
 
-impl Drop for StreamCloseFuture {
-    fn drop(&mut self) {
-        let this = unsafe { Pin::new_unchecked(self) }; // See pin module doc.
+/// Wait until data accepted by `poll_write_stream` has been sent.
+pub fn poll_flush_stream(s: &Option<Stream>, cx: &mut Context) -> Poll<()> {
+    if let Some(rc) = s {
+        let mut s = rc.borrow_mut();
 
-        if this.s.is_some() {
-            die("close future dropped before completion");
+        if s.unsent > 0 {
+            s.flusher = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+    }
+
+    Poll::Ready(())
+}
+
+/// Close a stream without a future.  Closing packets are sent during the
+/// first call.  The handle is cleared when the peer has closed the `wait`
+/// directions.
+pub fn poll_close_stream(
+    s: &mut Option<Stream>,
+    how: StreamFlags,
+    wait: StreamFlags,
+    cx: &mut Context,
+) -> Poll<()> {
+    if let Some(rc) = s {
+        let mut state = rc.borrow_mut();
+
+        let how = how & state.flags;
+        if how != 0 {
+            state.clear_flags(how);
+            state.send_close_packets(how);
+        }
+
+        if (state.flags & wait) != 0 {
+            state.add_closer(cx.waker());
+            return Poll::Pending;
         }
+
+        state.detach_closed();
     }
+
+    *s = None;
+    Poll::Ready(())
 }
 
 #[must_use = "futures do nothing unless you `.await` or poll them"]
@@ -1049,22 +2120,192 @@ pub fn register_service(name: &'static str) -> Result<Code, RegistrationError> {
 }
 
 pub fn init_stream(code: Code, id: StreamId, flags: StreamFlags) -> Option<Stream> {
+    match try_init_stream(code, id, flags) {
+        Ok(s) => s,
+        Err(StreamInitError::NegativeId) => panic!("negative stream id"),
+        Err(StreamInitError::AlreadyExists) => panic!("stream already exists"),
+    }
+}
+
+pub fn try_init_stream(
+    code: Code,
+    id: StreamId,
+    flags: StreamFlags,
+) -> Result<Option<Stream>, StreamInitError> {
     if id < 0 {
-        panic!("negative stream id");
+        return Err(StreamInitError::NegativeId);
+    }
+
+    let mut streams = STREAMS.borrow_mut();
+    if streams.contains_key(&(code, id)) {
+        return Err(StreamInitError::AlreadyExists);
     }
 
     let s = Rc::new(RefCell::new(StreamState::new(code, id, flags)));
+    streams.insert((code, id), s.clone());
+    Ok(Some(s))
+}
+
+/// Create an in-process stream which is open in both directions.
+pub(crate) fn init_local_stream(peer: Rc<dyn LocalPeer>, id: StreamId) -> Stream {
+    let flags = STREAM_SELF_FLOW | STREAM_SELF_DATA | STREAM_PEER_DATA | STREAM_PEER_FLOW;
+    let mut state = StreamState::new(0, id, flags);
+    state.local = Some(peer);
+    Rc::new(RefCell::new(state))
+}
+
+/// Deliver a data packet to a local stream.  Empty data closes the peer's
+/// output direction.  Returns false if the data exceeds the flow credit
+/// granted to the peer; it's discarded in that case.
+pub(crate) fn receive_local_data(s: &Stream, data: &[u8], note: i32) -> bool {
+    let mut s = s.borrow_mut();
+
+    if (s.flags & STREAM_PEER_DATA) == 0 {
+        return true;
+    }
+
+    if data.is_empty() {
+        s.recv_err = note;
+        peer_closed_stream(&mut s, STREAM_PEER_DATA);
+        s.detach_closed();
+        return true;
+    }
+
+    if data.len() as u64 > s.local_credit {
+        return false;
+    }
+    s.local_credit -= data.len() as u64;
+    s.count_received(data.len());
+
+    if (s.flags & STREAM_SELF_FLOW) == 0 {
+        return true; // Input direction has been closed.
+    }
+
+    s.recv_queue.push_back((data.to_vec(), note));
+    if let Some(w) = s.recv.take_waker() {
+        w.wake();
+    }
+    true
+}
+
+/// Deliver a flow packet to a local stream.
+pub(crate) fn receive_local_flow(s: &Stream, increment: i32) {
+    let mut s = s.borrow_mut();
+
+    if (s.flags & STREAM_PEER_FLOW) != 0 {
+        receive_flow(&mut s, increment);
+        s.detach_closed();
+    }
+}
+
+pub fn peer_closed(s: &Option<Stream>, how: StreamFlags) -> bool {
+    match s {
+        Some(s) => (s.borrow().flags & how) == 0,
+        None => true,
+    }
+}
+
+pub fn stream_stats(s: &Option<Stream>) -> StreamStats {
+    match s {
+        Some(s) => s.borrow().stats,
+        None => StreamStats::default(),
+    }
+}
+
+pub fn set_rate_limiter(s: &Option<Stream>, limiter: Option<RateLimiter>) {
+    if let Some(s) = s {
+        let old = replace(&mut s.borrow_mut().rate_limiter, limiter);
+        if let Some(old) = old {
+            old.wake();
+        }
+    }
+}
+
+pub fn rate_limiter(s: &Option<Stream>) -> Option<RateLimiter> {
+    s.as_ref().and_then(|s| s.borrow().rate_limiter.clone())
+}
+
+pub fn set_service_rate_limiter(code: Code, limiter: Option<RateLimiter>) {
+    let old = replace(
+        &mut SERVICE_STATES.borrow_mut()[code as usize].rate_limiter,
+        limiter,
+    );
+    if let Some(old) = old {
+        old.wake();
+    }
+}
+
+pub fn service_rate_limiter(code: Code) -> Option<RateLimiter> {
+    SERVICE_STATES.borrow()[code as usize].rate_limiter.clone()
+}
+
+pub fn service_stats(code: Code) -> StreamStats {
+    SERVICE_STATES.borrow()[code as usize].stats
+}
 
-    if STREAMS.borrow_mut().insert((code, id), s.clone()).is_some() {
-        panic!("stream already exists");
+pub fn writable_credit(s: &Option<Stream>) -> usize {
+    match s {
+        Some(s) => {
+            let s = s.borrow();
+            if (s.flags & STREAM_PEER_FLOW) != 0 {
+                s.writable
+            } else {
+                0
+            }
+        }
+        None => 0,
     }
+}
 
-    Some(s)
+/// The note of the peer's closing data packet, or the peer's negative flow
+/// increment.
+pub fn stream_error(s: &Option<Stream>) -> Option<StreamErrorCode> {
+    let s = s.as_ref()?.borrow();
+    NonZeroI32::new(s.recv_err)
+        .or_else(|| NonZeroI32::new(s.write_err))
+        .map(StreamErrorCode)
+}
+
+/// Set the maximum duration to wait for flow credit.
+pub fn set_write_timeout(s: &Option<Stream>, timeout: Option<Duration>) {
+    if let Some(s) = s {
+        let mut s = s.borrow_mut();
+        s.write_timeout = timeout;
+        s.write_deadline = None;
+    }
+}
+
+pub fn write_timeout(s: &Option<Stream>) -> Option<Duration> {
+    s.as_ref().and_then(|s| s.borrow().write_timeout)
+}
+
+/// Discard the deadline of an abandoned write when a new write starts.
+fn restart_write_timeout(s: &Option<Stream>) {
+    if let Some(s) = s {
+        s.borrow_mut().write_deadline = None;
+    }
+}
+
+fn write_timed_out() -> Error {
+    Error::new(ErrorKind::TimedOut, "stream write timed out")
+}
+
+fn stream_closed() -> Error {
+    Error::new(ErrorKind::WriteZero, "stream closed")
 }
 
 pub fn drop_stream(s: Option<Stream>, how: StreamFlags) {
     if let Some(s) = s {
         let mut s = s.borrow_mut();
+
+        // Discard unhandled data so that it doesn't block reception.
+        if (how & STREAM_SELF_FLOW) != 0 {
+            if let Recv::Some(offset) = take(&mut s.recv) {
+                RECV_BUF.borrow_mut().consume(offset);
+            }
+            s.recv_queue.clear();
+        }
+
         let how = how & s.flags;
         s.clear_flags(how);
         s.send_close_packets(how);
@@ -1092,6 +2333,25 @@ fn peer_closed_stream(s: &mut StreamState, how: StreamFlags) {
     }
 }
 
+fn receive_flow(s: &mut StreamState, increment: i32) {
+    s.unblocked();
+
+    if increment > 0 {
+        s.writable += increment as usize;
+        s.count(|x| x.credit_received += increment as u64);
+        if let Some(w) = s.writer.take() {
+            w.wake();
+        }
+    } else if increment == 0 {
+        peer_closed_stream(s, STREAM_PEER_FLOW);
+    } else {
+        s.write_err = increment;
+        if let Some(w) = s.writer.take() {
+            w.wake();
+        }
+    }
+}
+
 pub fn io() {
     let flags = perform_io();
 
@@ -1100,6 +2360,7 @@ pub fn io() {
     }
 
     process_received();
+    wake_expired_timers();
 }
 
 fn perform_io() -> u64 {
@@ -1198,7 +2459,11 @@ fn perform_io() -> u64 {
         }
     }
 
-    let timeout = if wait { None } else { Some(Duration::ZERO) };
+    let timeout = if wait {
+        next_timer_timeout()
+    } else {
+        Some(Duration::ZERO)
+    };
 
     let (recv_len, send_len, flags) = unsafe {
         gate::io(
@@ -1385,17 +2650,7 @@ fn process_received() {
                             .expect("flow packet received for unknown service or stream")
                             .borrow_mut();
 
-                        if flow.increment > 0 {
-                            s.writable += flow.increment as usize;
-                            if let Some(w) = s.writer.take() {
-                                w.wake();
-                            }
-                        } else if flow.increment == 0 {
-                            peer_closed_stream(&mut s, STREAM_PEER_FLOW);
-                        } else {
-                            s.write_err = flow.increment;
-                        }
-
+                        receive_flow(&mut s, flow.increment);
                         s.flags == 0
                     } {
                         streams.remove(&(code, flow.id));
@@ -1415,19 +2670,26 @@ fn process_received() {
                         .borrow_mut();
 
                     if data_size > 0 {
+                        s.count_received(data_size);
+
                         match take(&mut s.recv) {
                             Recv::None => {
-                                s.recv = Recv::Some(recv_buf.head.off);
+                                // Nobody is receiving at the moment; don't
+                                // hold up the other streams.
+                                let data = p[DATA_HEADER_SIZE..].to_vec();
+                                s.recv_queue.push_back((data, packet::data_note(p)));
                             }
                             Recv::Wake(w) => {
                                 s.recv = Recv::Some(recv_buf.head.off);
                                 w.wake();
+                                future_consumer = true;
                             }
-                            Recv::Some(_) => {
+                            Recv::Some(offset) => {
                                 // Don't overwrite unhandled offset.
+                                s.recv = Recv::Some(offset);
+                                future_consumer = true;
                             }
                         }
-                        future_consumer = true;
                     } else {
                         s.recv_err = packet::data_note(p);
                         peer_closed_stream(&mut s, STREAM_PEER_DATA);
@@ -1452,3 +2714,192 @@ fn die(s: &'static str) -> ! {
     eprintln!("gain: {}", s);
     exit(1)
 }
+
+#[derive(Default)]
+struct Timers {
+    wakers: BTreeMap<(u64, u64), Waker>, // Keyed by deadline and sequence number.
+    seq: u64,
+}
+
+/// Point in time which wakes up a task.  The I/O wait is limited by the
+/// earliest registered deadline.
+pub(crate) struct Deadline {
+    key: (u64, u64),
+}
+
+impl Deadline {
+    pub(crate) fn after(duration: Duration) -> Self {
+        let nanos = std::cmp::min(duration.as_nanos(), u64::MAX as u128) as u64;
+        let at = gate::clock_monotonic().saturating_add(nanos);
+
+        let mut timers = TIMERS.borrow_mut();
+        timers.seq += 1;
+        Self {
+            key: (at, timers.seq),
+        }
+    }
+
+    /// Ready when the deadline has passed.  Otherwise the task will be woken
+    /// up when it does.
+    pub(crate) fn poll(&mut self, cx: &mut Context) -> Poll<()> {
+        let mut timers = TIMERS.borrow_mut();
+
+        if gate::clock_monotonic() >= self.key.0 {
+            timers.wakers.remove(&self.key);
+            return Poll::Ready(());
+        }
+
+        timers.wakers.insert(self.key, cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for Deadline {
+    fn drop(&mut self) {
+        TIMERS.borrow_mut().wakers.remove(&self.key);
+    }
+}
+
+fn next_timer_timeout() -> Option<Duration> {
+    let timers = TIMERS.borrow();
+    let &(at, _) = timers.wakers.keys().next()?;
+    Some(Duration::from_nanos(
+        at.saturating_sub(gate::clock_monotonic()),
+    ))
+}
+
+fn wake_expired_timers() {
+    let expired = {
+        let mut timers = TIMERS.borrow_mut();
+        if timers.wakers.is_empty() {
+            return;
+        }
+
+        let now = gate::clock_monotonic();
+        let pending = timers.wakers.split_off(&(now.saturating_add(1), 0));
+        std::mem::replace(&mut timers.wakers, pending)
+    };
+
+    for w in expired.into_values() {
+        w.wake();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::future::join;
+    use futures_util::task::noop_waker;
+
+    use super::*;
+    use crate::stream::buf::ReadExt;
+    use crate::stream::{duplex, CloseWrite, Write};
+    use crate::task::test_block_on;
+
+    fn closers(s: &Option<Stream>) -> usize {
+        s.as_ref().unwrap().borrow().closers.len()
+    }
+
+    #[test]
+    fn closed_registers_once() {
+        test_block_on(async {
+            let (a, _b) = duplex(16);
+            let waker = noop_waker();
+            let mut cx = Context::from_waker(&waker);
+
+            let mut f = a.closed();
+            for _ in 0..3 {
+                assert!(Pin::new(&mut f).poll(&mut cx).is_pending());
+            }
+            let mut f = a.closed();
+            assert!(Pin::new(&mut f).poll(&mut cx).is_pending());
+
+            assert_eq!(closers(&a.w.s), 1);
+        })
+    }
+
+    #[test]
+    fn local_stream_stats() {
+        let data: Vec<u8> = (0..100).collect();
+
+        test_block_on(async {
+            let (mut a, mut b) = duplex(16);
+
+            let send = async {
+                a.write_all(&data).await.unwrap();
+                a.close_write(0).await;
+            };
+
+            let receive = async {
+                // Let the writer wait for credit for a while.
+                std::thread::sleep(Duration::from_millis(2));
+
+                let mut received = Vec::new();
+                b.read_to_end(&mut received, usize::MAX).await.unwrap();
+                received
+            };
+
+            let (_, received) = join(send, receive).await;
+            assert_eq!(received, data);
+
+            let sent = a.stats();
+            assert_eq!(sent.bytes_sent, 100);
+            assert!(sent.packets_sent >= 100 / 16);
+            assert!(sent.credit_received >= 100);
+            assert!(sent.blocked >= Duration::from_millis(2));
+            assert_eq!(sent.bytes_received, 0);
+
+            let recv = b.stats();
+            assert_eq!(recv.bytes_received, 100);
+            assert_eq!(recv.packets_received, sent.packets_sent);
+            assert!(recv.credit_granted >= sent.credit_received);
+            assert_eq!(recv.bytes_sent, 0);
+            assert_eq!(recv.blocked, Duration::ZERO);
+        })
+    }
+
+    #[test]
+    fn service_stats_sum_streams() {
+        let code = {
+            let mut states = SERVICE_STATES.borrow_mut();
+            states.push(ServiceState::new_unavail());
+            (states.len() - 1) as Code
+        };
+
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        let mut s1 = StreamState::new(code, 0, 0);
+        let mut s2 = StreamState::new(code, 1, 0);
+
+        assert!(s1.poll_write_timeout(&mut cx).is_pending());
+        std::thread::sleep(Duration::from_millis(1));
+        receive_flow(&mut s1, 10);
+        s1.count_sent(10);
+        s1.count_sent(0);
+        s2.count_granted(20);
+        s2.count_granted(-1);
+        s2.count_received(7);
+        s2.count_received(0);
+
+        assert_eq!(s1.stats.bytes_sent, 10);
+        assert_eq!(s1.stats.packets_sent, 1);
+        assert_eq!(s1.stats.credit_received, 10);
+        assert!(s1.stats.blocked >= Duration::from_millis(1));
+        assert_eq!(s2.stats.bytes_received, 7);
+        assert_eq!(s2.stats.packets_received, 1);
+        assert_eq!(s2.stats.credit_granted, 20);
+
+        assert_eq!(
+            service_stats(code),
+            StreamStats {
+                bytes_sent: 10,
+                packets_sent: 1,
+                bytes_received: 7,
+                packets_received: 1,
+                credit_granted: 20,
+                credit_received: 10,
+                blocked: s1.stats.blocked,
+            }
+        );
+    }
+}
@@ -54,6 +54,7 @@ impl Default for Iovec {
     }
 }
 
+#[cfg(target_family = "wasm")]
 pub unsafe fn io(
     recv: &[Iovec],
     send: &[Ciovec],
@@ -87,6 +88,57 @@ pub unsafe fn io(
     (received, sent, flags)
 }
 
+/// Monotonic time in nanoseconds.
+#[cfg(target_family = "wasm")]
+pub fn clock_monotonic() -> u64 {
+    let mut time: u64 = 0;
+    if unsafe { clock_time_get(CLOCKID_MONOTONIC, 1, &mut time) } != 0 {
+        panic!("monotonic clock is unavailable");
+    }
+    time
+}
+
+/// Stand-in for running tests on the host.  There is no runtime to
+/// communicate with, so only local streams and timers work: nothing can be
+/// sent or received, and waiting only sleeps until the next timer.
+#[cfg(not(target_family = "wasm"))]
+pub unsafe fn io(
+    _recv: &[Iovec],
+    send: &[Ciovec],
+    timeout: Option<Duration>,
+) -> (usize, usize, u64) {
+    if send.iter().any(|v| v.buf_len > 0) {
+        panic!("gate I/O is not available on this target");
+    }
+
+    match timeout {
+        Some(t) => std::thread::sleep(t),
+        None => panic!("all tasks are blocked without I/O"),
+    }
+
+    (0, 0, 0)
+}
+
+/// Monotonic time in nanoseconds since the first call.
+#[cfg(not(target_family = "wasm"))]
+pub fn clock_monotonic() -> u64 {
+    use std::sync::OnceLock;
+    use std::time::Instant;
+
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    EPOCH.get_or_init(Instant::now).elapsed().as_nanos() as u64
+}
+
+#[cfg(target_family = "wasm")]
+const CLOCKID_MONOTONIC: u32 = 1;
+
+#[cfg(target_family = "wasm")]
+#[link(wasm_import_module = "wasi_snapshot_preview1")]
+extern "C" {
+    fn clock_time_get(id: u32, precision: u64, time: *mut u64) -> u16;
+}
+
+#[cfg(target_family = "wasm")]
 #[link(wasm_import_module = "gate")]
 extern "C" {
     fn io_65536(
@@ -4,6 +4,7 @@
 
 //! Identity information for this execution context.
 
+use crate::service::typed::{Decode, Encode, ServiceCall};
 use crate::service::Service;
 
 lazy_static! {
@@ -25,14 +26,21 @@ pub async fn instance_id() -> Option<String> {
     get_id(CALL_INSTANCE_ID).await
 }
 
-async fn get_id(call: u8) -> Option<String> {
-    SERVICE
-        .call(&[call], |reply: &[u8]| {
-            if !reply.is_empty() {
-                Some(String::from_utf8(reply.to_vec()).unwrap())
-            } else {
-                None
-            }
-        })
-        .await
+#[derive(Encode, ServiceCall)]
+#[gain(reply = IdReply)]
+struct IdCall {
+    op: u8,
+}
+
+#[derive(Decode)]
+struct IdReply {
+    #[gain(rest)]
+    id: String,
+}
+
+async fn get_id(op: u8) -> Option<String> {
+    match SERVICE.call_typed(&IdCall { op }).await {
+        Ok(reply) if !reply.id.is_empty() => Some(reply.id),
+        _ => None,
+    }
 }
@@ -24,11 +24,25 @@
 //! async fn concurrent_work() {
 //!     do_stuff().await;
 //! }
+//! # async fn do_something() {}
+//! # async fn do_stuff() {}
 //! ```
 //!
 //! Concurrency is achieved by spawning more tasks.  The program exits when the
 //! top-level task returns.
 //!
+//! The [`main`](macro@main) attribute macro does the same for an async main
+//! function, and [`export`](macro@export) defines entry functions for
+//! reentrant programs:
+//!
+//! ```ignore
+//! #[gain::main]
+//! async fn main() {
+//!     spawn(concurrent_work());
+//!     do_something().await;
+//! }
+//! ```
+//!
 //! ## Service APIs
 //!
 //! The [`catalog`](catalog), [`identity`](identity), [`origin`](origin),
@@ -40,11 +54,15 @@
 //! ## Service implementation
 //!
 //! Additional service bindings can be implemented using the
-//! [`service`](service) module.
+//! [`service`](service) module.  Call content and reply layouts can be derived
+//! using the [`service::typed`](service::typed) module.
 
 #[macro_use]
 extern crate lazy_static;
 
+// Derive macros refer to ::gain paths.
+extern crate self as gain;
+
 pub mod catalog;
 mod core;
 mod gate;
@@ -59,3 +77,5 @@ pub mod service;
 pub mod stream;
 pub mod task;
 mod threadunsafe;
+
+pub use gain_macros::{export, main};
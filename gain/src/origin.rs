@@ -6,16 +6,27 @@
 //!
 //! This can be thought of as standard I/O streams.
 
-use std::convert::TryInto;
 use std::fmt;
 
-use crate::service::Service;
+use crate::service::typed::{Decode, Encode, ServiceCall};
+use crate::service::{Service, ServiceError};
 use crate::stream::RecvWriteStream;
 
 lazy_static! {
     static ref SERVICE: Service = Service::register("origin");
 }
 
+#[derive(Encode, ServiceCall)]
+#[gain(reply = AcceptReply)]
+struct AcceptCall;
+
+#[derive(Decode)]
+struct AcceptReply {
+    id: i32,
+    error: i16,
+    _pad: [u8; 2],
+}
+
 /// Accept a new incoming connection.
 ///
 /// The call is blocked while no connection is available, or the
@@ -25,20 +36,16 @@ lazy_static! {
 /// Typically there is a correspondence between a connection and a program
 /// invocation or resumption.
 pub async fn accept() -> Result<RecvWriteStream, AcceptError> {
-    SERVICE
-        .call(&[], |reply: &[u8]| {
-            if reply.len() < 8 {
-                return Err(AcceptError::new(0));
-            }
+    let reply = SERVICE
+        .call_typed(&AcceptCall)
+        .await
+        .map_err(ServiceError::from)?;
 
-            let error = i16::from_le_bytes(reply[4..6].try_into().unwrap());
-            if error != 0 {
-                return Err(AcceptError::new(error));
-            }
+    if reply.error != 0 {
+        return Err(AcceptError::new(reply.error));
+    }
 
-            Ok(SERVICE.stream(i32::from_le_bytes(reply[..4].try_into().unwrap())))
-        })
-        .await
+    Ok(SERVICE.try_stream(reply.id).map_err(ServiceError::from)?)
 }
 
 /// Reason for connection acceptance failure.
@@ -47,20 +54,43 @@ pub async fn accept() -> Result<RecvWriteStream, AcceptError> {
 #[derive(Debug)]
 pub struct AcceptError {
     code: i16,
+    service: Option<ServiceError>,
 }
 
 impl AcceptError {
     fn new(code: i16) -> Self {
-        Self { code }
+        Self {
+            code,
+            service: None,
+        }
     }
 
+    /// The error code reported by the service.  Zero if the error occurred
+    /// on the program side.
     pub fn as_i16(&self) -> i16 {
         self.code
     }
+
+    /// The program-side error, if the service didn't report the error.
+    pub fn service_error(&self) -> Option<&ServiceError> {
+        self.service.as_ref()
+    }
+}
+
+impl From<ServiceError> for AcceptError {
+    fn from(e: ServiceError) -> Self {
+        Self {
+            code: 0,
+            service: Some(e),
+        }
+    }
 }
 
 impl fmt::Display for AcceptError {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        self.code.fmt(f)
+        match &self.service {
+            Some(e) => e.fmt(f),
+            None => self.code.fmt(f),
+        }
     }
 }
@@ -5,21 +5,23 @@
 //! Communicate with other program instances.
 
 use std::collections::HashMap;
-use std::convert::TryInto;
 use std::fmt;
-use std::str;
 
 use futures_channel::oneshot::{channel, Sender};
 
-use crate::service::Service;
+use crate::service::typed::{self, Decode, Encode, ServiceCall};
+use crate::service::{Service, ServiceError, StreamInitError};
 use crate::stream::RecvWriteStream;
 use crate::task::spawn_local;
 use crate::threadunsafe::ThreadUnsafeRefCell;
 
+#[cfg(feature = "secure")]
+pub mod secure;
+
 pub type Listener = Box<dyn Fn(&str, &str)>;
 
 type ConnKey = (Vec<u8>, Vec<u8>);
-type ConnSender = Sender<(RecvWriteStream, String)>;
+type ConnSender = Sender<Result<(RecvWriteStream, String), StreamInitError>>;
 
 lazy_static! {
     static ref SERVICE: Service = Service::register("peer");
@@ -46,41 +48,50 @@ pub async fn register_group(group_name: &str, listener: Listener) {
     }
 }
 
-fn parse_name(b: &'_ [u8]) -> (&'_ [u8], &'_ [u8]) {
-    let size = b[0] as usize;
-    let b = &b[1..];
-    let name = &b[..size];
-    let b = &b[size..];
-    (name, b)
+#[derive(Decode)]
+struct Info {
+    id: i32,
+    _reserved: [u8; 4],
+    group_name: String,
+    peer_name: String,
+    type_name: String,
 }
 
 async fn handle_info_packets() {
     SERVICE
         .recv_info(|b: &[u8]| {
-            let id = i32::from_le_bytes(b[..4].try_into().unwrap());
-            let b = &b[8..];
-            let (group_name, b) = parse_name(b);
-            let (peer_name, b) = parse_name(b);
-            let (type_name, _) = parse_name(b);
+            let info: Info = typed::decode(b).expect("malformed peer info packet");
 
-            if id < 0 {
+            if info.id < 0 {
                 let groups = GROUPS.borrow();
-                groups[group_name](
-                    str::from_utf8(peer_name).unwrap(),
-                    str::from_utf8(type_name).unwrap(),
-                );
+                groups[info.group_name.as_bytes()](&info.peer_name, &info.type_name);
             } else {
-                let stream = SERVICE.stream(id);
-                let mut conns = CONNS.borrow_mut();
-                let _ = conns
-                    .remove(&(group_name.into(), peer_name.into()))
-                    .unwrap()
-                    .send((stream, String::from_utf8(type_name.into()).unwrap()));
+                let sender = CONNS
+                    .borrow_mut()
+                    .remove(&(info.group_name.into(), info.peer_name.into()))
+                    .unwrap();
+
+                let result = SERVICE.try_stream(info.id);
+                let _ = sender.send(result.map(|stream| (stream, info.type_name)));
             }
         })
         .await;
 }
 
+#[derive(Encode, ServiceCall)]
+#[gain(reply = ConnectReply)]
+struct ConnectCall<'a> {
+    _reserved: [u8; 8],
+    group_name: &'a str,
+    peer_name: &'a str,
+    type_name: &'a str,
+}
+
+#[derive(Decode)]
+struct ConnectReply {
+    error: i16,
+}
+
 /// Connect to a peer within a group.  Specify the incoming content type.  The
 /// outgoing content type is returned along with the stream.
 pub async fn connect(
@@ -88,17 +99,14 @@ pub async fn connect(
     peer_name: &str,
     type_name: &str,
 ) -> Result<(RecvWriteStream, String), ConnectError> {
-    let group_name = group_name.as_bytes();
     if group_name.len() > 255 {
         panic!("group name is too long");
     }
 
-    let peer_name = peer_name.as_bytes();
     if peer_name.len() > 255 {
         panic!("peer name is too long");
     }
 
-    let type_name = type_name.as_bytes();
     if type_name.len() > 255 {
         panic!("type name is too long");
     }
@@ -114,32 +122,28 @@ pub async fn connect(
         conns.insert(key, sender);
     }
 
-    let mut buf =
-        Vec::with_capacity(8 + 1 + group_name.len() + 1 + peer_name.len() + 1 + type_name.len());
-    buf.resize(8, 0); // Reserved.
-    buf.push(group_name.len() as u8);
-    buf.extend_from_slice(group_name);
-    buf.push(peer_name.len() as u8);
-    buf.extend_from_slice(peer_name);
-    buf.push(type_name.len() as u8);
-    buf.extend_from_slice(type_name);
+    let call = ConnectCall {
+        _reserved: [0; 8],
+        group_name,
+        peer_name,
+        type_name,
+    };
 
-    SERVICE
-        .call(buf.as_slice(), |buf: &[u8]| -> Result<(), ConnectError> {
-            if buf.len() < 2 {
-                return Err(ConnectError::new(0));
-            }
+    let reply = SERVICE
+        .call_typed(&call)
+        .await
+        .map_err(ServiceError::from)?;
 
-            let error = i16::from_le_bytes(buf[..2].try_into().unwrap());
-            match error {
-                0 => Ok(()),
-                1 => panic!("ABI violation"),
-                _ => Err(ConnectError::new(error)),
-            }
-        })
-        .await?;
+    match reply.error {
+        0 => {}
+        1 => panic!("ABI violation"),
+        error => return Err(ConnectError::new(error)),
+    }
 
-    Ok(receiver.await.unwrap())
+    match receiver.await {
+        Ok(result) => Ok(result.map_err(ServiceError::from)?),
+        Err(_) => Err(ConnectError::canceled()),
+    }
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -150,23 +154,51 @@ pub enum ConnectErrorKind {
     Singularity,
     AlreadyConnecting,
     AlreadyConnected,
+    Canceled,
+    Service,
 }
 
 #[derive(Debug)]
 pub struct ConnectError {
     code: i16,
+    repr: Repr,
+}
+
+#[derive(Debug)]
+enum Repr {
+    Code,
+    Canceled,
+    Service(ServiceError),
 }
 
 impl ConnectError {
     fn new(code: i16) -> Self {
-        Self { code }
+        Self {
+            code,
+            repr: Repr::Code,
+        }
     }
 
     fn already_connecting() -> Self {
         Self::new(5)
     }
 
+    /// The peer info handler went away before the connection was
+    /// established.
+    fn canceled() -> Self {
+        Self {
+            code: 0,
+            repr: Repr::Canceled,
+        }
+    }
+
     pub fn kind(&self) -> ConnectErrorKind {
+        match self.repr {
+            Repr::Code => {}
+            Repr::Canceled => return ConnectErrorKind::Canceled,
+            Repr::Service(_) => return ConnectErrorKind::Service,
+        }
+
         match self.code {
             2 => ConnectErrorKind::GroupNotFound,
             3 => ConnectErrorKind::PeerNotFound,
@@ -177,19 +209,43 @@ impl ConnectError {
         }
     }
 
+    /// The error code reported by the service.  Zero for errors of kinds
+    /// `Canceled` and `Service`.
     pub fn as_i16(&self) -> i16 {
         self.code
     }
+
+    /// The program-side error of kind `Service`.
+    pub fn service_error(&self) -> Option<&ServiceError> {
+        match &self.repr {
+            Repr::Service(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ServiceError> for ConnectError {
+    fn from(e: ServiceError) -> Self {
+        Self {
+            code: 0,
+            repr: Repr::Service(e),
+        }
+    }
 }
 
 impl fmt::Display for ConnectError {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        if let Repr::Service(e) = &self.repr {
+            return e.fmt(f);
+        }
+
         match self.kind() {
             ConnectErrorKind::GroupNotFound => f.write_str("group not found"),
             ConnectErrorKind::PeerNotFound => f.write_str("peer not found"),
             ConnectErrorKind::Singularity => f.write_str("singularity"),
             ConnectErrorKind::AlreadyConnecting => f.write_str("already connecting"),
             ConnectErrorKind::AlreadyConnected => f.write_str("already connected"),
+            ConnectErrorKind::Canceled => f.write_str("connection attempt canceled"),
             _ => self.code.fmt(f),
         }
     }
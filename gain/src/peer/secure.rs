@@ -0,0 +1,1086 @@
+// Copyright (c) 2026 Timo Savola.
+// Use of this source code is governed by the MIT
+// license that can be found in the LICENSE file.
+
+//! Encrypted peer connections.
+//!
+//! A [Noise](https://noiseprotocol.org) handshake is performed over a peer
+//! stream, after which all data is encrypted and authenticated.  Both sides
+//! have a static [`Keypair`]; the public keys are exchanged and verified
+//! during the handshake.
+//!
+//! Two handshake patterns are supported:
+//!
+//! - [`Pattern::XX`]: neither side knows the other's public key in advance.
+//! - [`Pattern::IK`]: the initiator knows the responder's public key, which
+//!   saves a round trip.
+//!
+//! Each side sends its [instance id](crate::identity::instance_id) as an
+//! encrypted handshake payload.  The id is asserted by the remote party and
+//! isn't verified: whoever holds the remote private key can claim any id.
+//! The application decides whether to trust the public key, and the id only
+//! as far as it trusts the key's owner.
+//!
+//! ```ignore
+//! let (stream, _) = peer::connect(group, peer, "application/octet-stream").await?;
+//! let mut conn = secure::initiate(stream, &keypair, None).await?;
+//! if trusted(conn.remote_public(), conn.remote_instance_id()) {
+//!     conn.write_all(b"hello").await?;
+//! }
+//! ```
+//!
+//! Encrypted records are at most 65535 bytes, preceded by a 16-bit
+//! little-endian length.  Closing the output direction sends an empty record,
+//! so that the reader can tell a complete stream from a truncated one.
+
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::mem::take;
+use std::task::{Context, Poll};
+
+use futures_util::future::{poll_fn, LocalBoxFuture};
+use rand_core::{CryptoRng, RngCore};
+use snow::params::{DHChoice, NoiseParams};
+use snow::resolvers::{CryptoResolver, DefaultResolver};
+use snow::types::{Cipher, Dh, Hash, Random};
+use snow::{HandshakeState, TransportState};
+use zeroize::Zeroize;
+
+use crate::core;
+use crate::identity;
+use crate::random::random;
+use crate::stream::buf::{self, Buf, ReadWriteStream};
+use crate::stream::{Close, CloseWrite, DynClose, DynRead, DynWrite, Write};
+
+const PROLOGUE: &[u8] = b"gain peer secure 1";
+const MAX_MESSAGE_LEN: usize = 65535;
+const TAG_LEN: usize = 16;
+const MAX_PLAINTEXT_LEN: usize = MAX_MESSAGE_LEN - TAG_LEN;
+
+/// Length of public and private keys.
+pub const KEY_LEN: usize = 32;
+
+/// Static Curve25519 key pair.  The private key is zeroed when the key pair
+/// is dropped.
+pub struct Keypair {
+    private: [u8; KEY_LEN],
+    public: [u8; KEY_LEN],
+}
+
+impl Keypair {
+    /// Generate a new key pair using the random service.
+    pub async fn generate() -> Self {
+        Self::from_private(random().await)
+    }
+
+    /// Restore a key pair from a private key.
+    pub fn from_private(private: [u8; KEY_LEN]) -> Self {
+        let mut dh = DefaultResolver.resolve_dh(&DHChoice::Curve25519).unwrap();
+        dh.set(&private);
+
+        let mut public = [0; KEY_LEN];
+        public.copy_from_slice(dh.pubkey());
+
+        Self { private, public }
+    }
+
+    pub fn private(&self) -> &[u8; KEY_LEN] {
+        &self.private
+    }
+
+    pub fn public(&self) -> &[u8; KEY_LEN] {
+        &self.public
+    }
+}
+
+impl Drop for Keypair {
+    fn drop(&mut self) {
+        self.private.zeroize();
+    }
+}
+
+impl fmt::Debug for Keypair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Keypair")
+            .field("public", &self.public)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Noise handshake pattern.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Pattern {
+    XX,
+    IK,
+}
+
+impl Pattern {
+    fn params(self) -> NoiseParams {
+        match self {
+            Pattern::XX => "Noise_XX_25519_ChaChaPoly_BLAKE2s",
+            Pattern::IK => "Noise_IK_25519_ChaChaPoly_BLAKE2s",
+        }
+        .parse()
+        .unwrap()
+    }
+
+    /// Number of handshake messages written by a party.
+    fn writes(self, initiator: bool) -> usize {
+        match (self, initiator) {
+            (Pattern::XX, true) => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// Perform the initiator side of a handshake.  The [`IK`](Pattern::IK)
+/// pattern is used if the remote public key is specified, otherwise
+/// [`XX`](Pattern::XX).
+///
+/// The stream may be a [`RecvWriteStream`](crate::stream::RecvWriteStream)
+/// or a [`ReadWriteStream`].
+pub async fn initiate<S: Into<ReadWriteStream>>(
+    stream: S,
+    keypair: &Keypair,
+    remote_public: Option<&[u8; KEY_LEN]>,
+) -> Result<SecureStream, HandshakeError> {
+    let (pattern, state) = initiator(keypair, remote_public, random().await)?;
+    let local_id = identity::instance_id().await.unwrap_or_default();
+    handshake(stream.into(), pattern, state, &local_id).await
+}
+
+/// Perform the responder side of a handshake.  The pattern must match the
+/// initiator's choice.
+pub async fn respond<S: Into<ReadWriteStream>>(
+    stream: S,
+    keypair: &Keypair,
+    pattern: Pattern,
+) -> Result<SecureStream, HandshakeError> {
+    let state = responder(keypair, pattern, random().await)?;
+    let local_id = identity::instance_id().await.unwrap_or_default();
+    handshake(stream.into(), pattern, state, &local_id).await
+}
+
+fn initiator(
+    keypair: &Keypair,
+    remote_public: Option<&[u8; KEY_LEN]>,
+    seed: [u8; KEY_LEN],
+) -> Result<(Pattern, HandshakeState), snow::Error> {
+    let pattern = match remote_public {
+        Some(_) => Pattern::IK,
+        None => Pattern::XX,
+    };
+
+    let mut builder = snow::Builder::with_resolver(pattern.params(), resolver(seed))
+        .local_private_key(&keypair.private)
+        .prologue(PROLOGUE);
+    if let Some(key) = remote_public {
+        builder = builder.remote_public_key(key);
+    }
+
+    Ok((pattern, builder.build_initiator()?))
+}
+
+fn responder(
+    keypair: &Keypair,
+    pattern: Pattern,
+    seed: [u8; KEY_LEN],
+) -> Result<HandshakeState, snow::Error> {
+    snow::Builder::with_resolver(pattern.params(), resolver(seed))
+        .local_private_key(&keypair.private)
+        .prologue(PROLOGUE)
+        .build_responder()
+}
+
+async fn handshake(
+    mut rw: ReadWriteStream,
+    pattern: Pattern,
+    mut state: HandshakeState,
+    local_id: &str,
+) -> Result<SecureStream, HandshakeError> {
+    let remote_id = exchange(&mut rw, pattern, &mut state, local_id).await?;
+
+    let handshake_hash = state.get_handshake_hash().to_vec();
+    let transport = state.into_transport_mode()?;
+
+    let mut remote_public = [0; KEY_LEN];
+    remote_public.copy_from_slice(transport.get_remote_static().unwrap());
+
+    Ok(SecureStream {
+        rw,
+        transport,
+        remote_public,
+        remote_id: if remote_id.is_empty() {
+            None
+        } else {
+            Some(remote_id)
+        },
+        handshake_hash,
+        plain: Buf::new(MAX_PLAINTEXT_LEN),
+        ended: false,
+        out: Vec::new(),
+        out_pos: 0,
+        closing: false,
+        failed: false,
+    })
+}
+
+/// Exchange handshake messages until the handshake is finished.  Returns the
+/// remote instance id.
+async fn exchange<S: buf::Read + Write>(
+    stream: &mut S,
+    pattern: Pattern,
+    state: &mut HandshakeState,
+    local_id: &str,
+) -> Result<String, HandshakeError> {
+    let mut remote_id = String::new();
+    let mut writes = pattern.writes(state.is_initiator());
+    let mut buf = vec![0; 2 + MAX_MESSAGE_LEN];
+
+    while !state.is_handshake_finished() {
+        if state.is_my_turn() {
+            // The instance id is sent with the last message, which is
+            // encrypted in both patterns.
+            writes -= 1;
+            let payload = if writes == 0 {
+                local_id.as_bytes()
+            } else {
+                &[]
+            };
+
+            let n = state.write_message(payload, &mut buf[2..])?;
+            buf[..2].copy_from_slice(&(n as u16).to_le_bytes());
+            stream.write_all(&buf[..2 + n]).await?;
+        } else {
+            buf::ReadExt::read_exact(stream, &mut buf[..2]).await?;
+            let n = u16::from_le_bytes([buf[0], buf[1]]) as usize;
+            buf::ReadExt::read_exact(stream, &mut buf[2..2 + n]).await?;
+
+            let mut payload = vec![0; n];
+            let len = state.read_message(&buf[2..2 + n], &mut payload)?;
+            if len > 0 {
+                remote_id = String::from_utf8(payload[..len].to_vec())
+                    .map_err(|_| HandshakeError::InvalidInstanceId)?;
+            }
+        }
+    }
+
+    Ok(remote_id)
+}
+
+/// Encrypted bidirectional stream.
+///
+/// Writes are encrypted in records of up to 65519 bytes.  Flushing waits
+/// until the records have been sent.
+///
+/// If a record can't be decrypted or encrypted, the stream becomes unusable:
+/// all subsequent reads and writes fail with `ErrorKind::InvalidData`.
+///
+/// The stream implements [`DynRead`], [`DynWrite`] and [`DynClose`], so it
+/// can be used in place of other streams.  Notes can't be written, as they
+/// would be conveyed unencrypted.
+pub struct SecureStream {
+    rw: ReadWriteStream,
+    transport: TransportState,
+    remote_public: [u8; KEY_LEN],
+    remote_id: Option<String>,
+    handshake_hash: Vec<u8>,
+    plain: Buf,
+    ended: bool, // The end record has been received.
+    out: Vec<u8>,
+    out_pos: usize,
+    closing: bool, // The end record has been queued.
+    failed: bool,  // The session is unusable.
+}
+
+impl SecureStream {
+    /// The remote party's static public key.
+    pub fn remote_public(&self) -> &[u8; KEY_LEN] {
+        &self.remote_public
+    }
+
+    /// The instance id sent by the remote party, if it has one.  The id is
+    /// not authenticated; see the [module documentation](self).
+    pub fn remote_instance_id(&self) -> Option<&str> {
+        self.remote_id.as_deref()
+    }
+
+    /// Hash which uniquely identifies the session.  It can be used for
+    /// channel binding.
+    pub fn handshake_hash(&self) -> &[u8] {
+        &self.handshake_hash
+    }
+
+    /// Get a reference to the underlying stream.
+    pub fn get_ref(&self) -> &ReadWriteStream {
+        &self.rw
+    }
+
+    /// Read some decrypted bytes into a slice.  Zero is returned at the end
+    /// of the stream.
+    ///
+    /// Fails with `ErrorKind::InvalidData` if a record can't be decrypted,
+    /// and with `ErrorKind::UnexpectedEof` if the underlying stream ends
+    /// before the remote party has closed its output direction.
+    pub async fn read(&mut self, dest: &mut [u8]) -> io::Result<usize> {
+        poll_fn(|cx| self.poll_read(cx, dest)).await
+    }
+
+    /// Fill a slice completely.
+    ///
+    /// Fails with `ErrorKind::UnexpectedEof` if the stream ends first.
+    pub async fn read_exact(&mut self, dest: &mut [u8]) -> io::Result<()> {
+        let mut filled = 0;
+        while filled < dest.len() {
+            match self.read(&mut dest[filled..]).await? {
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                n => filled += n,
+            }
+        }
+        Ok(())
+    }
+
+    /// Read until the end of the stream.  See
+    /// [`ReadExt::read_to_end`](buf::ReadExt::read_to_end).
+    pub async fn read_to_end(&mut self, dest: &mut Vec<u8>, limit: usize) -> io::Result<usize> {
+        let mut count = 0;
+
+        while poll_fn(|cx| self.poll_plain(cx, 1)).await? {
+            let data = self.plain.as_slice();
+            if count + data.len() > limit {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "stream data exceeds limit",
+                ));
+            }
+
+            dest.extend_from_slice(data);
+            count += data.len();
+            self.plain.consume_all();
+        }
+
+        Ok(count)
+    }
+
+    /// Read a UTF-8 line.  See [`ReadExt::read_line`](buf::ReadExt::read_line).
+    pub async fn read_line(&mut self, dest: &mut String, max_len: usize) -> io::Result<usize> {
+        let mut line = Vec::new();
+        let mut overlong = false;
+
+        while poll_fn(|cx| self.poll_plain(cx, 1)).await? {
+            let data = self.plain.as_slice();
+            let (n, found) = match data.iter().position(|&b| b == b'\n') {
+                Some(i) => (i + 1, true),
+                None => (data.len(), false),
+            };
+
+            // An overlong line is discarded up to and including the newline.
+            overlong |= line.len() + n > max_len;
+            if !overlong {
+                line.extend_from_slice(&data[..n]);
+            }
+            self.plain.consume(n);
+            if found {
+                break;
+            }
+        }
+
+        if overlong {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"));
+        }
+
+        match String::from_utf8(line) {
+            Ok(s) => {
+                dest.push_str(&s);
+                Ok(s.len())
+            }
+            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        }
+    }
+
+    /// Encrypt part of a byte slice.  The record is sent in the background.
+    pub async fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        poll_fn(|cx| self.poll_write(cx, data)).await
+    }
+
+    /// Encrypt a whole byte slice.
+    pub async fn write_all(&mut self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            let n = self.write(data).await?;
+            data = &data[n..];
+        }
+        Ok(())
+    }
+
+    /// Wait until all records have been sent.
+    pub async fn flush(&mut self) -> io::Result<()> {
+        poll_fn(|cx| self.poll_flush(cx)).await
+    }
+
+    /// Send the end record, flush, and close the output direction, leaving
+    /// the input direction open.
+    pub async fn close_write(&mut self) -> io::Result<()> {
+        poll_fn(|cx| self.poll_end(cx)).await?;
+        self.rw.close_write(0).await;
+        Ok(())
+    }
+
+    /// Send the end record, flush, and close both directions.
+    pub async fn close(&mut self) -> io::Result<()> {
+        poll_fn(|cx| self.poll_end(cx)).await?;
+        self.rw.close().await;
+        Ok(())
+    }
+
+    fn poll_read(&mut self, cx: &mut Context, dest: &mut [u8]) -> Poll<io::Result<usize>> {
+        if dest.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        match self.poll_plain(cx, 1) {
+            Poll::Ready(Ok(true)) => Poll::Ready(io::Read::read(&mut self.plain, dest)),
+            Poll::Ready(Ok(false)) => Poll::Ready(Ok(0)),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// Decrypt records until `min_read` bytes are available or the stream
+    /// ends.  The result is false if nothing is available.
+    fn poll_plain(&mut self, cx: &mut Context, min_read: usize) -> Poll<io::Result<bool>> {
+        while self.plain.len() < min_read {
+            match self.poll_record(cx) {
+                Poll::Ready(Ok(true)) => {}
+                Poll::Ready(Ok(false)) => break,
+                other => return other,
+            }
+        }
+
+        Poll::Ready(Ok(!self.plain.is_empty()))
+    }
+
+    /// Decrypt the next record.  The result is false if the stream has ended.
+    fn poll_record(&mut self, cx: &mut Context) -> Poll<io::Result<bool>> {
+        if self.failed {
+            return Poll::Ready(Err(failed()));
+        }
+
+        if self.ended {
+            return Poll::Ready(Ok(false));
+        }
+
+        let r = &mut self.rw.r.r;
+
+        match r.poll_buf(cx, 2) {
+            Poll::Ready(Ok(true)) => {}
+            Poll::Ready(Ok(false)) => return Poll::Ready(Err(truncated())),
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => return Poll::Pending,
+        }
+
+        let b = r.buf.as_slice();
+        if b.len() < 2 {
+            return Poll::Ready(Err(truncated()));
+        }
+        let len = 2 + u16::from_le_bytes([b[0], b[1]]) as usize;
+
+        match r.poll_buf(cx, len) {
+            Poll::Ready(Ok(_)) => {}
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => return Poll::Pending,
+        }
+
+        let b = r.buf.as_slice();
+        if b.len() < len {
+            return Poll::Ready(Err(truncated()));
+        }
+
+        let mut plain = vec![0; len - 2];
+        let result = self.transport.read_message(&b[2..len], &mut plain);
+        r.buf.consume(len);
+
+        match result {
+            Ok(0) => {
+                // Writes never produce empty records.
+                self.ended = true;
+                Poll::Ready(Ok(false))
+            }
+            Ok(n) => {
+                plain.truncate(n);
+                self.plain.append(&mut plain, &mut Vec::new());
+                Poll::Ready(Ok(true))
+            }
+            Err(_) => {
+                self.plain.consume_all();
+                self.failed = true;
+                Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "record decryption failed",
+                )))
+            }
+        }
+    }
+
+    fn poll_write(&mut self, cx: &mut Context, data: &[u8]) -> Poll<io::Result<usize>> {
+        if self.failed {
+            return Poll::Ready(Err(failed()));
+        }
+
+        match self.poll_send(cx) {
+            Poll::Ready(Ok(())) => {}
+            other => return other.map(|r| r.map(|_| 0)),
+        }
+
+        if self.closing {
+            return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+        }
+
+        if data.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let n = data.len().min(MAX_PLAINTEXT_LEN);
+        if let Err(e) = self.seal(&data[..n]) {
+            return Poll::Ready(Err(e));
+        }
+
+        // Start sending right away.
+        if let Poll::Ready(Err(e)) = self.poll_send(cx) {
+            return Poll::Ready(Err(e));
+        }
+
+        Poll::Ready(Ok(n))
+    }
+
+    /// Queue the end record (once), and wait until everything has been sent.
+    fn poll_end(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        match self.poll_send(cx) {
+            Poll::Ready(Ok(())) => {}
+            other => return other,
+        }
+
+        if !self.closing {
+            self.seal(&[])?;
+            self.closing = true;
+        }
+
+        self.poll_flush(cx)
+    }
+
+    /// Encrypt a record and append it to the output buffer.
+    fn seal(&mut self, data: &[u8]) -> io::Result<()> {
+        let start = self.out.len();
+        self.out.resize(start + 2 + data.len() + TAG_LEN, 0);
+
+        match self
+            .transport
+            .write_message(data, &mut self.out[start + 2..])
+        {
+            Ok(len) => {
+                self.out[start..start + 2].copy_from_slice(&(len as u16).to_le_bytes());
+                self.out.truncate(start + 2 + len);
+                Ok(())
+            }
+            Err(e) => {
+                self.out.truncate(start);
+                self.failed = true;
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    HandshakeError::Noise(e),
+                ))
+            }
+        }
+    }
+
+    fn poll_send(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        while self.out_pos < self.out.len() {
+            match core::poll_write_stream(&self.rw.w.s, cx, &self.out[self.out_pos..], 0) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                Poll::Ready(Ok(n)) => self.out_pos += n,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        self.out.clear();
+        self.out_pos = 0;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        match self.poll_send(cx) {
+            Poll::Ready(Ok(())) => core::poll_flush_stream(&self.rw.w.s, cx).map(Ok),
+            other => other,
+        }
+    }
+}
+
+impl DynRead for SecureStream {
+    fn dyn_read<'a>(&'a mut self, dest: &'a mut [u8]) -> LocalBoxFuture<'a, io::Result<usize>> {
+        Box::pin(self.read(dest))
+    }
+
+    fn dyn_buf_read<'a>(
+        &'a mut self,
+        min_read: usize,
+        receptor: &'a mut dyn FnMut(&mut Buf),
+    ) -> LocalBoxFuture<'a, io::Result<()>> {
+        if min_read == 0 {
+            panic!("minimum read length is zero");
+        }
+
+        Box::pin(async move {
+            if poll_fn(|cx| self.poll_plain(cx, min_read)).await? {
+                receptor(&mut self.plain);
+            }
+            Ok(())
+        })
+    }
+
+    fn dyn_read_exact<'a>(&'a mut self, dest: &'a mut [u8]) -> LocalBoxFuture<'a, io::Result<()>> {
+        Box::pin(self.read_exact(dest))
+    }
+
+    fn dyn_read_to_end<'a>(
+        &'a mut self,
+        dest: &'a mut Vec<u8>,
+        limit: usize,
+    ) -> LocalBoxFuture<'a, io::Result<usize>> {
+        Box::pin(self.read_to_end(dest, limit))
+    }
+
+    fn dyn_read_line<'a>(
+        &'a mut self,
+        dest: &'a mut String,
+        max_len: usize,
+    ) -> LocalBoxFuture<'a, io::Result<usize>> {
+        Box::pin(self.read_line(dest, max_len))
+    }
+}
+
+impl DynWrite for SecureStream {
+    fn dyn_write<'a>(&'a mut self, data: &'a [u8]) -> LocalBoxFuture<'a, io::Result<usize>> {
+        Box::pin(self.write(data))
+    }
+
+    /// Fails with `ErrorKind::Unsupported` if the note is non-zero.
+    fn dyn_write_note<'a>(
+        &'a mut self,
+        data: &'a [u8],
+        note: i32,
+    ) -> LocalBoxFuture<'a, io::Result<usize>> {
+        if note != 0 {
+            return Box::pin(async {
+                Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "notes can't be encrypted",
+                ))
+            });
+        }
+
+        Box::pin(self.write(data))
+    }
+
+    fn dyn_write_all<'a>(&'a mut self, data: &'a [u8]) -> LocalBoxFuture<'a, io::Result<()>> {
+        Box::pin(self.write_all(data))
+    }
+}
+
+impl DynClose for SecureStream {
+    /// Sends the pending and end records, and closes both directions.  The
+    /// stream can't be used afterwards.
+    fn dyn_close(&mut self) -> LocalBoxFuture<'static, ()> {
+        if !self.closing && self.seal(&[]).is_ok() {
+            self.closing = true;
+        }
+
+        let mut out = take(&mut self.out);
+        out.drain(..take(&mut self.out_pos));
+        let mut rw = take(&mut self.rw);
+
+        Box::pin(async move {
+            if !out.is_empty() {
+                let _ = rw.write_all(&out).await;
+            }
+            rw.close().await;
+        })
+    }
+}
+
+impl fmt::Debug for SecureStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SecureStream")
+            .field("remote_public", &self.remote_public)
+            .field("remote_instance_id", &self.remote_id)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl futures_io::AsyncRead for SecureStream {
+    fn poll_read(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut Context,
+        dest: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_read(cx, dest)
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl futures_io::AsyncWrite for SecureStream {
+    fn poll_write(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut Context,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_write(cx, data)
+    }
+
+    fn poll_flush(self: std::pin::Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.get_mut().poll_flush(cx)
+    }
+
+    /// Sends the end record and closes both directions.
+    fn poll_close(self: std::pin::Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.poll_end(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        let closer = &mut this.rw.r.closer;
+        let wait = closer.how << 2; // STREAM_SELF -> STREAM_PEER
+        core::poll_close_stream(&mut closer.s, closer.how, wait, cx).map(Ok)
+    }
+}
+
+fn failed() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "secure stream is unusable after an error",
+    )
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "secure stream was truncated")
+}
+
+/// Handshake failure.
+#[derive(Debug)]
+pub enum HandshakeError {
+    /// Stream I/O failed.
+    Io(io::Error),
+    /// The Noise protocol failed, e.g. due to an unexpected public key.
+    Noise(snow::Error),
+    /// The remote instance id is not valid UTF-8.
+    InvalidInstanceId,
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            HandshakeError::Io(e) => e.fmt(f),
+            HandshakeError::Noise(e) => e.fmt(f),
+            HandshakeError::InvalidInstanceId => f.write_str("invalid instance id"),
+        }
+    }
+}
+
+impl Error for HandshakeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HandshakeError::Io(e) => Some(e),
+            HandshakeError::Noise(e) => Some(e),
+            HandshakeError::InvalidInstanceId => None,
+        }
+    }
+}
+
+impl From<io::Error> for HandshakeError {
+    fn from(e: io::Error) -> Self {
+        HandshakeError::Io(e)
+    }
+}
+
+impl From<snow::Error> for HandshakeError {
+    fn from(e: snow::Error) -> Self {
+        HandshakeError::Noise(e)
+    }
+}
+
+impl From<HandshakeError> for io::Error {
+    fn from(e: HandshakeError) -> Self {
+        match e {
+            HandshakeError::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+/// Create a resolver which provides the ephemeral key material from a seed
+/// (obtained from the random service), and delegates everything else to
+/// snow's default resolver.
+fn resolver(seed: [u8; KEY_LEN]) -> Box<dyn CryptoResolver + Send> {
+    Box::new(Resolver {
+        seed: Cell::new(Some(seed)),
+    })
+}
+
+struct Resolver {
+    seed: Cell<Option<[u8; KEY_LEN]>>,
+}
+
+impl CryptoResolver for Resolver {
+    fn resolve_rng(&self) -> Option<Box<dyn Random>> {
+        Some(Box::new(SeedRng(self.seed.take()?, 0)))
+    }
+
+    fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<dyn Dh>> {
+        DefaultResolver.resolve_dh(choice)
+    }
+
+    fn resolve_hash(&self, choice: &snow::params::HashChoice) -> Option<Box<dyn Hash>> {
+        DefaultResolver.resolve_hash(choice)
+    }
+
+    fn resolve_cipher(&self, choice: &snow::params::CipherChoice) -> Option<Box<dyn Cipher>> {
+        DefaultResolver.resolve_cipher(choice)
+    }
+}
+
+/// Yields the seed bytes once; a handshake needs just one ephemeral key.
+struct SeedRng([u8; KEY_LEN], usize);
+
+impl RngCore for SeedRng {
+    fn next_u32(&mut self) -> u32 {
+        rand_core::impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.try_fill_bytes(dest)
+            .expect("handshake randomness exhausted")
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        let avail = &mut self.0[self.1..];
+        if dest.len() > avail.len() {
+            return Err(rand_core::Error::new("handshake randomness exhausted"));
+        }
+
+        dest.copy_from_slice(&avail[..dest.len()]);
+        avail[..dest.len()].fill(0);
+        self.1 += dest.len();
+        Ok(())
+    }
+}
+
+impl CryptoRng for SeedRng {}
+
+impl Random for SeedRng {}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::future::join;
+
+    use super::*;
+    use crate::stream::duplex;
+    use crate::task::test_block_on;
+
+    const CAPACITY: usize = 4096;
+
+    fn keypairs() -> (Keypair, Keypair) {
+        (
+            Keypair::from_private([1; KEY_LEN]),
+            Keypair::from_private([2; KEY_LEN]),
+        )
+    }
+
+    /// Handshake without the random and identity services.
+    async fn connect(
+        a: &Keypair,
+        b: &Keypair,
+        remote_public: Option<&[u8; KEY_LEN]>,
+    ) -> (SecureStream, SecureStream) {
+        let (x, y) = duplex(CAPACITY);
+        let (pattern, i) = initiator(a, remote_public, [3; KEY_LEN]).unwrap();
+        let r = responder(b, pattern, [4; KEY_LEN]).unwrap();
+
+        let (i, r) = join(
+            handshake(x, pattern, i, "initiator"),
+            handshake(y, pattern, r, "responder"),
+        )
+        .await;
+        (i.unwrap(), r.unwrap())
+    }
+
+    fn check_session(i: &SecureStream, r: &SecureStream, a: &Keypair, b: &Keypair) {
+        assert_eq!(i.remote_public(), b.public());
+        assert_eq!(r.remote_public(), a.public());
+        assert_eq!(i.remote_instance_id(), Some("responder"));
+        assert_eq!(r.remote_instance_id(), Some("initiator"));
+        assert_eq!(i.handshake_hash(), r.handshake_hash());
+    }
+
+    #[test]
+    fn handshake_xx() {
+        test_block_on(async {
+            let (a, b) = keypairs();
+            let (i, r) = connect(&a, &b, None).await;
+            check_session(&i, &r, &a, &b);
+        });
+    }
+
+    #[test]
+    fn handshake_ik() {
+        test_block_on(async {
+            let (a, b) = keypairs();
+            let (i, r) = connect(&a, &b, Some(b.public())).await;
+            check_session(&i, &r, &a, &b);
+        });
+    }
+
+    #[test]
+    fn handshake_ik_wrong_key() {
+        test_block_on(async {
+            let (a, b) = keypairs();
+            let (x, mut y) = duplex(CAPACITY);
+            let (pattern, i) = initiator(&a, Some(a.public()), [3; KEY_LEN]).unwrap();
+            let mut r = responder(&b, pattern, [4; KEY_LEN]).unwrap();
+
+            let (i, r) = join(handshake(x, pattern, i, ""), async {
+                let result = exchange(&mut y, pattern, &mut r, "").await;
+                y.close().await;
+                result
+            })
+            .await;
+
+            assert!(matches!(r, Err(HandshakeError::Noise(_))));
+            assert!(matches!(i, Err(HandshakeError::Io(_))));
+        });
+    }
+
+    #[test]
+    fn round_trip() {
+        test_block_on(async {
+            let (a, b) = keypairs();
+            let (mut i, mut r) = connect(&a, &b, None).await;
+
+            // Spans several records.
+            let data: Vec<u8> = (0..150_000u32).map(|x| x as u8).collect();
+
+            let ((), received) = join(
+                async {
+                    i.write_all(&data).await.unwrap();
+                    i.write_all(b"line\n").await.unwrap();
+                    i.close_write().await.unwrap();
+
+                    let mut reply = String::new();
+                    i.read_line(&mut reply, 100).await.unwrap();
+                    assert_eq!(reply, "ok\n");
+                    i.close().await.unwrap();
+                },
+                async {
+                    let mut received = vec![0; data.len()];
+                    r.dyn_read_exact(&mut received).await.unwrap();
+
+                    let mut line = Vec::new();
+                    assert_eq!(r.dyn_read_to_end(&mut line, 100).await.unwrap(), 5);
+                    assert_eq!(line, b"line\n");
+
+                    r.dyn_write_all(b"ok\n").await.unwrap();
+                    r.dyn_close().await;
+                    received
+                },
+            )
+            .await;
+
+            assert_eq!(received, data);
+        });
+    }
+
+    #[test]
+    fn tampered_record() {
+        test_block_on(async {
+            let (a, b) = keypairs();
+            let (x, mut y) = duplex(CAPACITY);
+            let (pattern, i) = initiator(&a, None, [3; KEY_LEN]).unwrap();
+            let r = responder(&b, pattern, [4; KEY_LEN]).unwrap();
+
+            let (i, r) = join(handshake(x, pattern, i, ""), async {
+                let mut r = r;
+                exchange(&mut y, pattern, &mut r, "").await.unwrap();
+                y
+            })
+            .await;
+            let mut i = i.unwrap();
+            let mut y = r;
+
+            // A record which isn't encrypted with the session key.
+            let mut record = vec![20, 0];
+            record.extend_from_slice(&[0; 20]);
+            let (_, result) = join(y.write_all(&record), i.read(&mut [0; 10])).await;
+
+            let e = result.unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+            // The stream is unusable afterwards.
+            let e = i.read(&mut [0; 10]).await.unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+            let e = i.write(b"x").await.unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+            let e = i.read_line(&mut String::new(), 10).await.unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        });
+    }
+
+    #[test]
+    fn end_record() {
+        test_block_on(async {
+            let (a, b) = keypairs();
+            let (mut i, mut r) = connect(&a, &b, None).await;
+
+            i.write_all(b"data").await.unwrap();
+            i.close_write().await.unwrap();
+
+            let mut received = Vec::new();
+            assert_eq!(r.read_to_end(&mut received, 100).await.unwrap(), 4);
+            assert_eq!(received, b"data");
+            assert_eq!(r.read(&mut [0; 10]).await.unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn truncated_stream() {
+        test_block_on(async {
+            let (a, b) = keypairs();
+            let (mut i, mut r) = connect(&a, &b, None).await;
+
+            // Close the underlying stream without the end record.
+            i.write_all(b"data").await.unwrap();
+            i.flush().await.unwrap();
+            i.rw.close_write(0).await;
+
+            let mut received = Vec::new();
+            let e = r.read_to_end(&mut received, 100).await.unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+            assert_eq!(received, b"data");
+        });
+    }
+
+    #[test]
+    fn write_note_unsupported() {
+        test_block_on(async {
+            let (a, b) = keypairs();
+            let (mut i, _r) = connect(&a, &b, None).await;
+
+            let e = i.dyn_write_note(b"x", 1).await.unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::Unsupported);
+        });
+    }
+}
@@ -6,38 +6,69 @@
 
 use std::fmt;
 
+use crate::service::ServiceError;
+
 pub mod principal;
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum ErrorKind {
     Other,
     NotRegistered,
+    Service,
 }
 
 #[derive(Debug)]
 pub struct Error {
     code: i16,
+    service: Option<ServiceError>,
 }
 
 impl Error {
     fn new(code: i16) -> Self {
-        Self { code }
+        Self {
+            code,
+            service: None,
+        }
     }
 
     pub fn kind(&self) -> ErrorKind {
+        if self.service.is_some() {
+            return ErrorKind::Service;
+        }
+
         match self.code {
             1 => ErrorKind::NotRegistered,
             _ => ErrorKind::Other,
         }
     }
 
+    /// The error code reported by the service.  Zero for errors of kind
+    /// `Service`.
     pub fn as_i16(&self) -> i16 {
         self.code
     }
+
+    /// The program-side error of kind `Service`.
+    pub fn service_error(&self) -> Option<&ServiceError> {
+        self.service.as_ref()
+    }
+}
+
+impl From<ServiceError> for Error {
+    fn from(e: ServiceError) -> Self {
+        Self {
+            code: 0,
+            service: Some(e),
+        }
+    }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        if let Some(e) = &self.service {
+            return e.fmt(f);
+        }
+
         match self.kind() {
             ErrorKind::NotRegistered => f.write_str("not registered"),
             _ => self.code.fmt(f),
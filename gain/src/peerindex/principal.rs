@@ -4,11 +4,10 @@
 
 //! Find your program instances.
 
-use std::convert::TryInto;
-
 use crate::peer::{register_group, Listener};
 use crate::peerindex::Error;
-use crate::service::Service;
+use crate::service::typed::{Decode, Encode, ServiceCall};
+use crate::service::{Service, ServiceError};
 
 /// Peer group name.
 pub static GROUP_NAME: &str = "index/principal";
@@ -25,32 +24,27 @@ pub async fn register(listener: Listener) {
     SERVICE.send_info(&[]).await;
 }
 
+#[derive(Encode, ServiceCall)]
+#[gain(reply = InstancesReply)]
+struct InstancesCall;
+
+#[derive(Decode)]
+struct InstancesReply {
+    error: i16,
+    #[gain(prefix = u16)]
+    list: Vec<String>,
+}
+
 /// List peers.
 pub async fn instances() -> Result<Vec<String>, Error> {
-    SERVICE
-        .call(&[], |reply: &[u8]| {
-            if reply.len() < 4 {
-                return Err(Error::new(0));
-            }
-
-            let error = i16::from_le_bytes(reply[..2].try_into().unwrap());
-            if error != 0 {
-                return Err(Error::new(error));
-            }
-
-            let count = u16::from_le_bytes(reply[2..4].try_into().unwrap());
-            let mut list = Vec::with_capacity(count as usize);
-
-            let mut reply = &reply[4..];
-
-            for _ in 0..count {
-                let size = reply[0] as usize;
-                reply = &reply[1..];
-                list.push(String::from_utf8(reply[..size].into()).unwrap());
-                reply = &reply[size..];
-            }
-
-            Ok(list)
-        })
+    let reply = SERVICE
+        .call_typed(&InstancesCall)
         .await
+        .map_err(ServiceError::from)?;
+
+    if reply.error != 0 {
+        return Err(Error::new(reply.error));
+    }
+
+    Ok(reply.list)
 }
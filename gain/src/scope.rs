@@ -4,6 +4,7 @@
 
 //! Restrict execution privileges.
 
+use crate::service::typed::{CallError, Decode, Encode, ServiceCall};
 use crate::service::Service;
 
 lazy_static! {
@@ -12,45 +13,34 @@ lazy_static! {
 
 const CALL_RESTRICT: u8 = 0;
 
+#[derive(Encode, ServiceCall)]
+#[gain(reply = RestrictReply)]
+struct RestrictCall<'a> {
+    op: u8,
+    scope: &'a [&'a str],
+}
+
+#[derive(Decode)]
+struct RestrictReply {
+    error: i16,
+}
+
 /// Restrict execution privileges to the specified set.  Privileges cannot be
 /// added; each invocation can only remove privileges (extraneous scope is
 /// ignored).  Actual privileges depend also on the execution environment, and
 /// may vary during program execution.
 pub async fn restrict(scope: &[&str]) {
-    if scope.len() > 255 {
-        panic!("scope is too large");
+    let call = RestrictCall {
+        op: CALL_RESTRICT,
+        scope,
+    };
+
+    match SERVICE.call_typed(&call).await {
+        Ok(RestrictReply { error: 0 }) => {}
+        Ok(_) => panic!("unexpected scope service call error"),
+        Err(CallError::ContentTooLong) => panic!("scope is too large"),
+        Err(_) => panic!("unknown scope service call"),
     }
-
-    let mut bufsize = 1 + 1;
-    for s in scope.iter() {
-        let n = s.as_bytes().len();
-        if n > 255 {
-            panic!("scope string is too long");
-        }
-        bufsize += 1 + n;
-    }
-
-    let mut buf = Vec::with_capacity(bufsize);
-    buf.push(CALL_RESTRICT);
-    buf.push(scope.len() as u8);
-    for s in scope.iter() {
-        let b = s.as_bytes();
-        buf.push(b.len() as u8);
-        buf.extend_from_slice(b);
-    }
-
-    SERVICE
-        .call(buf.as_slice(), |reply: &[u8]| {
-            if reply.is_empty() {
-                panic!("unknown scope service call");
-            }
-
-            let error = i16::from_le_bytes(reply[..2].try_into().unwrap());
-            if error != 0 {
-                panic!("unexpected scope service call error");
-            }
-        })
-        .await;
 }
 
 /// Represents system access.
@@ -0,0 +1,258 @@
+// Copyright (c) 2020 Timo Savola.
+// Use of this source code is governed by the MIT
+// license that can be found in the LICENSE file.
+
+//! Service binding implementation support.
+//!
+//! Call content and replies can be encoded and decoded using the
+//! [`typed`] module.
+
+use std::error;
+use std::fmt;
+use std::ops::ControlFlow;
+use std::time::Duration;
+
+use crate::core;
+use crate::packet::Code;
+use crate::service::typed::{CallError, ServiceCall};
+use crate::stream::{RateLimiter, RecvStream, RecvWriteStream, Stats, WriteStream};
+
+pub mod typed;
+
+pub mod future {
+    pub use crate::core::CallFuture as Call;
+    pub use crate::core::CallOwnedFuture as CallOwned;
+    pub use crate::core::CallTimeoutFuture as CallTimeout;
+    pub use crate::core::InfoRecvFuture as InfoRecv;
+    pub use crate::core::InfoRecvUntilFuture as InfoRecvUntil;
+    pub use crate::core::InfoSendFuture as InfoSend;
+
+    pub type CallVec<'a> = Call<'a, fn(&[u8]) -> Vec<u8>, Vec<u8>>;
+}
+
+/// Reason for service registration failure.
+#[derive(Debug)]
+pub enum RegistrationError {
+    NameAlreadyRegistered,
+    TooManyServices,
+}
+
+/// Reason for stream construction failure.
+#[derive(Debug, Eq, PartialEq)]
+pub enum StreamInitError {
+    NegativeId,
+    AlreadyExists,
+}
+
+impl fmt::Display for StreamInitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            StreamInitError::NegativeId => f.write_str("negative stream id"),
+            StreamInitError::AlreadyExists => f.write_str("stream already exists"),
+        }
+    }
+}
+
+impl error::Error for StreamInitError {}
+
+/// Failure on the program side of a service interaction, as opposed to an
+/// error reported by the service.
+#[derive(Debug, Eq, PartialEq)]
+pub enum ServiceError {
+    Call(CallError),
+    StreamInit(StreamInitError),
+}
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            ServiceError::Call(e) => e.fmt(f),
+            ServiceError::StreamInit(e) => e.fmt(f),
+        }
+    }
+}
+
+impl error::Error for ServiceError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ServiceError::Call(e) => Some(e),
+            ServiceError::StreamInit(e) => Some(e),
+        }
+    }
+}
+
+impl From<CallError> for ServiceError {
+    fn from(e: CallError) -> Self {
+        ServiceError::Call(e)
+    }
+}
+
+impl From<StreamInitError> for ServiceError {
+    fn from(e: StreamInitError) -> Self {
+        ServiceError::StreamInit(e)
+    }
+}
+
+/// Service call didn't get a reply in time.
+#[derive(Debug, Eq, PartialEq)]
+pub struct TimedOut;
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.write_str("service call timed out")
+    }
+}
+
+impl error::Error for TimedOut {}
+
+/// Handle to a registered service.
+///
+/// If a stream is opened as a result of service registration or a call, the
+/// appropriate stream constructor must be called immediately.  Care must be
+/// taken when using input buffering with streams which carry stream ids.
+pub struct Service {
+    code: Code,
+}
+
+impl Service {
+    /// Register a service or panic.
+    pub fn register(name: &'static str) -> Self {
+        match Self::try_register(name) {
+            Ok(this) => this,
+            Err(e) => panic!("{}: {:?}", name, e),
+        }
+    }
+
+    /// Register a service.
+    pub fn try_register(name: &'static str) -> Result<Self, RegistrationError> {
+        Ok(Self {
+            code: core::register_service(name)?,
+        })
+    }
+
+    /// Call the service.  Returns a future.
+    ///
+    /// The receptor is invoked with the reply content, and its return value is
+    /// passed through.
+    pub fn call<'a, R, T>(&self, content: &'a [u8], receptor: R) -> future::Call<'a, R, T>
+    where
+        R: FnOnce(&[u8]) -> T + Unpin,
+    {
+        future::Call::new(self.code, content, receptor)
+    }
+
+    /// Call the service and copy the reply.  Returns a future.
+    pub fn call_vec<'a>(&self, content: &'a [u8]) -> future::CallVec<'a> {
+        future::Call::new(self.code, content, <[u8]>::to_vec)
+    }
+
+    /// Call the service with owned content, and copy the reply.  Returns a
+    /// future which doesn't borrow anything, so it can be spawned.  It may
+    /// also be dropped before completion; the reply is discarded.
+    pub fn call_owned<B>(&self, content: B) -> future::CallOwned<B>
+    where
+        B: AsRef<[u8]> + 'static,
+    {
+        future::CallOwned::new(self.code, content)
+    }
+
+    /// Call the service and copy the reply, unless it takes longer than the
+    /// timeout.  A late reply is discarded.  Returns a future.
+    pub fn call_timeout<B>(&self, content: B, timeout: Duration) -> future::CallTimeout<B>
+    where
+        B: AsRef<[u8]> + 'static,
+    {
+        future::CallTimeout::new(self.code, content, timeout)
+    }
+
+    /// Call the service with typed content, and decode the reply.
+    ///
+    /// Streams opened by the call can be constructed once the future
+    /// completes, as long as the task doesn't yield in between.
+    pub async fn call_typed<C: ServiceCall>(&self, call: &C) -> Result<C::Reply, CallError> {
+        let mut content = Vec::new();
+        call.encode(&mut content)?;
+        self.call(&content, typed::decode).await
+    }
+
+    /// Receive info packets from the service repeatedly.  Returns a future.
+    pub fn recv_info<R>(&self, receptor: R) -> future::InfoRecv<R>
+    where
+        R: FnMut(&[u8]) + Unpin,
+    {
+        future::InfoRecv::new(self.code, receptor)
+    }
+
+    /// Receive info packets from the service until the receptor breaks the
+    /// loop.  Returns a future which yields the break value.
+    pub fn recv_info_until<R, B>(&self, receptor: R) -> future::InfoRecvUntil<R, B>
+    where
+        R: FnMut(&[u8]) -> ControlFlow<B> + Unpin,
+    {
+        future::InfoRecvUntil::new(self.code, receptor)
+    }
+
+    /// Send an info packet to the service.  Returns a future.
+    pub fn send_info<'a>(&self, content: &'a [u8]) -> future::InfoSend<'a> {
+        future::InfoSend::new(self.code, content)
+    }
+
+    /// Traffic counters summed over all streams of the service, including
+    /// closed ones.
+    pub fn stats(&self) -> Stats {
+        core::service_stats(self.code)
+    }
+
+    /// Delay writes to all streams of the service according to a rate
+    /// limiter.  Stream-specific limiters apply in addition to it.
+    pub fn set_rate_limiter(&self, limiter: Option<RateLimiter>) {
+        core::set_service_rate_limiter(self.code, limiter)
+    }
+
+    pub fn rate_limiter(&self) -> Option<RateLimiter> {
+        core::service_rate_limiter(self.code)
+    }
+
+    /// Construct a handle to a new bidirectional stream.  Panics if the id
+    /// is invalid.
+    pub fn stream(&self, id: i32) -> RecvWriteStream {
+        RecvWriteStream::new(core::init_stream(self.code, id, BIDIRECTIONAL_FLAGS))
+    }
+
+    /// Construct a handle to a new unidirectional stream.  Panics if the id
+    /// is invalid.
+    pub fn input_stream(&self, id: i32) -> RecvStream {
+        RecvStream::new(core::init_stream(self.code, id, INPUT_FLAGS))
+    }
+
+    /// Construct a handle to a new unidirectional stream.  Panics if the id
+    /// is invalid.
+    pub fn output_stream(&self, id: i32) -> WriteStream {
+        WriteStream::new(core::init_stream(self.code, id, OUTPUT_FLAGS))
+    }
+
+    /// Construct a handle to a new bidirectional stream, or fail if the id is
+    /// negative or already in use.
+    pub fn try_stream(&self, id: i32) -> Result<RecvWriteStream, StreamInitError> {
+        core::try_init_stream(self.code, id, BIDIRECTIONAL_FLAGS).map(RecvWriteStream::new)
+    }
+
+    /// Construct a handle to a new unidirectional stream, or fail if the id is
+    /// negative or already in use.
+    pub fn try_input_stream(&self, id: i32) -> Result<RecvStream, StreamInitError> {
+        core::try_init_stream(self.code, id, INPUT_FLAGS).map(RecvStream::new)
+    }
+
+    /// Construct a handle to a new unidirectional stream, or fail if the id is
+    /// negative or already in use.
+    pub fn try_output_stream(&self, id: i32) -> Result<WriteStream, StreamInitError> {
+        core::try_init_stream(self.code, id, OUTPUT_FLAGS).map(WriteStream::new)
+    }
+}
+
+const BIDIRECTIONAL_FLAGS: core::StreamFlags = core::STREAM_SELF_FLOW
+    | core::STREAM_SELF_DATA
+    | core::STREAM_PEER_FLOW
+    | core::STREAM_PEER_DATA;
+const INPUT_FLAGS: core::StreamFlags = core::STREAM_SELF_FLOW | core::STREAM_PEER_DATA;
+const OUTPUT_FLAGS: core::StreamFlags = core::STREAM_SELF_DATA | core::STREAM_PEER_FLOW;
@@ -0,0 +1,503 @@
+// Copyright (c) 2026 Timo Savola.
+// Use of this source code is governed by the MIT
+// license that can be found in the LICENSE file.
+
+//! Typed service calls.
+//!
+//! Call content and replies are described as Rust types which implement
+//! [`Encode`] and [`Decode`].  The layouts used by the built-in services can
+//! be derived:
+//!
+//! - Integers are little-endian.
+//! - Byte arrays (e.g. padding) are copied as is.
+//! - Strings and vectors are prefixed by their length as `u8`.  Another
+//!   prefix type can be chosen with `#[gain(prefix = u16)]`.
+//! - The last field can take the rest of the content without a prefix with
+//!   `#[gain(rest)]`.
+//!
+//! ```ignore
+//! use gain::service::typed::{Decode, Encode, ServiceCall};
+//!
+//! #[derive(Encode, ServiceCall)]
+//! #[gain(reply = ListReply)]
+//! struct ListCall {
+//!     op: u8,
+//! }
+//!
+//! #[derive(Decode)]
+//! struct ListReply {
+//!     error: i16,
+//!     #[gain(prefix = u16)]
+//!     names: Vec<String>,
+//! }
+//!
+//! let reply = SERVICE.call_typed(&ListCall { op: 0 }).await?;
+//! ```
+//!
+//! Replies may be longer than their layout; the excess is ignored.  Vector
+//! elements must be at least one byte long.
+
+use std::error;
+use std::fmt;
+
+pub use gain_macros::{Decode, Encode, ServiceCall};
+
+/// Reason for typed call failure.
+#[derive(Debug, Eq, PartialEq)]
+pub enum CallError {
+    /// The reply is shorter than its layout.
+    ShortReply,
+    /// The reply contains an invalid value, such as a string which isn't
+    /// valid UTF-8.
+    MalformedReply,
+    /// A string or vector in the call content is too long for its length
+    /// prefix.
+    ContentTooLong,
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            CallError::ShortReply => f.write_str("service reply is too short"),
+            CallError::MalformedReply => f.write_str("malformed service reply"),
+            CallError::ContentTooLong => f.write_str("service call content is too long"),
+        }
+    }
+}
+
+impl error::Error for CallError {}
+
+/// Call content which has a typed reply.
+pub trait ServiceCall: Encode {
+    type Reply: Decode;
+}
+
+/// Serializable call content.
+pub trait Encode {
+    fn encode(&self, buf: &mut Vec<u8>) -> Result<(), CallError>;
+}
+
+/// Deserializable reply content.
+pub trait Decode: Sized {
+    fn decode(r: &mut Reader) -> Result<Self, CallError>;
+}
+
+/// Sequence which is encoded with a length prefix or as the rest of the
+/// content.
+pub trait EncodeSeq {
+    fn encode_prefixed<P: Prefix>(&self, buf: &mut Vec<u8>) -> Result<(), CallError>;
+
+    fn encode_rest(&self, buf: &mut Vec<u8>) -> Result<(), CallError>;
+}
+
+/// Sequence which is decoded with a length prefix or from the rest of the
+/// content.
+pub trait DecodeSeq: Sized {
+    fn decode_prefixed<P: Prefix>(r: &mut Reader) -> Result<Self, CallError>;
+
+    fn decode_rest(r: &mut Reader) -> Result<Self, CallError>;
+}
+
+/// Length prefix type.
+pub trait Prefix {
+    fn encode_len(len: usize, buf: &mut Vec<u8>) -> Result<(), CallError>;
+
+    fn decode_len(r: &mut Reader) -> Result<usize, CallError>;
+}
+
+/// Reply decoding position.
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    /// Consume a number of bytes.
+    pub fn take(&mut self, n: usize) -> Result<&'a [u8], CallError> {
+        if n > self.data.len() {
+            return Err(CallError::ShortReply);
+        }
+
+        let (b, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(b)
+    }
+
+    /// Consume all remaining bytes.
+    pub fn take_rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.data)
+    }
+}
+
+/// Decode a whole reply.
+pub fn decode<T: Decode>(data: &[u8]) -> Result<T, CallError> {
+    T::decode(&mut Reader::new(data))
+}
+
+macro_rules! impl_int {
+    ($($t:ty),*) => {
+        $(
+            impl Encode for $t {
+                fn encode(&self, buf: &mut Vec<u8>) -> Result<(), CallError> {
+                    buf.extend_from_slice(&self.to_le_bytes());
+                    Ok(())
+                }
+            }
+
+            impl Decode for $t {
+                fn decode(r: &mut Reader) -> Result<Self, CallError> {
+                    let b = r.take(std::mem::size_of::<$t>())?;
+                    Ok(<$t>::from_le_bytes(b.try_into().unwrap()))
+                }
+            }
+        )*
+    };
+}
+
+impl_int!(u8, i8, u16, i16, u32, i32, u64, i64);
+
+macro_rules! impl_prefix {
+    ($($t:ty),*) => {
+        $(
+            impl Prefix for $t {
+                fn encode_len(len: usize, buf: &mut Vec<u8>) -> Result<(), CallError> {
+                    let n = <$t>::try_from(len).map_err(|_| CallError::ContentTooLong)?;
+                    n.encode(buf)
+                }
+
+                fn decode_len(r: &mut Reader) -> Result<usize, CallError> {
+                    Ok(<$t>::decode(r)? as usize)
+                }
+            }
+        )*
+    };
+}
+
+impl_prefix!(u8, u16, u32);
+
+impl<const N: usize> Encode for [u8; N] {
+    fn encode(&self, buf: &mut Vec<u8>) -> Result<(), CallError> {
+        buf.extend_from_slice(self);
+        Ok(())
+    }
+}
+
+impl<const N: usize> Decode for [u8; N] {
+    fn decode(r: &mut Reader) -> Result<Self, CallError> {
+        Ok(r.take(N)?.try_into().unwrap())
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode(&self, buf: &mut Vec<u8>) -> Result<(), CallError> {
+        (**self).encode(buf)
+    }
+}
+
+impl<T: EncodeSeq + ?Sized> EncodeSeq for &T {
+    fn encode_prefixed<P: Prefix>(&self, buf: &mut Vec<u8>) -> Result<(), CallError> {
+        (**self).encode_prefixed::<P>(buf)
+    }
+
+    fn encode_rest(&self, buf: &mut Vec<u8>) -> Result<(), CallError> {
+        (**self).encode_rest(buf)
+    }
+}
+
+impl EncodeSeq for str {
+    fn encode_prefixed<P: Prefix>(&self, buf: &mut Vec<u8>) -> Result<(), CallError> {
+        P::encode_len(self.len(), buf)?;
+        self.encode_rest(buf)
+    }
+
+    fn encode_rest(&self, buf: &mut Vec<u8>) -> Result<(), CallError> {
+        buf.extend_from_slice(self.as_bytes());
+        Ok(())
+    }
+}
+
+impl Encode for str {
+    fn encode(&self, buf: &mut Vec<u8>) -> Result<(), CallError> {
+        self.encode_prefixed::<u8>(buf)
+    }
+}
+
+impl EncodeSeq for String {
+    fn encode_prefixed<P: Prefix>(&self, buf: &mut Vec<u8>) -> Result<(), CallError> {
+        self.as_str().encode_prefixed::<P>(buf)
+    }
+
+    fn encode_rest(&self, buf: &mut Vec<u8>) -> Result<(), CallError> {
+        self.as_str().encode_rest(buf)
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, buf: &mut Vec<u8>) -> Result<(), CallError> {
+        self.as_str().encode(buf)
+    }
+}
+
+impl DecodeSeq for String {
+    fn decode_prefixed<P: Prefix>(r: &mut Reader) -> Result<Self, CallError> {
+        let len = P::decode_len(r)?;
+        utf8(r.take(len)?)
+    }
+
+    fn decode_rest(r: &mut Reader) -> Result<Self, CallError> {
+        utf8(r.take_rest())
+    }
+}
+
+impl Decode for String {
+    fn decode(r: &mut Reader) -> Result<Self, CallError> {
+        Self::decode_prefixed::<u8>(r)
+    }
+}
+
+fn utf8(b: &[u8]) -> Result<String, CallError> {
+    String::from_utf8(b.to_vec()).map_err(|_| CallError::MalformedReply)
+}
+
+impl<T: Encode> EncodeSeq for [T] {
+    fn encode_prefixed<P: Prefix>(&self, buf: &mut Vec<u8>) -> Result<(), CallError> {
+        P::encode_len(self.len(), buf)?;
+        self.encode_rest(buf)
+    }
+
+    fn encode_rest(&self, buf: &mut Vec<u8>) -> Result<(), CallError> {
+        for x in self {
+            x.encode(buf)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Encode> Encode for [T] {
+    fn encode(&self, buf: &mut Vec<u8>) -> Result<(), CallError> {
+        self.encode_prefixed::<u8>(buf)
+    }
+}
+
+impl<T: Encode> EncodeSeq for Vec<T> {
+    fn encode_prefixed<P: Prefix>(&self, buf: &mut Vec<u8>) -> Result<(), CallError> {
+        self.as_slice().encode_prefixed::<P>(buf)
+    }
+
+    fn encode_rest(&self, buf: &mut Vec<u8>) -> Result<(), CallError> {
+        self.as_slice().encode_rest(buf)
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, buf: &mut Vec<u8>) -> Result<(), CallError> {
+        self.as_slice().encode(buf)
+    }
+}
+
+/// Decode a vector element.  Elements which don't consume any bytes are
+/// rejected, so that a count or the rest of the reply bounds the work.
+fn decode_element<T: Decode>(r: &mut Reader) -> Result<T, CallError> {
+    let len = r.data.len();
+    let x = T::decode(r)?;
+    if r.data.len() == len {
+        return Err(CallError::MalformedReply);
+    }
+    Ok(x)
+}
+
+impl<T: Decode> DecodeSeq for Vec<T> {
+    fn decode_prefixed<P: Prefix>(r: &mut Reader) -> Result<Self, CallError> {
+        let count = P::decode_len(r)?;
+        let mut v = Vec::with_capacity(std::cmp::min(count, r.data.len()));
+        for _ in 0..count {
+            v.push(decode_element(r)?);
+        }
+        Ok(v)
+    }
+
+    fn decode_rest(r: &mut Reader) -> Result<Self, CallError> {
+        let mut v = Vec::new();
+        while !r.data.is_empty() {
+            v.push(decode_element(r)?);
+        }
+        Ok(v)
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(r: &mut Reader) -> Result<Self, CallError> {
+        Self::decode_prefixed::<u8>(r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode<T: Encode>(x: &T) -> Result<Vec<u8>, CallError> {
+        let mut buf = Vec::new();
+        x.encode(&mut buf)?;
+        Ok(buf)
+    }
+
+    #[derive(Debug, Decode, Encode, PartialEq)]
+    struct Ints {
+        a: u8,
+        b: i16,
+        c: u32,
+        d: i64,
+        pad: [u8; 3],
+    }
+
+    #[derive(Debug, Decode, Encode, PartialEq)]
+    struct Prefixed {
+        s8: String,
+        #[gain(prefix = u16)]
+        s16: String,
+        #[gain(prefix = u32)]
+        v32: Vec<u16>,
+        v8: Vec<String>,
+    }
+
+    #[derive(Debug, Decode, Encode, PartialEq)]
+    struct Rest {
+        error: i16,
+        #[gain(rest)]
+        data: Vec<u8>,
+    }
+
+    #[derive(Debug, Decode, Encode, PartialEq)]
+    struct Tuple(u16, #[gain(rest)] String);
+
+    #[derive(Debug, Decode, PartialEq)]
+    struct Empty;
+
+    #[test]
+    fn ints() {
+        let x = Ints {
+            a: 1,
+            b: -2,
+            c: 0x03040506,
+            d: -7,
+            pad: [8, 9, 10],
+        };
+        let b = encode(&x).unwrap();
+        assert_eq!(
+            b,
+            [1, 0xfe, 0xff, 6, 5, 4, 3, 0xf9, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 8, 9, 10]
+        );
+        assert_eq!(decode::<Ints>(&b), Ok(x));
+    }
+
+    #[test]
+    fn prefixed() {
+        let x = Prefixed {
+            s8: "ab".into(),
+            s16: "c".into(),
+            v32: vec![1, 2],
+            v8: vec!["d".into(), "".into()],
+        };
+        let b = encode(&x).unwrap();
+        assert_eq!(
+            b,
+            [2, b'a', b'b', 1, 0, b'c', 2, 0, 0, 0, 1, 0, 2, 0, 2, 1, b'd', 0]
+        );
+        assert_eq!(decode::<Prefixed>(&b), Ok(x));
+    }
+
+    #[test]
+    fn rest() {
+        let x = Rest {
+            error: 3,
+            data: vec![4, 5, 6],
+        };
+        let b = encode(&x).unwrap();
+        assert_eq!(b, [3, 0, 4, 5, 6]);
+        assert_eq!(decode::<Rest>(&b), Ok(x));
+
+        let x = Tuple(1, "xyz".into());
+        let b = encode(&x).unwrap();
+        assert_eq!(b, [1, 0, b'x', b'y', b'z']);
+        assert_eq!(decode::<Tuple>(&b), Ok(x));
+
+        assert_eq!(
+            decode::<Rest>(&[0, 0]),
+            Ok(Rest {
+                error: 0,
+                data: Vec::new()
+            })
+        );
+    }
+
+    #[test]
+    fn excess_ignored() {
+        assert_eq!(decode::<u16>(&[1, 0, 2]), Ok(1));
+    }
+
+    #[test]
+    fn short_reply() {
+        assert_eq!(decode::<i32>(&[1, 2, 3]), Err(CallError::ShortReply));
+        assert_eq!(decode::<[u8; 2]>(&[1]), Err(CallError::ShortReply));
+        assert_eq!(
+            decode::<String>(&[3, b'a', b'b']),
+            Err(CallError::ShortReply)
+        );
+        assert_eq!(decode::<Vec<u16>>(&[2, 1, 0]), Err(CallError::ShortReply));
+        assert_eq!(decode::<Rest>(&[0]), Err(CallError::ShortReply));
+
+        let b = encode(&Prefixed {
+            s8: "ab".into(),
+            s16: "c".into(),
+            v32: vec![1, 2],
+            v8: vec!["d".into()],
+        })
+        .unwrap();
+        for n in 0..b.len() {
+            assert_eq!(decode::<Prefixed>(&b[..n]), Err(CallError::ShortReply));
+        }
+    }
+
+    #[test]
+    fn malformed_reply() {
+        assert_eq!(decode::<String>(&[1, 0xff]), Err(CallError::MalformedReply));
+        assert_eq!(
+            decode::<Tuple>(&[0, 0, 0xc3]),
+            Err(CallError::MalformedReply)
+        );
+    }
+
+    #[test]
+    fn zero_size_elements() {
+        assert_eq!(decode::<Empty>(&[]), Ok(Empty));
+        assert_eq!(decode::<Vec<Empty>>(&[0]), Ok(Vec::new()));
+        assert_eq!(decode::<Vec<Empty>>(&[1]), Err(CallError::MalformedReply));
+
+        let mut r = Reader::new(&[1]);
+        assert_eq!(
+            Vec::<Empty>::decode_rest(&mut r),
+            Err(CallError::MalformedReply)
+        );
+    }
+
+    #[test]
+    fn content_too_long() {
+        let s = "x".repeat(256);
+        assert_eq!(encode(&s), Err(CallError::ContentTooLong));
+        assert_eq!(encode(&vec![0u8; 256]), Err(CallError::ContentTooLong));
+
+        let mut buf = Vec::new();
+        assert_eq!(s.encode_prefixed::<u16>(&mut buf), Ok(()));
+        assert_eq!(buf.len(), 2 + 256);
+
+        let s = "x".repeat(65536);
+        assert_eq!(
+            s.encode_prefixed::<u16>(&mut Vec::new()),
+            Err(CallError::ContentTooLong)
+        );
+        assert_eq!(s.encode_prefixed::<u32>(&mut Vec::new()), Ok(()));
+    }
+}
@@ -4,77 +4,332 @@
 
 //! Buffered I/O streams.
 
-use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::future::Future;
 use std::io;
-use std::num::NonZeroI32;
-use std::rc::Rc;
-use std::task::Waker;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
 
+use futures_core::Stream as FuturesStream;
+
+use crate::core::{self, Deadline};
+
+use crate::stream::receiver::{self, Shared, SharedRc, Sink, Status};
 use crate::stream::{
-    Close, CloseStream, ErrorCode, Recv, RecvOnlyStream, RecvStream, RecvWriteStream, Write,
-    WriteOnlyStream,
+    Close, CloseStream, CloseWrite, ErrorCode, OwnedWrite, RateLimiter, RecvOnlyStream, RecvStream,
+    RecvWriteStream, Write, WriteCredit, WriteOnlyStream, WriteStream,
 };
 
-#[derive(PartialEq)]
-pub(crate) enum BufResult {
-    Pending,
-    Eof,
-    Err(ErrorCode),
-}
-
 /// Read buffer.
+///
+/// Consumed bytes are reclaimed lazily, so consuming is cheap regardless of
+/// how much data remains.  The buffered bytes are always available as a
+/// contiguous slice.
+///
+/// The capacity bounds the amount of data which may be buffered: the peer is
+/// granted flow credit only for the free space.  Consumed space is offered to
+/// the peer again when the reader waits for more data.
 pub struct Buf {
-    pub(crate) data: Vec<u8>,
-    pub(crate) result: BufResult,
-    pub(crate) waker: Option<Waker>,
+    data: Vec<u8>,
+    offset: usize,
+    notes: VecDeque<(usize, i32)>, // End positions are relative to data.
+    capacity: usize,
 }
 
 impl Buf {
-    pub(crate) fn new(result: BufResult) -> Self {
+    pub(crate) fn new(capacity: usize) -> Self {
         Self {
             data: Vec::new(),
-            result,
-            waker: None,
+            offset: 0,
+            notes: VecDeque::new(),
+            capacity,
         }
     }
 
     /// Returns `true` if nothing is buffered.
     pub fn is_empty(&self) -> bool {
-        self.data.is_empty()
+        self.len() == 0
     }
 
     /// Returns the number of buffered bytes.
     pub fn len(&self) -> usize {
-        self.data.len()
+        self.data.len() - self.offset
+    }
+
+    /// Returns the maximum number of bytes which can be buffered.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Increase the capacity so that at least `additional` more bytes can be
+    /// buffered.  The peer is granted the additional flow credit when the
+    /// reader waits for more data.
+    pub fn reserve(&mut self, additional: usize) {
+        self.capacity = std::cmp::max(self.capacity, self.len() + additional);
     }
 
     /// Access the buffered bytes.
     pub fn as_slice(&self) -> &[u8] {
-        self.data.as_slice()
+        &self.data[self.offset..]
+    }
+
+    /// Find the first non-zero note which was received with the buffered
+    /// data.  Returns the length of the data up to the end of the packet
+    /// which carried it, and the note.
+    pub fn note(&self) -> Option<(usize, i32)> {
+        self.notes
+            .front()
+            .map(|&(end, note)| (end - self.offset, note))
     }
 
-    /// Remove bytes from the start of the buffer.
+    /// Remove bytes from the start of the buffer.  Notes of packets which are
+    /// consumed completely are forgotten.
     pub fn consume(&mut self, n: usize) {
-        self.data = self.data.split_off(n);
+        if n > self.len() {
+            panic!("consuming more than is buffered");
+        }
+
+        self.offset += n;
+
+        while let Some(&(end, _)) = self.notes.front() {
+            if end > self.offset {
+                break;
+            }
+            self.notes.pop_front();
+        }
+
+        if self.offset == self.data.len() {
+            self.data.clear();
+            self.offset = 0;
+        }
     }
 
     /// Remove all bytes from the buffer.
     pub fn consume_all(&mut self) {
-        self.data = Vec::new();
+        self.data.clear();
+        self.offset = 0;
+        self.notes.clear();
+    }
+
+    /// Remove bytes from the start of the buffer and return them.  Taking all
+    /// bytes of a buffer which hasn't been partially consumed doesn't copy.
+    pub fn take(&mut self, n: usize) -> Vec<u8> {
+        if n == self.len() && self.offset == 0 {
+            self.notes.clear();
+            return std::mem::take(&mut self.data);
+        }
+
+        let v = self.as_slice()[..n].to_vec();
+        self.consume(n);
+        v
+    }
+
+    pub(crate) fn append(&mut self, data: &mut Vec<u8>, notes: &mut Vec<(usize, i32)>) {
+        if data.is_empty() {
+            return;
+        }
+
+        // Reclaim consumed space when it's at least as large as the remaining
+        // data, so that each byte is moved at most once on average.
+        if self.offset > 0 && self.offset >= self.len() {
+            self.data.drain(..self.offset);
+            for (end, _) in self.notes.iter_mut() {
+                *end -= self.offset;
+            }
+            self.offset = 0;
+        }
+
+        let base = self.data.len();
+        self.notes
+            .extend(notes.drain(..).map(|(end, note)| (base + end, note)));
+
+        if self.data.is_empty() {
+            std::mem::swap(&mut self.data, data);
+        } else {
+            self.data.extend_from_slice(data);
+        }
+        data.clear();
+    }
+
+    /// Unconsumed data and the positions of its notes.
+    #[cfg(any(feature = "deflate", feature = "zstd"))]
+    pub(crate) fn into_parts(mut self) -> (Vec<u8>, VecDeque<(usize, i32)>) {
+        self.data.drain(..self.offset);
+        for (end, _) in self.notes.iter_mut() {
+            *end -= self.offset;
+        }
+        (self.data, self.notes)
+    }
+}
+
+impl AsRef<[u8]> for Buf {
+    fn as_ref(&self) -> &[u8] {
+        self.as_slice()
     }
 }
 
 impl io::Read for Buf {
     fn read(&mut self, mut dest: &mut [u8]) -> io::Result<usize> {
-        let n = io::Write::write(&mut dest, self.data.as_slice())?;
+        let n = io::Write::write(&mut dest, self.as_slice())?;
         if n > 0 {
-            self.data = self.data.split_off(n);
+            self.consume(n);
         }
         Ok(n)
     }
 }
 
-pub(crate) type SharedBuf = Rc<RefCell<Buf>>;
+/// Data received in the background, and the notes of its packets.
+#[derive(Default)]
+pub(crate) struct Received {
+    data: Vec<u8>,
+    notes: Vec<(usize, i32)>, // End positions are relative to data.
+}
+
+impl Sink for Received {
+    fn push(&mut self, data: &[u8], note: i32) {
+        self.data.extend_from_slice(data);
+        if note != 0 {
+            self.notes.push((self.data.len(), note));
+        }
+    }
+}
+
+/// Buffer owned by a reader, and the background receiver's shared state.
+pub(crate) struct Reader {
+    pub(crate) buf: Buf,
+    pub(crate) shared: SharedRc<Received>,
+    held: usize,    // Buffer length after the previous sync.
+    granted: usize, // Buffer capacity after the previous sync.
+    timeout: Option<Duration>,
+    deadline: Option<Deadline>,
+    polling: bool, // A poll-based operation is pending.
+}
+
+impl Reader {
+    pub(crate) fn new(shared: SharedRc<Received>, capacity: usize) -> Self {
+        Self {
+            buf: Buf::new(capacity),
+            shared,
+            held: 0,
+            granted: capacity,
+            timeout: None,
+            deadline: None,
+            polling: false,
+        }
+    }
+
+    pub(crate) fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+        self.deadline = None;
+    }
+
+    /// Discard the deadline of an abandoned operation.
+    pub(crate) fn restart_timeout(&mut self) {
+        self.deadline = None;
+        self.polling = false;
+    }
+
+    /// Called at the start of each poll of a poll-based operation (such as
+    /// `AsyncRead::poll_read`), which has no future to mark its start.  The
+    /// deadline is restarted unless the previous poll left the operation
+    /// pending.
+    pub(crate) fn begin_poll(&mut self) {
+        if !self.polling {
+            self.deadline = None;
+            self.polling = true;
+        }
+    }
+
+    /// Called when a poll-based operation is ready.
+    pub(crate) fn end_poll(&mut self) {
+        self.polling = false;
+    }
+
+    /// Convert pending result to an error if the read timeout has expired.
+    fn check_timeout(
+        &mut self,
+        result: Poll<io::Result<bool>>,
+        cx: &mut Context,
+    ) -> Poll<io::Result<bool>> {
+        if result.is_pending() {
+            if let Some(timeout) = self.timeout {
+                let deadline = self
+                    .deadline
+                    .get_or_insert_with(|| Deadline::after(timeout));
+
+                if deadline.poll(cx).is_ready() {
+                    self.deadline = None;
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "stream read timed out",
+                    )));
+                }
+            }
+        } else {
+            self.deadline = None;
+        }
+
+        result
+    }
+
+    /// Move received data into the buffer, and release the space which has
+    /// been consumed (or reserved) since the previous sync.
+    fn sync(&mut self, shared: &mut Shared<Received>) {
+        let released = (self.held - self.buf.len()) + (self.buf.capacity - self.granted);
+        let received = &mut shared.sink;
+        self.buf.append(&mut received.data, &mut received.notes);
+        self.held = self.buf.len();
+        self.granted = self.buf.capacity;
+        shared.release(released);
+    }
+
+    /// Wait until at least min_read bytes are buffered, or the stream has
+    /// ended.  The result is false if the stream has ended and nothing is
+    /// buffered.
+    pub(crate) fn poll_buf(&mut self, cx: &mut Context, min_read: usize) -> Poll<io::Result<bool>> {
+        if min_read > self.buf.capacity {
+            self.buf.reserve(min_read - self.buf.len());
+        }
+
+        let rc = self.shared.clone();
+        let mut shared = rc.borrow_mut();
+        let shared = &mut *shared;
+        self.sync(shared);
+
+        let min_read = if shared.status != Status::Receiving {
+            1
+        } else {
+            min_read
+        };
+
+        if self.buf.len() >= min_read {
+            self.deadline = None;
+            return Poll::Ready(Ok(true));
+        }
+
+        let result = shared.poll_status(cx);
+        self.check_timeout(result, cx)
+    }
+
+    /// Wait until more data is buffered than before the call.  The result is
+    /// false if the stream has ended.
+    pub(crate) fn poll_more(&mut self, cx: &mut Context) -> Poll<io::Result<bool>> {
+        let rc = self.shared.clone();
+        let mut shared = rc.borrow_mut();
+        let shared = &mut *shared;
+
+        let more = !shared.sink.data.is_empty();
+        self.sync(shared);
+        if more {
+            self.deadline = None;
+            return Poll::Ready(Ok(true));
+        }
+
+        let result = shared.poll_status(cx);
+        self.check_timeout(result, cx)
+    }
+}
 
 /// Buffered data reader.
 pub trait Read {
@@ -84,7 +339,7 @@ pub trait Read {
     /// Read buffered data.  Returns a future.
     ///
     /// The receptor must be prepared to handle as much data as the buffer can
-    /// hold.
+    /// hold.  The buffer capacity is increased if `min_read` exceeds it.
     ///
     /// The value returned by the receptor is passed through.  If the stream
     /// has been closed, the default value is returned.
@@ -92,6 +347,231 @@ pub trait Read {
     where
         R: FnOnce(&mut Buf) -> T + Unpin,
         T: Default;
+
+    /// Poll buffered data as part of an ongoing operation.  The receptor is
+    /// invoked if data is available; `None` means that the stream has ended.
+    ///
+    /// [`ReadExt`] futures are built on this.  Unlike [`buf_read`](Self::buf_read),
+    /// this doesn't restart the read timeout.  The default implementation
+    /// polls a `buf_read` future.
+    fn poll_buf_read<R, T>(&mut self, cx: &mut Context, receptor: R) -> Poll<io::Result<Option<T>>>
+    where
+        R: FnOnce(&mut Buf) -> T + Unpin,
+    {
+        let mut f = self.buf_read(1, move |buf: &mut Buf| Some(receptor(buf)));
+        Pin::new(&mut f).poll(cx)
+    }
+
+    /// Discard the read deadline of an abandoned operation.  Called when a
+    /// [`ReadExt`] operation starts.  The default implementation does
+    /// nothing.
+    fn restart_read_timeout(&mut self) {}
+}
+
+/// Extension methods for buffered data readers.
+///
+/// Implemented for all [`Read`] implementations.
+pub trait ReadExt: Read {
+    /// Fill a slice completely.  Returns a future.
+    ///
+    /// Fails with `ErrorKind::UnexpectedEof` if the stream ends first.
+    fn read_exact<'a>(&'a mut self, dest: &'a mut [u8]) -> future::ReadExact<'a, Self>
+    where
+        Self: Sized,
+    {
+        self.restart_read_timeout();
+        future::ReadExact {
+            stream: self,
+            dest,
+            filled: 0,
+        }
+    }
+
+    /// Read until the end of the stream, appending to a vector.  Returns a
+    /// future which yields the number of bytes read.
+    ///
+    /// Fails with `ErrorKind::InvalidData` if the stream contains more than
+    /// `limit` bytes.
+    fn read_to_end<'a>(
+        &'a mut self,
+        dest: &'a mut Vec<u8>,
+        limit: usize,
+    ) -> future::ReadToEnd<'a, Self>
+    where
+        Self: Sized,
+    {
+        self.restart_read_timeout();
+        future::ReadToEnd {
+            stream: self,
+            dest,
+            limit,
+            count: 0,
+        }
+    }
+
+    /// Read until a delimiter byte or the end of the stream, appending to a
+    /// vector.  The delimiter is included.  Returns a future which yields the
+    /// number of bytes read; zero means that the stream has ended.
+    fn read_until<'a>(&'a mut self, delim: u8, dest: &'a mut Vec<u8>) -> future::ReadUntil<'a, Self>
+    where
+        Self: Sized,
+    {
+        self.restart_read_timeout();
+        future::ReadUntil {
+            stream: self,
+            delim,
+            dest,
+            count: 0,
+        }
+    }
+
+    /// Read a UTF-8 line, appending to a string.  The newline character is
+    /// included (unless the stream ends without one).  Returns a future which
+    /// yields the number of bytes read; zero means that the stream has ended.
+    ///
+    /// Fails with `ErrorKind::InvalidData` if the line is longer than
+    /// `max_len` bytes (including the newline), or if it's not valid UTF-8.
+    /// The line is discarded in that case (an overlong line is consumed up to
+    /// and including the newline), and the next read starts at the next line.
+    fn read_line<'a>(
+        &'a mut self,
+        dest: &'a mut String,
+        max_len: usize,
+    ) -> future::ReadLine<'a, Self>
+    where
+        Self: Sized,
+    {
+        self.restart_read_timeout();
+        future::ReadLine {
+            stream: self,
+            dest,
+            max_len,
+            line: Vec::new(),
+            count: 0,
+        }
+    }
+
+    /// Stream of UTF-8 lines without line terminators ("\n" or "\r\n").
+    /// Line length is limited like with `read_line`.  The stream ends after
+    /// the first error.
+    fn lines(&mut self, max_len: usize) -> Lines<'_, Self>
+    where
+        Self: Sized,
+    {
+        self.restart_read_timeout();
+        Lines {
+            stream: self,
+            max_len,
+            line: Vec::new(),
+            count: 0,
+            done: false,
+        }
+    }
+}
+
+impl<T: Read> ReadExt for T {}
+
+/// Append data up to and including a delimiter to a vector.  The result is
+/// false if the stream ended before the delimiter was found.
+///
+/// If the data exceeds `limit`, it is discarded up to and including the
+/// delimiter before failing, so that reading can resume at the next line.
+/// `count` exceeds `limit` while the data is being discarded.
+fn poll_until<S: Read>(
+    stream: &mut S,
+    cx: &mut Context,
+    delim: u8,
+    dest: &mut Vec<u8>,
+    limit: usize,
+    count: &mut usize,
+) -> Poll<io::Result<bool>> {
+    loop {
+        let result = stream.poll_buf_read(cx, |buf: &mut Buf| {
+            let data = buf.as_slice();
+            let (n, found) = match data.iter().position(|&b| b == delim) {
+                Some(i) => (i + 1, true),
+                None => (data.len(), false),
+            };
+
+            if *count > limit || *count + n > limit {
+                buf.consume(n);
+                *count = limit.saturating_add(1);
+                return if found {
+                    Err(line_too_long())
+                } else {
+                    Ok(false)
+                };
+            }
+
+            dest.extend_from_slice(&data[..n]);
+            buf.consume(n);
+            *count += n;
+            Ok(found)
+        });
+
+        match result {
+            Poll::Ready(Ok(Some(Ok(true)))) => return Poll::Ready(Ok(true)),
+            Poll::Ready(Ok(Some(Ok(false)))) => {}
+            Poll::Ready(Ok(Some(Err(e)))) => return Poll::Ready(Err(e)),
+            Poll::Ready(Ok(None)) if *count > limit => return Poll::Ready(Err(line_too_long())),
+            Poll::Ready(Ok(None)) => return Poll::Ready(Ok(false)),
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => return Poll::Pending,
+        }
+    }
+}
+
+fn line_too_long() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "line too long")
+}
+
+/// Stream of lines.  See [`ReadExt::lines`].
+pub struct Lines<'a, S> {
+    stream: &'a mut S,
+    max_len: usize,
+    line: Vec<u8>,
+    count: usize,
+    done: bool,
+}
+
+impl<S: Read> FuturesStream for Lines<'_, S> {
+    type Item = io::Result<String>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let m = self.get_mut();
+        if m.done {
+            return Poll::Ready(None);
+        }
+
+        let result = poll_until(m.stream, cx, b'\n', &mut m.line, m.max_len, &mut m.count);
+        let found = match result {
+            Poll::Ready(Ok(found)) => found,
+            Poll::Ready(Err(e)) => {
+                m.done = true;
+                return Poll::Ready(Some(Err(e)));
+            }
+            Poll::Pending => return Poll::Pending,
+        };
+
+        let mut line = std::mem::take(&mut m.line);
+        m.count = 0;
+        if !found {
+            m.done = true;
+            if line.is_empty() {
+                return Poll::Ready(None);
+            }
+        } else {
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+        }
+
+        Poll::Ready(Some(String::from_utf8(line).map_err(|e| {
+            m.done = true;
+            io::Error::new(io::ErrorKind::InvalidData, e)
+        })))
+    }
 }
 
 pub mod future {
@@ -100,12 +580,12 @@ pub mod future {
     use std::pin::Pin;
     use std::task::{Context, Poll};
 
-    use super::{Buf, BufResult, SharedBuf};
+    use super::{poll_until, Buf, Reader};
 
     /// Asynchronous read.
     #[must_use = "futures do nothing unless you `.await` or poll them"]
     pub struct Read<'a> {
-        pub(crate) shared: &'a mut SharedBuf,
+        pub(crate) reader: &'a mut Reader,
         pub(crate) dest: &'a mut [u8],
     }
 
@@ -114,19 +594,12 @@ pub mod future {
 
         fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
             let m = self.get_mut();
-            let mut buf = m.shared.borrow_mut();
-
-            if !buf.data.is_empty() {
-                Poll::Ready(io::Read::read(&mut *buf, m.dest))
-            } else {
-                match buf.result {
-                    BufResult::Pending => {
-                        buf.waker = Some(cx.waker().clone());
-                        Poll::Pending
-                    }
-                    BufResult::Eof => Poll::Ready(Ok(0)),
-                    BufResult::Err(e) => Poll::Ready(Err(io::Error::new(io::ErrorKind::Other, e))),
-                }
+
+            match m.reader.poll_buf(cx, 1) {
+                Poll::Ready(Ok(true)) => Poll::Ready(io::Read::read(&mut m.reader.buf, m.dest)),
+                Poll::Ready(Ok(false)) => Poll::Ready(Ok(0)),
+                Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+                Poll::Pending => Poll::Pending,
             }
         }
     }
@@ -138,7 +611,7 @@ pub mod future {
         R: FnOnce(&mut Buf) -> T + Unpin,
         T: Default,
     {
-        pub(crate) shared: &'a mut SharedBuf,
+        pub(crate) reader: &'a mut Reader,
         pub(crate) min_read: usize,
         pub(crate) receptor: Option<R>,
     }
@@ -151,51 +624,149 @@ pub mod future {
         type Output = io::Result<T>;
 
         fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
-            let mut min_read = self.min_read;
+            let m = self.get_mut();
+
+            match m.reader.poll_buf(cx, m.min_read) {
+                Poll::Ready(Ok(true)) => {
+                    Poll::Ready(Ok((m.receptor.take().unwrap())(&mut m.reader.buf)))
+                }
+                Poll::Ready(Ok(false)) => Poll::Ready(Ok(Default::default())),
+                Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+                Poll::Pending => Poll::Pending,
+            }
+        }
+    }
 
+    /// Asynchronous exact read.
+    #[must_use = "futures do nothing unless you `.await` or poll them"]
+    pub struct ReadExact<'a, S> {
+        pub(crate) stream: &'a mut S,
+        pub(crate) dest: &'a mut [u8],
+        pub(crate) filled: usize,
+    }
+
+    impl<S: super::Read> Future for ReadExact<'_, S> {
+        type Output = io::Result<()>;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
             let m = self.get_mut();
-            let mut buf = m.shared.borrow_mut();
 
-            if buf.result != BufResult::Pending {
-                min_read = 1;
+            while m.filled < m.dest.len() {
+                let dest = &mut m.dest[m.filled..];
+                match m
+                    .stream
+                    .poll_buf_read(cx, |buf: &mut Buf| io::Read::read(buf, dest))
+                {
+                    Poll::Ready(Ok(Some(n))) => m.filled += n?,
+                    Poll::Ready(Ok(None)) => {
+                        return Poll::Ready(Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "stream ended before buffer was filled",
+                        )))
+                    }
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    Poll::Pending => return Poll::Pending,
+                }
             }
 
-            if buf.len() >= min_read {
-                Poll::Ready(Ok((m.receptor.take().unwrap())(&mut buf)))
-            } else {
-                match buf.result {
-                    BufResult::Pending => {
-                        buf.waker = Some(cx.waker().clone());
-                        Poll::Pending
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// Asynchronous read until end of stream.
+    #[must_use = "futures do nothing unless you `.await` or poll them"]
+    pub struct ReadToEnd<'a, S> {
+        pub(crate) stream: &'a mut S,
+        pub(crate) dest: &'a mut Vec<u8>,
+        pub(crate) limit: usize,
+        pub(crate) count: usize,
+    }
+
+    impl<S: super::Read> Future for ReadToEnd<'_, S> {
+        type Output = io::Result<usize>;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+            let m = self.get_mut();
+
+            loop {
+                let (dest, limit, count) = (&mut *m.dest, m.limit, &mut m.count);
+                let result = m.stream.poll_buf_read(cx, |buf: &mut Buf| {
+                    let data = buf.as_slice();
+                    if *count + data.len() > limit {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "stream data exceeds limit",
+                        ));
                     }
-                    BufResult::Eof => Poll::Ready(Ok(Default::default())),
-                    BufResult::Err(e) => Poll::Ready(Err(io::Error::new(io::ErrorKind::Other, e))),
+
+                    dest.extend_from_slice(data);
+                    *count += data.len();
+                    buf.consume_all();
+                    Ok(())
+                });
+
+                match result {
+                    Poll::Ready(Ok(Some(Ok(())))) => {}
+                    Poll::Ready(Ok(Some(Err(e)))) => return Poll::Ready(Err(e)),
+                    Poll::Ready(Ok(None)) => return Poll::Ready(Ok(m.count)),
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    Poll::Pending => return Poll::Pending,
                 }
             }
         }
     }
-}
 
-async fn receive(shared: SharedBuf, mut stream: RecvOnlyStream, capacity: usize) {
-    let note = stream
-        .recv(capacity, |src: &[u8], _: i32| {
-            let mut buf = shared.borrow_mut();
-            buf.data.extend_from_slice(src);
-            if let Some(w) = buf.waker.take() {
-                w.wake();
+    /// Asynchronous read until delimiter.
+    #[must_use = "futures do nothing unless you `.await` or poll them"]
+    pub struct ReadUntil<'a, S> {
+        pub(crate) stream: &'a mut S,
+        pub(crate) delim: u8,
+        pub(crate) dest: &'a mut Vec<u8>,
+        pub(crate) count: usize,
+    }
+
+    impl<S: super::Read> Future for ReadUntil<'_, S> {
+        type Output = io::Result<usize>;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+            let m = self.get_mut();
+
+            match poll_until(m.stream, cx, m.delim, m.dest, usize::MAX, &mut m.count) {
+                Poll::Ready(Ok(_)) => Poll::Ready(Ok(m.count)),
+                Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+                Poll::Pending => Poll::Pending,
             }
-            src.len()
-        })
-        .await
-        .unwrap();
+        }
+    }
+
+    /// Asynchronous line read.
+    #[must_use = "futures do nothing unless you `.await` or poll them"]
+    pub struct ReadLine<'a, S> {
+        pub(crate) stream: &'a mut S,
+        pub(crate) dest: &'a mut String,
+        pub(crate) max_len: usize,
+        pub(crate) line: Vec<u8>,
+        pub(crate) count: usize,
+    }
+
+    impl<S: super::Read> Future for ReadLine<'_, S> {
+        type Output = io::Result<usize>;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+            let m = self.get_mut();
 
-    let mut buf = shared.borrow_mut();
-    buf.result = match NonZeroI32::new(note) {
-        None => BufResult::Eof,
-        Some(n) => BufResult::Err(ErrorCode(n)),
-    };
-    if let Some(w) = buf.waker.take() {
-        w.wake();
+            match poll_until(m.stream, cx, b'\n', &mut m.line, m.max_len, &mut m.count) {
+                Poll::Ready(Ok(_)) => match std::str::from_utf8(&m.line) {
+                    Ok(s) => {
+                        m.dest.push_str(s);
+                        Poll::Ready(Ok(m.count))
+                    }
+                    Err(e) => Poll::Ready(Err(io::Error::new(io::ErrorKind::InvalidData, e))),
+                },
+                Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+                Poll::Pending => Poll::Pending,
+            }
+        }
     }
 }
 
@@ -204,8 +775,8 @@ pub const DEFAULT_READ_CAPACITY: usize = 8192;
 
 /// Buffered input stream.
 pub struct ReadStream {
-    shared: SharedBuf,
-    closer: CloseStream,
+    pub(crate) r: Reader,
+    pub(crate) closer: CloseStream,
 }
 
 impl ReadStream {
@@ -221,17 +792,38 @@ impl ReadStream {
         Self::with_custom_closer(capacity, receiver, closer)
     }
 
+    /// Limit the time to wait for data.  A read which times out fails with
+    /// `ErrorKind::TimedOut`; the stream remains usable.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.r.set_timeout(timeout)
+    }
+
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.r.timeout
+    }
+
+    /// Close the stream with an error code.  A negative code is conveyed to
+    /// the peer's writes.  Returns a future.
+    pub fn abort(&mut self, code: ErrorCode) -> super::future::Close {
+        self.closer.abort(code)
+    }
+
     fn with_custom_closer(capacity: usize, receiver: RecvOnlyStream, closer: CloseStream) -> Self {
-        let shared: SharedBuf = Rc::new(RefCell::new(Buf::new(BufResult::Pending)));
-        crate::task::spawn_local(receive(shared.clone(), receiver, capacity));
-        Self { shared, closer }
+        let shared = Shared::new(Received::default(), Status::Receiving);
+        receiver::spawn(&shared, receiver, capacity);
+        Self {
+            r: Reader::new(shared, capacity),
+            closer,
+        }
     }
 }
 
+impl_peer_state!(ReadStream, this => &this.closer.s);
+
 impl Default for ReadStream {
     fn default() -> Self {
         Self {
-            shared: Rc::new(RefCell::new(Buf::new(BufResult::Eof))),
+            r: Reader::new(Shared::new(Received::default(), Status::Eof), 0),
             closer: Default::default(),
         }
     }
@@ -245,8 +837,9 @@ impl From<RecvStream> for ReadStream {
 
 impl Read for ReadStream {
     fn read<'a>(&'a mut self, dest: &'a mut [u8]) -> future::Read {
+        self.r.restart_timeout();
         future::Read {
-            shared: &mut self.shared,
+            reader: &mut self.r,
             dest,
         }
     }
@@ -260,12 +853,29 @@ impl Read for ReadStream {
             panic!("minimum read length is zero");
         }
 
+        self.r.restart_timeout();
         future::BufRead {
-            shared: &mut self.shared,
+            reader: &mut self.r,
             min_read,
             receptor: Some(receptor),
         }
     }
+
+    fn poll_buf_read<R, T>(&mut self, cx: &mut Context, receptor: R) -> Poll<io::Result<Option<T>>>
+    where
+        R: FnOnce(&mut Buf) -> T + Unpin,
+    {
+        match self.r.poll_buf(cx, 1) {
+            Poll::Ready(Ok(true)) => Poll::Ready(Ok(Some(receptor(&mut self.r.buf)))),
+            Poll::Ready(Ok(false)) => Poll::Ready(Ok(None)),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn restart_read_timeout(&mut self) {
+        self.r.restart_timeout();
+    }
 }
 
 impl Close for ReadStream {
@@ -277,8 +887,8 @@ impl Close for ReadStream {
 /// Bidirectional stream with input buffering.
 #[derive(Default)]
 pub struct ReadWriteStream {
-    r: ReadStream,
-    w: WriteOnlyStream,
+    pub(crate) r: ReadStream,
+    pub(crate) w: WriteOnlyStream,
 }
 
 impl ReadWriteStream {
@@ -296,8 +906,41 @@ impl ReadWriteStream {
             w: writer,
         }
     }
+
+    /// Limit the time to wait for data.  A read which times out fails with
+    /// `ErrorKind::TimedOut`; the stream remains usable.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.r.set_read_timeout(timeout)
+    }
+
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.r.read_timeout()
+    }
+
+    /// Limit the time to wait for flow credit.  A write which times out fails
+    /// with `ErrorKind::TimedOut`.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        core::set_write_timeout(&self.w.s, timeout)
+    }
+
+    pub fn write_timeout(&self) -> Option<Duration> {
+        core::write_timeout(&self.w.s)
+    }
+
+    /// Delay writes according to a rate limiter.  The limiter of the
+    /// stream's service (if any) applies in addition to it.
+    pub fn set_rate_limiter(&mut self, limiter: Option<RateLimiter>) {
+        core::set_rate_limiter(&self.w.s, limiter)
+    }
+
+    pub fn rate_limiter(&self) -> Option<RateLimiter> {
+        core::rate_limiter(&self.w.s)
+    }
 }
 
+// The write half is released by close_write; the closer lives until close.
+impl_peer_state!(ReadWriteStream, this => &this.r.closer.s);
+
 impl From<RecvWriteStream> for ReadWriteStream {
     fn from(stream: RecvWriteStream) -> Self {
         Self::new(stream)
@@ -316,6 +959,17 @@ impl Read for ReadWriteStream {
     {
         self.r.buf_read(min_read, receptor)
     }
+
+    fn poll_buf_read<R, T>(&mut self, cx: &mut Context, receptor: R) -> Poll<io::Result<Option<T>>>
+    where
+        R: FnOnce(&mut Buf) -> T + Unpin,
+    {
+        self.r.poll_buf_read(cx, receptor)
+    }
+
+    fn restart_read_timeout(&mut self) {
+        self.r.restart_read_timeout()
+    }
 }
 
 impl Write for ReadWriteStream {
@@ -332,8 +986,444 @@ impl Write for ReadWriteStream {
     }
 }
 
+impl OwnedWrite for ReadWriteStream {
+    fn write_owned<B>(&mut self, data: B) -> super::future::WriteOwned<B>
+    where
+        B: AsRef<[u8]> + 'static,
+    {
+        self.w.write_owned(data)
+    }
+}
+
+impl WriteCredit for ReadWriteStream {
+    fn writable_credit(&self) -> usize {
+        core::writable_credit(&self.w.s)
+    }
+}
+
+impl CloseWrite for ReadWriteStream {
+    fn close_write(&mut self, note: i32) -> super::future::Close {
+        self.w.close_write(note)
+    }
+}
+
 impl Close for ReadWriteStream {
     fn close(&mut self) -> super::future::Close {
         self.r.close()
     }
 }
+
+/// Buffer size used by `BufWriter::new`.
+pub const DEFAULT_WRITE_CAPACITY: usize = 8192;
+
+/// Buffered output stream.
+///
+/// Small writes are coalesced into larger data packets.  The buffer holds at
+/// most as much data as the peer has granted flow credit for (or the buffer
+/// capacity, if it's smaller), so buffered data can always be sent without
+/// waiting.  When there is no room, the buffer is flushed; when there is no
+/// credit, writes wait for it.  Larger writes bypass the buffer.
+///
+/// The writer implements [`Write`], [`CloseWrite`] and [`Close`]; closing
+/// writes the buffered data first.  It should be flushed or closed
+/// explicitly; buffered data is discarded if it's dropped.
+pub struct BufWriter<W = WriteStream> {
+    inner: W,
+    buf: Vec<u8>,
+    capacity: usize,
+}
+
+impl<W: Write + WriteCredit> BufWriter<W> {
+    /// Buffer writes to a stream.
+    pub fn new(inner: W) -> Self {
+        Self::with_capacity(DEFAULT_WRITE_CAPACITY, inner)
+    }
+
+    /// Buffer writes to a stream using a custom buffer size.
+    pub fn with_capacity(capacity: usize, inner: W) -> Self {
+        Self {
+            inner,
+            buf: Vec::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The data which hasn't been written to the stream yet.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Get a reference to the underlying stream.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the underlying stream.  Writing to it
+    /// directly reorders data with respect to buffered data.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Unwrap the underlying stream.  Buffered data is discarded.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    // Buffered data counts against the limit, as it hasn't consumed credit
+    // yet.
+    fn limit(&self) -> usize {
+        std::cmp::min(self.capacity, self.inner.writable_credit())
+    }
+
+    /// Write part of a byte slice.  Buffered data is flushed first if there
+    /// isn't room for more.
+    pub async fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        if self.buf.len() + data.len() > self.limit() {
+            self.flush().await?;
+        }
+
+        // Without enough credit the write waits for it.
+        let limit = self.limit();
+        if self.buf.is_empty() && data.len() >= limit {
+            return self.inner.write(data).await;
+        }
+
+        let n = std::cmp::min(data.len(), limit - self.buf.len());
+        self.buf.extend_from_slice(&data[..n]);
+        Ok(n)
+    }
+
+    /// Write a whole byte slice.
+    pub async fn write_all(&mut self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            match self.write(data).await? {
+                0 => return Err(io::ErrorKind::WriteZero.into()),
+                n => data = &data[n..],
+            }
+        }
+        Ok(())
+    }
+
+    /// Write a whole byte slice and flush.  The note is attached to the last
+    /// packet.
+    pub async fn write_note(&mut self, data: &[u8], note: i32) -> io::Result<()> {
+        if self.buf.len() + data.len() > self.limit() {
+            self.flush().await?;
+        }
+
+        if data.len() > self.limit() {
+            return super::write_all_note(&mut self.inner, data, note).await;
+        }
+
+        self.buf.extend_from_slice(data);
+        self.flush_note(note).await
+    }
+
+    /// Write all buffered data to the stream.
+    pub async fn flush(&mut self) -> io::Result<()> {
+        self.flush_note(0).await
+    }
+
+    // Written data is removed from the buffer even if a later write fails.
+    // The last byte is held back so that the note isn't attached to a
+    // partial write.
+    async fn flush_note(&mut self, note: i32) -> io::Result<()> {
+        let mut offset = 0;
+        let result = loop {
+            let rest = &self.buf[offset..];
+            let n = match rest.len() {
+                0 => break Ok(()),
+                n if note == 0 || n == 1 => n,
+                n => n - 1,
+            };
+            let note = if n == rest.len() { note } else { 0 };
+
+            match self.inner.write_note(&rest[..n], note).await {
+                Ok(0) => break Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => offset += n,
+                Err(e) => break Err(e),
+            }
+        };
+
+        self.buf.drain(..offset);
+        result
+    }
+}
+
+impl<W: Write + WriteCredit + OwnedWrite> BufWriter<W> {
+    // The buffered data is written by an owned future which is created before
+    // the closing future, so that it's sent before the stream is detached.
+    fn close_after_flush(
+        &mut self,
+        close: impl FnOnce(&mut W) -> super::future::Close,
+    ) -> super::future::Close {
+        let write = match std::mem::take(&mut self.buf) {
+            data if data.is_empty() => None,
+            data => Some(self.inner.write_owned(data)),
+        };
+        let close = close(&mut self.inner);
+
+        super::future::Close::boxed(Box::pin(async move {
+            if let Some(write) = write {
+                let _ = write.await;
+            }
+            close.await
+        }))
+    }
+}
+
+impl<W: Write + WriteCredit + CloseWrite> BufWriter<W> {
+    /// Flush and close the output direction of the underlying stream.  The
+    /// stream is closed even if flushing fails.
+    pub async fn close_write(&mut self) -> io::Result<()> {
+        let result = self.flush().await;
+        self.inner.close_write(0).await;
+        result
+    }
+
+    /// Close the output direction of the underlying stream with an error
+    /// code.  Buffered data is discarded.
+    pub async fn abort(&mut self, code: ErrorCode) {
+        self.buf.clear();
+        self.inner.abort(code).await;
+    }
+}
+
+impl<W: Write + WriteCredit + Close> BufWriter<W> {
+    /// Flush and close the underlying stream.  The stream is closed even if
+    /// flushing fails.
+    pub async fn close(&mut self) -> io::Result<()> {
+        let result = self.flush().await;
+        self.inner.close().await;
+        result
+    }
+}
+
+impl<W: Write + WriteCredit> Write for BufWriter<W> {
+    fn write<'a>(&'a mut self, data: &'a [u8]) -> super::future::Write<'a> {
+        super::future::Write::boxed(Box::pin(self.write(data)))
+    }
+
+    /// Write and flush a whole byte slice.
+    fn write_note<'a>(&'a mut self, data: &'a [u8], note: i32) -> super::future::Write<'a> {
+        super::future::Write::boxed(Box::pin(async move {
+            self.write_note(data, note).await.map(|()| data.len())
+        }))
+    }
+
+    fn write_all<'a>(&'a mut self, data: &'a [u8]) -> super::future::WriteAll<'a> {
+        super::future::WriteAll::boxed(Box::pin(self.write_all(data)))
+    }
+}
+
+impl<W: Write + WriteCredit + OwnedWrite + CloseWrite> CloseWrite for BufWriter<W> {
+    fn close_write(&mut self, note: i32) -> super::future::Close {
+        self.close_after_flush(|inner| inner.close_write(note))
+    }
+
+    /// Buffered data is discarded.
+    fn abort(&mut self, code: ErrorCode) -> super::future::Close {
+        self.buf.clear();
+        self.inner.abort(code)
+    }
+}
+
+impl<W: Write + WriteCredit + OwnedWrite + Close> Close for BufWriter<W> {
+    fn close(&mut self) -> super::future::Close {
+        self.close_after_flush(W::close)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use futures_util::future::join;
+    use futures_util::StreamExt;
+
+    use super::*;
+    use crate::stream::{duplex, CloseWrite, DynClose, DynWrite};
+    use crate::task::test_block_on;
+
+    async fn send(mut stream: ReadWriteStream, data: &[u8]) {
+        stream.write_all(data).await.unwrap();
+        stream.close_write(0).await;
+    }
+
+    #[test]
+    fn read_line_skips_overlong_line() {
+        test_block_on(async {
+            let (a, mut b) = duplex(16);
+
+            let receive = async {
+                let mut line = String::new();
+                let e = b.read_line(&mut line, 8).await.unwrap_err();
+                assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+                assert_eq!(line, "");
+
+                assert_eq!(b.read_line(&mut line, 8).await.unwrap(), 3);
+                assert_eq!(line, "ok\n");
+                assert_eq!(b.read_line(&mut line, 8).await.unwrap(), 0);
+            };
+
+            join(send(a, b"this line is too long\nok\n"), receive).await;
+        })
+    }
+
+    #[test]
+    fn read_line_overlong_at_eof() {
+        test_block_on(async {
+            let (a, mut b) = duplex(4);
+
+            let receive = async {
+                let mut line = String::new();
+                let e = b.read_line(&mut line, 8).await.unwrap_err();
+                assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+                assert_eq!(b.read_line(&mut line, 8).await.unwrap(), 0);
+            };
+
+            join(send(a, b"no newline at all"), receive).await;
+        })
+    }
+
+    #[test]
+    fn lines_resume_after_overlong_line() {
+        test_block_on(async {
+            let (a, mut b) = duplex(4);
+
+            let receive = async {
+                let mut lines = b.lines(8);
+                assert_eq!(lines.next().await.unwrap().unwrap(), "one");
+                let e = lines.next().await.unwrap().unwrap_err();
+                assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+                assert!(lines.next().await.is_none());
+
+                let rest: Vec<String> = b.lines(8).map(Result::unwrap).collect().await;
+                assert_eq!(rest, vec!["two"]);
+            };
+
+            join(send(a, b"one\r\noverlong line\ntwo"), receive).await;
+        })
+    }
+
+    async fn receive(mut stream: ReadWriteStream) -> Vec<u8> {
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data, usize::MAX).await.unwrap();
+        data
+    }
+
+    #[test]
+    fn buf_writer_coalesces_writes() {
+        test_block_on(async {
+            let (a, b) = duplex(64);
+
+            let send = async {
+                let mut w = BufWriter::new(a);
+
+                // There is no credit before the peer starts receiving, so the
+                // first write bypasses the buffer and waits for it.
+                w.write_all(b"0").await.unwrap();
+                assert!(w.buffer().is_empty());
+
+                w.write_all(b"abc").await.unwrap();
+                w.write_all(b"def").await.unwrap();
+                assert_eq!(w.buffer(), b"abcdef");
+                w.close_write().await.unwrap();
+            };
+
+            let (_, data) = join(send, receive(b)).await;
+            assert_eq!(data, b"0abcdef");
+        })
+    }
+
+    #[test]
+    fn buf_writer_is_capped_at_credit() {
+        let data: Vec<u8> = (0..100).collect();
+
+        test_block_on(async {
+            let (a, b) = duplex(8);
+
+            let send = async {
+                let mut w = BufWriter::with_capacity(64, a);
+                for chunk in data.chunks(3) {
+                    w.write_all(chunk).await.unwrap();
+                    assert!(w.buffer().len() <= w.get_ref().writable_credit());
+                }
+                w.close_write().await.unwrap();
+            };
+
+            let (_, received) = join(send, receive(b)).await;
+            assert_eq!(received, data);
+        })
+    }
+
+    #[test]
+    fn buf_writer_note_is_attached_once() {
+        test_block_on(async {
+            let (a, mut b) = duplex(4);
+
+            let send = async {
+                let mut w = BufWriter::with_capacity(64, a);
+                w.write_all(b"01").await.unwrap();
+                w.write_note(b"23456789", 7).await.unwrap();
+                assert!(w.buffer().is_empty());
+            };
+
+            let receive = async {
+                let mut data = Vec::new();
+                let mut notes = Vec::new();
+                while data.len() < 10 {
+                    b.buf_read(1, |buf: &mut Buf| {
+                        if let Some((offset, note)) = buf.note() {
+                            notes.push((data.len() + offset, note));
+                        }
+                        data.extend_from_slice(buf.as_slice());
+                        buf.consume_all();
+                    })
+                    .await
+                    .unwrap();
+                }
+                (data, notes)
+            };
+
+            let (_, (data, notes)) = join(send, receive).await;
+            assert_eq!(data, b"0123456789");
+            assert_eq!(notes, vec![(10, 7)]);
+        })
+    }
+
+    #[test]
+    fn buf_writer_close_flushes() {
+        test_block_on(async {
+            let (a, b) = duplex(64);
+            let mut w = BufWriter::new(a);
+
+            let send = async {
+                Write::write_all(&mut w, b"abc").await.unwrap();
+                CloseWrite::close_write(&mut w, 0).await;
+            };
+
+            let (_, data) = join(send, receive(b)).await;
+            assert_eq!(data, b"abc");
+
+            let (a, b) = duplex(64);
+            let mut w = BufWriter::new(a);
+
+            let send = async {
+                w.dyn_write_all(b"def").await.unwrap();
+                w.dyn_close().await;
+            };
+
+            let (_, data) = join(send, receive(b)).await;
+            assert_eq!(data, b"def");
+        })
+    }
+}
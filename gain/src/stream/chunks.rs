@@ -0,0 +1,66 @@
+// Copyright (c) 2026 Timo Savola.
+// Use of this source code is governed by the MIT
+// license that can be found in the LICENSE file.
+
+use std::collections::VecDeque;
+use std::io;
+use std::mem::take;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::Stream as FuturesStream;
+
+use crate::core::Chunk;
+use crate::stream::receiver::{self, Shared, SharedRc, Sink, Status};
+use crate::stream::{CloseStream, RecvStream};
+
+impl Sink for VecDeque<Chunk> {
+    fn push(&mut self, data: &[u8], note: i32) {
+        self.push_back((data.to_vec(), note));
+    }
+}
+
+/// Stream of data packets.  See [`RecvStream::chunks`].
+pub struct Chunks {
+    shared: SharedRc<VecDeque<Chunk>>,
+    closer: CloseStream,
+}
+
+impl Chunks {
+    pub(crate) fn new(capacity: usize, stream: RecvStream) -> Self {
+        let shared = Shared::new(VecDeque::new(), Status::Receiving);
+        let (receiver, closer) = stream.split();
+        receiver::spawn(&shared, receiver, capacity);
+        Self { shared, closer }
+    }
+}
+
+impl FuturesStream for Chunks {
+    type Item = io::Result<Chunk>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let mut shared = this.shared.borrow_mut();
+
+        if let Some(chunk) = shared.sink.pop_front() {
+            shared.release(chunk.0.len());
+            return Poll::Ready(Some(Ok(chunk)));
+        }
+
+        let result = match shared.poll_status(cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => return Poll::Pending,
+        };
+
+        // Close the input direction when the end is reached, and yield the
+        // error only once.
+        shared.status = Status::Eof;
+        drop(shared);
+        drop(take(&mut this.closer));
+
+        match result {
+            Ok(_) => Poll::Ready(None),
+            Err(e) => Poll::Ready(Some(Err(e))),
+        }
+    }
+}
@@ -0,0 +1,921 @@
+// Copyright (c) 2026 Timo Savola.
+// Use of this source code is governed by the MIT
+// license that can be found in the LICENSE file.
+
+//! Message framing.
+//!
+//! [`Framed`] turns a buffered stream into a `futures::Stream` of decoded
+//! messages and/or a `futures::Sink` of encoded messages.  It is implemented
+//! for [`ReadWriteStream`] (both), [`ReadStream`] (stream) and
+//! [`WriteStream`] (sink).
+//!
+//! ```ignore
+//! let mut framed = Framed::new(ReadWriteStream::new(stream), NewlineDelimited::new());
+//! framed.send("hello".to_string()).await?;
+//! while let Some(line) = framed.next().await {
+//!     println!("{}", line?);
+//! }
+//! ```
+
+use std::collections::VecDeque;
+use std::io;
+use std::num::NonZeroI32;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::Stream as FuturesStream;
+use futures_sink::Sink;
+
+use crate::core::{self, Stream};
+use crate::stream::buf::{Buf, ReadStream, ReadWriteStream, Reader};
+use crate::stream::WriteStream;
+
+/// Buffered encoded data is flushed by `poll_ready` when it exceeds this.
+const WRITE_BACKPRESSURE: usize = 8192;
+
+/// Message decoder.
+pub trait Decoder {
+    type Item;
+
+    /// Decode a message from the start of the buffer, and consume its bytes.
+    /// Returns `None` if more data is needed.
+    fn decode(&mut self, buf: &mut Buf) -> io::Result<Option<Self::Item>>;
+
+    /// Decode a message after the stream has ended.  By default, data which
+    /// doesn't form a complete message is an error.
+    fn decode_eof(&mut self, buf: &mut Buf) -> io::Result<Option<Self::Item>> {
+        match self.decode(buf)? {
+            Some(item) => Ok(Some(item)),
+            None if buf.is_empty() => Ok(None),
+            None => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "stream ended in the middle of a message",
+            )),
+        }
+    }
+
+    /// Maximum length of an encoded message.  The read buffer isn't grown
+    /// beyond it: if a message doesn't fit, [`Framed`] fails with
+    /// `ErrorKind::InvalidData` and ends.  The default is unlimited.
+    fn max_frame_len(&self) -> usize {
+        usize::MAX
+    }
+}
+
+/// Message encoder.
+pub trait Encoder<Item> {
+    /// Append an encoded message to a buffer.
+    fn encode(&mut self, item: Item, dest: &mut Vec<u8>) -> io::Result<()>;
+
+    /// Note which is attached to the data packet that ends a message.  Zero
+    /// means that message boundaries are not marked.
+    fn end_note(&self) -> i32 {
+        0
+    }
+}
+
+/// Messages prefixed with their length as a 32-bit little-endian integer.
+#[derive(Clone, Debug)]
+pub struct LengthDelimited {
+    max_len: usize,
+}
+
+impl LengthDelimited {
+    /// Default maximum message length.
+    pub const DEFAULT_MAX_LEN: usize = 8 * 1024 * 1024;
+
+    pub fn new() -> Self {
+        Self::with_max_len(Self::DEFAULT_MAX_LEN)
+    }
+
+    /// Create a codec which refuses to decode or encode longer messages.
+    pub fn with_max_len(max_len: usize) -> Self {
+        Self { max_len }
+    }
+}
+
+impl Default for LengthDelimited {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for LengthDelimited {
+    type Item = Vec<u8>;
+
+    fn decode(&mut self, buf: &mut Buf) -> io::Result<Option<Vec<u8>>> {
+        let data = buf.as_slice();
+        if data.len() < 4 {
+            return Ok(None);
+        }
+
+        let len = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+        if len > self.max_len {
+            return Err(too_long());
+        }
+
+        if data.len() < 4 + len {
+            buf.reserve((4 + len).saturating_sub(data.len()));
+            return Ok(None);
+        }
+
+        buf.consume(4);
+        Ok(Some(buf.take(len)))
+    }
+
+    fn max_frame_len(&self) -> usize {
+        self.max_len.saturating_add(4)
+    }
+}
+
+impl<T: AsRef<[u8]>> Encoder<T> for LengthDelimited {
+    fn encode(&mut self, item: T, dest: &mut Vec<u8>) -> io::Result<()> {
+        let data = item.as_ref();
+        if data.len() > self.max_len || data.len() > u32::MAX as usize {
+            return Err(too_long());
+        }
+
+        dest.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        dest.extend_from_slice(data);
+        Ok(())
+    }
+}
+
+/// UTF-8 lines terminated by newline characters.  A carriage return before
+/// the newline is removed when decoding.  The last line of a stream doesn't
+/// need to be terminated.
+#[derive(Clone, Debug)]
+pub struct NewlineDelimited {
+    max_len: usize,
+    scanned: usize,
+    discarding: bool,
+}
+
+impl NewlineDelimited {
+    /// Default maximum line length.
+    pub const DEFAULT_MAX_LEN: usize = 64 * 1024;
+
+    pub fn new() -> Self {
+        Self::with_max_len(Self::DEFAULT_MAX_LEN)
+    }
+
+    /// Create a codec which refuses to decode longer lines (excluding the
+    /// newline character).  The rest of an overlong line is skipped.
+    pub fn with_max_len(max_len: usize) -> Self {
+        Self {
+            max_len,
+            scanned: 0,
+            discarding: false,
+        }
+    }
+
+    fn take_line(&mut self, buf: &mut Buf, len: usize, consume: usize) -> io::Result<String> {
+        self.scanned = 0;
+
+        let mut line = &buf.as_slice()[..len];
+        if line.last() == Some(&b'\r') {
+            line = &line[..len - 1];
+        }
+
+        let result = match std::str::from_utf8(line) {
+            Ok(s) => Ok(s.to_string()),
+            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+
+        buf.consume(consume);
+        result
+    }
+}
+
+impl Default for NewlineDelimited {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for NewlineDelimited {
+    type Item = String;
+
+    fn decode(&mut self, buf: &mut Buf) -> io::Result<Option<String>> {
+        loop {
+            let data = buf.as_slice();
+
+            match data[self.scanned..].iter().position(|&b| b == b'\n') {
+                Some(i) => {
+                    let len = self.scanned + i;
+                    if self.discarding {
+                        self.discarding = false;
+                        self.scanned = 0;
+                        buf.consume(len + 1);
+                        continue;
+                    }
+                    if len > self.max_len {
+                        self.scanned = 0;
+                        buf.consume(len + 1);
+                        return Err(too_long());
+                    }
+                    return self.take_line(buf, len, len + 1).map(Some);
+                }
+
+                None => {
+                    if self.discarding {
+                        buf.consume_all();
+                        return Ok(None);
+                    }
+                    if data.len() > self.max_len {
+                        self.discarding = true;
+                        buf.consume_all();
+                        return Err(too_long());
+                    }
+                    self.scanned = data.len();
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    fn decode_eof(&mut self, buf: &mut Buf) -> io::Result<Option<String>> {
+        match self.decode(buf)? {
+            Some(line) => Ok(Some(line)),
+            None if buf.is_empty() || self.discarding => Ok(None),
+            None => {
+                let len = buf.len();
+                self.take_line(buf, len, len).map(Some)
+            }
+        }
+    }
+
+    fn max_frame_len(&self) -> usize {
+        self.max_len.saturating_add(2) // "\r\n"
+    }
+}
+
+impl<T: AsRef<str>> Encoder<T> for NewlineDelimited {
+    fn encode(&mut self, item: T, dest: &mut Vec<u8>) -> io::Result<()> {
+        dest.extend_from_slice(item.as_ref().as_bytes());
+        dest.push(b'\n');
+        Ok(())
+    }
+}
+
+/// Messages delimited by data packet notes.  When decoding, any non-zero note
+/// ends a message.  When encoding, the configured note is attached to the last
+/// data packet of each message.
+///
+/// Empty messages cannot be transferred, as an empty data packet would close
+/// the stream.
+#[derive(Clone, Debug)]
+pub struct NoteDelimited {
+    note: NonZeroI32,
+    max_len: usize,
+    discarding: bool,
+}
+
+impl NoteDelimited {
+    /// Default maximum message length.
+    pub const DEFAULT_MAX_LEN: usize = 8 * 1024 * 1024;
+
+    /// Create a codec which ends messages with the given note.
+    pub fn new(note: NonZeroI32) -> Self {
+        Self::with_max_len(note, Self::DEFAULT_MAX_LEN)
+    }
+
+    /// Create a codec which refuses to decode or encode longer messages.  The
+    /// rest of an overlong message is skipped.
+    pub fn with_max_len(note: NonZeroI32, max_len: usize) -> Self {
+        Self {
+            note,
+            max_len,
+            discarding: false,
+        }
+    }
+}
+
+impl Default for NoteDelimited {
+    /// Messages are ended with note 1.
+    fn default() -> Self {
+        Self::new(NonZeroI32::new(1).unwrap())
+    }
+}
+
+impl Decoder for NoteDelimited {
+    type Item = Vec<u8>;
+
+    fn decode(&mut self, buf: &mut Buf) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            match buf.note() {
+                Some((len, _)) => {
+                    if self.discarding {
+                        self.discarding = false;
+                        buf.consume(len);
+                        continue;
+                    }
+                    if len > self.max_len {
+                        buf.consume(len);
+                        return Err(too_long());
+                    }
+                    return Ok(Some(buf.take(len)));
+                }
+
+                None => {
+                    if self.discarding {
+                        buf.consume_all();
+                        return Ok(None);
+                    }
+                    if buf.len() > self.max_len {
+                        self.discarding = true;
+                        buf.consume_all();
+                        return Err(too_long());
+                    }
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    fn max_frame_len(&self) -> usize {
+        self.max_len
+    }
+}
+
+impl<T: AsRef<[u8]>> Encoder<T> for NoteDelimited {
+    fn encode(&mut self, item: T, dest: &mut Vec<u8>) -> io::Result<()> {
+        let data = item.as_ref();
+        if data.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "empty message cannot be note-delimited",
+            ));
+        }
+        if data.len() > self.max_len {
+            return Err(too_long());
+        }
+
+        dest.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn end_note(&self) -> i32 {
+        self.note.get()
+    }
+}
+
+fn too_long() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "message too long")
+}
+
+#[derive(PartialEq)]
+enum ReadState {
+    Reading,
+    Eof,
+    Done,
+}
+
+/// Encoded data which hasn't been written yet.
+struct WriteBuf {
+    data: Vec<u8>,
+    offset: usize,
+    notes: VecDeque<(usize, i32)>,
+}
+
+impl WriteBuf {
+    fn pending(&self) -> usize {
+        self.data.len() - self.offset
+    }
+}
+
+/// Stream of decoded messages and sink of encoded messages.
+pub struct Framed<S, C> {
+    stream: S,
+    codec: C,
+    state: ReadState,
+    w: WriteBuf,
+}
+
+impl<S, C> Framed<S, C> {
+    pub fn new(stream: S, codec: C) -> Self {
+        Self {
+            stream,
+            codec,
+            state: ReadState::Reading,
+            w: WriteBuf {
+                data: Vec::new(),
+                offset: 0,
+                notes: VecDeque::new(),
+            },
+        }
+    }
+
+    /// Access the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Access the underlying stream.  Reading or writing it directly mixes
+    /// its data with the framed messages.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// Access the codec.
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    /// Access the codec.
+    pub fn codec_mut(&mut self) -> &mut C {
+        &mut self.codec
+    }
+
+    /// Unwrap the stream and the codec.  Decoded data which has been buffered
+    /// by the stream remains buffered, but encoded messages which haven't been
+    /// flushed are discarded.
+    pub fn into_inner(self) -> (S, C) {
+        (self.stream, self.codec)
+    }
+}
+
+/// Decode the next message.  The read deadline is restarted when a new
+/// message is awaited.
+fn poll_next<C: Decoder>(
+    r: &mut Reader,
+    codec: &mut C,
+    state: &mut ReadState,
+    cx: &mut Context,
+) -> Poll<Option<io::Result<C::Item>>> {
+    r.begin_poll();
+    let result = poll_decode(r, codec, state, cx);
+    if result.is_ready() {
+        r.end_poll();
+    }
+    result
+}
+
+fn poll_decode<C: Decoder>(
+    r: &mut Reader,
+    codec: &mut C,
+    state: &mut ReadState,
+    cx: &mut Context,
+) -> Poll<Option<io::Result<C::Item>>> {
+    loop {
+        let result = match state {
+            ReadState::Reading => codec.decode(&mut r.buf),
+            ReadState::Eof => codec.decode_eof(&mut r.buf),
+            ReadState::Done => return Poll::Ready(None),
+        };
+
+        match result {
+            Ok(Some(item)) => return Poll::Ready(Some(Ok(item))),
+            Ok(None) if *state == ReadState::Eof => {
+                *state = ReadState::Done;
+                return Poll::Ready(None);
+            }
+            Ok(None) => {
+                if r.buf.len() >= r.buf.capacity() {
+                    let max = codec.max_frame_len();
+                    if r.buf.capacity() >= max {
+                        *state = ReadState::Done;
+                        return Poll::Ready(Some(Err(too_long())));
+                    }
+
+                    let n = std::cmp::max(r.buf.capacity(), 1);
+                    r.buf.reserve(std::cmp::min(n, max - r.buf.capacity()));
+                }
+            }
+            Err(e) => {
+                if *state == ReadState::Eof {
+                    *state = ReadState::Done;
+                }
+                return Poll::Ready(Some(Err(e)));
+            }
+        }
+
+        match r.poll_more(cx) {
+            Poll::Ready(Ok(true)) => {}
+            Poll::Ready(Ok(false)) => *state = ReadState::Eof,
+            Poll::Ready(Err(e)) => {
+                *state = ReadState::Done;
+                return Poll::Ready(Some(Err(e)));
+            }
+            Poll::Pending => return Poll::Pending,
+        }
+    }
+}
+
+fn start_send<C: Encoder<T>, T>(codec: &mut C, w: &mut WriteBuf, item: T) -> io::Result<()> {
+    let len = w.data.len();
+    if let Err(e) = codec.encode(item, &mut w.data) {
+        w.data.truncate(len);
+        return Err(e);
+    }
+
+    let note = codec.end_note();
+    if note != 0 && w.data.len() > len {
+        w.notes.push_back((w.data.len(), note));
+    }
+    Ok(())
+}
+
+fn poll_flush(s: &Option<Stream>, w: &mut WriteBuf, cx: &mut Context) -> Poll<io::Result<()>> {
+    while w.pending() > 0 {
+        let (end, note) = match w.notes.front() {
+            Some(&(end, note)) => (end, note),
+            None => (w.data.len(), 0),
+        };
+
+        let n = match core::poll_write_stream(s, cx, &w.data[w.offset..end], note) {
+            Poll::Ready(Ok(0)) => {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "stream is closed",
+                )))
+            }
+            Poll::Ready(Ok(n)) => n,
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => return Poll::Pending,
+        };
+
+        w.offset += n;
+        if w.offset == end && note != 0 {
+            w.notes.pop_front();
+        }
+    }
+
+    w.data.clear();
+    w.offset = 0;
+
+    core::poll_flush_stream(s, cx).map(Ok)
+}
+
+fn poll_ready(s: &Option<Stream>, w: &mut WriteBuf, cx: &mut Context) -> Poll<io::Result<()>> {
+    if w.pending() >= WRITE_BACKPRESSURE {
+        poll_flush(s, w, cx)
+    } else {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<C: Decoder + Unpin> FuturesStream for Framed<ReadStream, C> {
+    type Item = io::Result<C::Item>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        poll_next(&mut this.stream.r, &mut this.codec, &mut this.state, cx)
+    }
+}
+
+impl<C: Decoder + Unpin> FuturesStream for Framed<ReadWriteStream, C> {
+    type Item = io::Result<C::Item>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        poll_next(&mut this.stream.r.r, &mut this.codec, &mut this.state, cx)
+    }
+}
+
+impl<C: Encoder<T> + Unpin, T> Sink<T> for Framed<WriteStream, C> {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        poll_ready(&this.stream.s, &mut this.w, cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> io::Result<()> {
+        let this = self.get_mut();
+        start_send(&mut this.codec, &mut this.w, item)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        poll_flush(&this.stream.s, &mut this.w, cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match poll_flush(&this.stream.s, &mut this.w, cx) {
+            Poll::Ready(Ok(())) => {}
+            other => return other,
+        }
+
+        core::poll_close_stream(
+            &mut this.stream.s,
+            core::STREAM_SELF_DATA,
+            core::STREAM_PEER_FLOW,
+            cx,
+        )
+        .map(Ok)
+    }
+}
+
+impl<C: Encoder<T> + Unpin, T> Sink<T> for Framed<ReadWriteStream, C> {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        poll_ready(&this.stream.w.s, &mut this.w, cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> io::Result<()> {
+        let this = self.get_mut();
+        start_send(&mut this.codec, &mut this.w, item)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        poll_flush(&this.stream.w.s, &mut this.w, cx)
+    }
+
+    /// Closes both directions, like [`Close`](crate::stream::Close).
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match poll_flush(&this.stream.w.s, &mut this.w, cx) {
+            Poll::Ready(Ok(())) => {}
+            other => return other,
+        }
+
+        let closer = &mut this.stream.r.closer;
+        let wait = closer.how << 2; // STREAM_SELF -> STREAM_PEER
+        core::poll_close_stream(&mut closer.s, closer.how, wait, cx).map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::pin;
+    use std::time::Duration;
+
+    use futures_util::future::{join, poll_fn, select, Either};
+    use futures_util::task::noop_waker;
+    use futures_util::StreamExt;
+
+    use super::*;
+    use crate::stream::buf::{Read, DEFAULT_READ_CAPACITY};
+    use crate::stream::{duplex, Write};
+    use crate::task::{sleep, test_block_on};
+
+    fn append(buf: &mut Buf, data: &[u8], notes: &[(usize, i32)]) {
+        buf.append(&mut data.to_vec(), &mut notes.to_vec());
+    }
+
+    fn encoded<C: Encoder<T>, T>(codec: &mut C, items: Vec<T>) -> Vec<u8> {
+        let mut dest = Vec::new();
+        for item in items {
+            codec.encode(item, &mut dest).unwrap();
+        }
+        dest
+    }
+
+    fn kind<T>(result: io::Result<T>) -> io::ErrorKind {
+        result.err().unwrap().kind()
+    }
+
+    #[test]
+    fn length_round_trip() {
+        let mut codec = LengthDelimited::new();
+        let mut buf = Buf::new(64);
+        append(&mut buf, &encoded(&mut codec, vec!["foo", "", "bar"]), &[]);
+
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap(), b"foo");
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap(), b"");
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap(), b"bar");
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        assert!(codec.decode_eof(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn length_split_frame() {
+        let mut codec = LengthDelimited::new();
+        let data = encoded(&mut codec, vec![[7; 100]]);
+        let mut buf = Buf::new(16);
+
+        append(&mut buf, &data[..2], &[]);
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        append(&mut buf, &data[2..50], &[]);
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        assert!(buf.capacity() >= data.len());
+        append(&mut buf, &data[50..], &[]);
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap(), [7; 100]);
+    }
+
+    #[test]
+    fn length_oversize_frame() {
+        let mut codec = LengthDelimited::with_max_len(4);
+        assert_eq!(
+            kind(codec.encode("hello", &mut Vec::new())),
+            io::ErrorKind::InvalidData
+        );
+
+        let mut buf = Buf::new(64);
+        append(&mut buf, &5u32.to_le_bytes(), &[]);
+        assert_eq!(kind(codec.decode(&mut buf)), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn length_eof_mid_frame() {
+        let mut codec = LengthDelimited::new();
+        let data = encoded(&mut codec, vec!["hello"]);
+        let mut buf = Buf::new(64);
+        append(&mut buf, &data[..6], &[]);
+
+        assert_eq!(
+            kind(codec.decode_eof(&mut buf)),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn newline_round_trip() {
+        let mut codec = NewlineDelimited::new();
+        let mut buf = Buf::new(64);
+        append(&mut buf, &encoded(&mut codec, vec!["foo", "", "bar"]), &[]);
+        append(&mut buf, b"baz\r\n", &[]);
+
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap(), "foo");
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap(), "");
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap(), "bar");
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap(), "baz");
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn newline_split_frame() {
+        let mut codec = NewlineDelimited::new();
+        let mut buf = Buf::new(64);
+
+        append(&mut buf, b"hel", &[]);
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        append(&mut buf, b"lo\nwor", &[]);
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap(), "hello");
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        append(&mut buf, b"ld\n", &[]);
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap(), "world");
+    }
+
+    #[test]
+    fn newline_oversize_frame() {
+        let mut codec = NewlineDelimited::with_max_len(3);
+        let mut buf = Buf::new(64);
+
+        append(&mut buf, b"toolong\nok\n", &[]);
+        assert_eq!(kind(codec.decode(&mut buf)), io::ErrorKind::InvalidData);
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap(), "ok");
+
+        // The rest of an unterminated line is skipped.
+        append(&mut buf, b"toolong", &[]);
+        assert_eq!(kind(codec.decode(&mut buf)), io::ErrorKind::InvalidData);
+        append(&mut buf, b"er\nok\n", &[]);
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap(), "ok");
+    }
+
+    #[test]
+    fn newline_eof_mid_frame() {
+        let mut codec = NewlineDelimited::new();
+        let mut buf = Buf::new(64);
+        append(&mut buf, b"foo\nbar", &[]);
+
+        assert_eq!(codec.decode_eof(&mut buf).unwrap().unwrap(), "foo");
+        assert_eq!(codec.decode_eof(&mut buf).unwrap().unwrap(), "bar");
+        assert!(codec.decode_eof(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn note_round_trip() {
+        let mut codec = NoteDelimited::default();
+        assert_eq!(Encoder::<&[u8]>::end_note(&codec), 1);
+
+        let mut buf = Buf::new(64);
+        append(&mut buf, b"foobar", &[(3, 1), (6, 5)]);
+
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap(), b"foo");
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap(), b"bar");
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        assert_eq!(
+            kind(codec.encode("", &mut Vec::new())),
+            io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn note_split_frame() {
+        let mut codec = NoteDelimited::default();
+        let mut buf = Buf::new(64);
+
+        append(&mut buf, b"foo", &[]);
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        append(&mut buf, b"bar", &[(3, 1)]);
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap(), b"foobar");
+    }
+
+    #[test]
+    fn note_oversize_frame() {
+        let note = NonZeroI32::new(1).unwrap();
+        let mut codec = NoteDelimited::with_max_len(note, 3);
+        assert_eq!(
+            kind(codec.encode("hello", &mut Vec::new())),
+            io::ErrorKind::InvalidData
+        );
+
+        let mut buf = Buf::new(64);
+        append(&mut buf, b"toolongok", &[(7, 1), (9, 1)]);
+        assert_eq!(kind(codec.decode(&mut buf)), io::ErrorKind::InvalidData);
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap(), b"ok");
+
+        // The rest of an unterminated message is skipped.
+        append(&mut buf, b"toolong", &[]);
+        assert_eq!(kind(codec.decode(&mut buf)), io::ErrorKind::InvalidData);
+        append(&mut buf, b"erok", &[(2, 1), (4, 1)]);
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap(), b"ok");
+    }
+
+    #[test]
+    fn note_eof_mid_frame() {
+        let mut codec = NoteDelimited::default();
+        let mut buf = Buf::new(64);
+        append(&mut buf, b"foo", &[]);
+
+        assert_eq!(
+            kind(codec.decode_eof(&mut buf)),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn framed_round_trip() {
+        test_block_on(async {
+            let (a, b) = duplex(64);
+            let mut a = Framed::new(a, LengthDelimited::new());
+            let mut b = Framed::new(b, LengthDelimited::new());
+
+            let messages = vec![vec![1; 10], vec![2; 1000], vec![3; 100]];
+
+            let (_, received) = join(
+                async {
+                    for m in &messages {
+                        poll_fn(|cx| Sink::<&Vec<u8>>::poll_ready(Pin::new(&mut a), cx))
+                            .await
+                            .unwrap();
+                        Pin::new(&mut a).start_send(m).unwrap();
+                    }
+                    poll_fn(|cx| Sink::<&Vec<u8>>::poll_close(Pin::new(&mut a), cx))
+                        .await
+                        .unwrap();
+                },
+                async move {
+                    let mut received = Vec::new();
+                    while let Some(m) = b.next().await {
+                        received.push(m.unwrap());
+                    }
+                    received // The stream is closed when it's dropped.
+                },
+            )
+            .await;
+
+            assert_eq!(received, messages);
+        });
+    }
+
+    #[test]
+    fn framed_restarts_deadline() {
+        test_block_on(async {
+            let (_a, mut b) = duplex(64);
+            b.set_read_timeout(Some(Duration::from_millis(50)));
+            let mut dest = [0; 16];
+
+            // Abandon a read which armed the deadline.
+            let waker = noop_waker();
+            let mut cx = Context::from_waker(&waker);
+            let mut f = b.read(&mut dest);
+            assert!(Pin::new(&mut f).poll(&mut cx).is_pending());
+            drop(f);
+
+            sleep(Duration::from_millis(60)).await;
+
+            let mut b = Framed::new(b, LengthDelimited::new());
+            assert!(b.poll_next_unpin(&mut cx).is_pending());
+
+            let e = b.next().await.unwrap().unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+        });
+    }
+
+    #[test]
+    fn framed_stops_growing() {
+        test_block_on(async {
+            let (mut a, b) = duplex(64);
+            let note = NonZeroI32::new(1).unwrap();
+            let max_len = DEFAULT_READ_CAPACITY * 3;
+            let mut b = Framed::new(b, NoteDelimited::with_max_len(note, max_len));
+
+            let data = vec![0; max_len * 2];
+            let result = match select(pin!(a.write_all(&data)), b.next()).await {
+                Either::Right((result, _)) => result.unwrap(),
+                Either::Left(_) => panic!("all data was received"),
+            };
+
+            assert_eq!(kind(result), io::ErrorKind::InvalidData);
+            assert!(b.get_ref().r.r.buf.capacity() <= max_len);
+        });
+    }
+}
@@ -0,0 +1,961 @@
+// Copyright (c) 2026 Timo Savola.
+// Use of this source code is governed by the MIT
+// license that can be found in the LICENSE file.
+
+//! Compressed streams.
+//!
+//! [`CompressWriter`] compresses data before writing it to a stream, and
+//! [`DecompressReader`] decompresses data read from a buffered stream.  The
+//! available encodings depend on the enabled features:
+//!
+//! - `deflate`: raw DEFLATE (RFC 1951) data.  Each flush ends with a sync
+//!   flush block, and closing ends the DEFLATE stream with a final block.
+//! - `zstd`: a sequence of Zstandard frames, each preceded by its length as a
+//!   32-bit little-endian integer.  Each flush ends the current frame.  A frame
+//!   holds at most 64 KiB of uncompressed data.
+//!
+//! Writes with a note are flushed, and the note is attached to the last
+//! packet of the flushed data.  The reader reports the note once the data
+//! written before it has been read.
+//!
+//! The writer implements [`Write`], [`CloseWrite`] and [`Close`], and the
+//! reader implements [`DynRead`], so they can be used in place of other
+//! streams.
+//!
+//! The encoding is negotiated out of band.  With [`peer`](crate::peer)
+//! connections the content type name can carry it as a suffix, e.g.
+//! `text/plain+zstd`; see [`Encoding::type_name`] and
+//! [`Encoding::parse_type_name`].
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::mem::take;
+
+use futures_util::future::LocalBoxFuture;
+
+#[cfg(feature = "deflate")]
+use miniz_oxide::{
+    deflate::core::{create_comp_flags_from_zip_params, CompressorOxide},
+    inflate::stream::InflateState,
+    DataFormat, MZError, MZFlush, MZStatus,
+};
+
+use crate::stream::buf::{self, Buf, ReadStream};
+use crate::stream::{
+    future, Close, CloseWrite, DynRead, ErrorCode, OwnedWrite, Write, WriteStream,
+};
+
+/// Segment size: uncompressed data is processed in chunks of this size, and
+/// Zstandard frames are limited to it.
+const SEGMENT_LEN: usize = 65536;
+
+/// Compressed data is written when this much has accumulated.
+const WRITE_THRESHOLD: usize = 16384;
+
+#[cfg(feature = "deflate")]
+const DEFLATE_LEVEL: i32 = 4;
+
+/// Compression format.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Encoding {
+    #[cfg(feature = "deflate")]
+    Deflate,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Encoding {
+    /// All supported encodings, in order of preference.
+    pub const ALL: &'static [Encoding] = &[
+        #[cfg(feature = "zstd")]
+        Encoding::Zstd,
+        #[cfg(feature = "deflate")]
+        Encoding::Deflate,
+    ];
+
+    /// Encoding name, such as "deflate" or "zstd".
+    pub fn name(self) -> &'static str {
+        match self {
+            #[cfg(feature = "deflate")]
+            Encoding::Deflate => "deflate",
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => "zstd",
+        }
+    }
+
+    /// Look up a supported encoding by name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|e| e.name() == name)
+    }
+
+    /// Append the encoding name to a content type name, separated by a plus
+    /// sign.
+    pub fn type_name(self, base: &str) -> String {
+        format!("{}+{}", base, self.name())
+    }
+
+    /// Split an encoding suffix off a content type name.  The encoding is
+    /// None if the name has no suffix naming a supported encoding, in which
+    /// case the name is returned as is.
+    pub fn parse_type_name(type_name: &str) -> (&str, Option<Self>) {
+        if let Some((base, suffix)) = type_name.rsplit_once('+') {
+            if let Some(e) = Self::from_name(suffix) {
+                return (base, Some(e));
+            }
+        }
+        (type_name, None)
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+enum Compressor {
+    #[cfg(feature = "deflate")]
+    Deflate(Box<CompressorOxide>),
+    #[cfg(feature = "zstd")]
+    Zstd(Vec<u8>),
+}
+
+/// Compressing writer.
+///
+/// Data is buffered until a threshold is reached, or until it's flushed
+/// explicitly.  The writer should be closed with [`close`](Self::close);
+/// buffered data is discarded if it's dropped.
+pub struct CompressWriter<W = WriteStream> {
+    inner: W,
+    compressor: Compressor,
+    out: Vec<u8>,
+    dirty: bool,
+}
+
+impl<W: Write + CloseWrite> CompressWriter<W> {
+    /// Compress data written to a stream.
+    pub fn new(inner: W, encoding: Encoding) -> Self {
+        let compressor = match encoding {
+            #[cfg(feature = "deflate")]
+            Encoding::Deflate => {
+                let flags = create_comp_flags_from_zip_params(DEFLATE_LEVEL, -15, 0);
+                Compressor::Deflate(Box::new(CompressorOxide::new(flags)))
+            }
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => Compressor::Zstd(Vec::new()),
+        };
+
+        Self {
+            inner,
+            compressor,
+            out: Vec::new(),
+            dirty: false,
+        }
+    }
+
+    /// Get a reference to the underlying stream.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the underlying stream.  Writing to it
+    /// directly corrupts the compressed stream.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Unwrap the underlying stream.  Buffered data is discarded.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// Compress a whole byte slice.  Compressed data is written to the stream
+    /// when enough of it has accumulated.
+    pub async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        for chunk in data.chunks(SEGMENT_LEN) {
+            self.compress(chunk, false)?;
+            if self.out.len() >= WRITE_THRESHOLD {
+                self.send(0).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Compress a whole byte slice and flush.  The note is attached to the
+    /// last packet.
+    pub async fn write_note(&mut self, data: &[u8], note: i32) -> io::Result<()> {
+        self.write(data).await?;
+        self.compress(&[], true)?;
+        self.send(note).await
+    }
+
+    /// Write all buffered data to the stream.
+    pub async fn flush(&mut self) -> io::Result<()> {
+        if self.dirty {
+            self.compress(&[], true)?;
+        }
+        self.send(0).await
+    }
+
+    /// Flush, end the compressed stream, and close the output direction of
+    /// the underlying stream.
+    pub async fn close(&mut self) -> io::Result<()> {
+        self.flush().await?;
+        self.finish()?;
+        self.send(0).await?;
+        self.inner.close_write(0).await;
+        Ok(())
+    }
+
+    /// Close the output direction of the underlying stream with an error
+    /// code.  Buffered data is discarded.
+    pub async fn abort(&mut self, code: ErrorCode) {
+        self.out.clear();
+        self.dirty = false;
+        self.inner.abort(code).await;
+    }
+
+    fn compress(&mut self, data: &[u8], flush: bool) -> io::Result<()> {
+        match &mut self.compressor {
+            #[cfg(feature = "deflate")]
+            Compressor::Deflate(c) => {
+                let flush = if flush { MZFlush::Sync } else { MZFlush::None };
+                deflate(c, data, &mut self.out, flush)?;
+            }
+
+            #[cfg(feature = "zstd")]
+            Compressor::Zstd(pending) => {
+                pending.extend_from_slice(data);
+                while pending.len() >= SEGMENT_LEN {
+                    let rest = pending.split_off(SEGMENT_LEN);
+                    write_frame(&mut self.out, pending);
+                    *pending = rest;
+                }
+                if flush {
+                    write_frame(&mut self.out, pending);
+                    pending.clear();
+                }
+            }
+        }
+
+        self.dirty = !flush;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match &mut self.compressor {
+            #[cfg(feature = "deflate")]
+            Compressor::Deflate(c) => deflate(c, &[], &mut self.out, MZFlush::Finish),
+
+            #[cfg(feature = "zstd")]
+            Compressor::Zstd(_) => Ok(()),
+        }
+    }
+
+    async fn send(&mut self, note: i32) -> io::Result<()> {
+        super::write_all_note(&mut self.inner, &self.out, note).await?;
+        self.out.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write + OwnedWrite + CloseWrite> CompressWriter<W> {
+    /// End the compressed stream, and close the underlying stream after the
+    /// rest of the data has been written.
+    fn close_after_end(&mut self, close: impl FnOnce(&mut W) -> future::Close) -> future::Close {
+        let result = self.end();
+        let data = take(&mut self.out);
+        let write = match result {
+            Ok(()) if !data.is_empty() => Some(self.inner.write_owned(data)),
+            _ => None,
+        };
+        let close = close(&mut self.inner); // Detaches the stream.
+
+        future::Close::boxed(Box::pin(async move {
+            if let Some(write) = write {
+                let _ = write.await;
+            }
+            close.await
+        }))
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        if self.dirty {
+            self.compress(&[], true)?;
+        }
+        self.finish()
+    }
+}
+
+impl<W: Write + CloseWrite> Write for CompressWriter<W> {
+    /// Compresses the whole byte slice.
+    fn write<'a>(&'a mut self, data: &'a [u8]) -> future::Write<'a> {
+        future::Write::boxed(Box::pin(async move {
+            self.write(data).await.map(|()| data.len())
+        }))
+    }
+
+    /// Compresses the whole byte slice and flushes.
+    fn write_note<'a>(&'a mut self, data: &'a [u8], note: i32) -> future::Write<'a> {
+        future::Write::boxed(Box::pin(async move {
+            self.write_note(data, note).await.map(|()| data.len())
+        }))
+    }
+
+    fn write_all<'a>(&'a mut self, data: &'a [u8]) -> future::WriteAll<'a> {
+        future::WriteAll::boxed(Box::pin(self.write(data)))
+    }
+}
+
+impl<W: Write + OwnedWrite + CloseWrite> CloseWrite for CompressWriter<W> {
+    /// Ends the compressed stream, and closes the output direction of the
+    /// underlying stream once the rest of the data has been written.
+    fn close_write(&mut self, note: i32) -> future::Close {
+        self.close_after_end(|inner| inner.close_write(note))
+    }
+
+    /// Buffered data is discarded.
+    fn abort(&mut self, code: ErrorCode) -> future::Close {
+        self.out.clear();
+        self.dirty = false;
+        self.inner.abort(code)
+    }
+}
+
+impl<W: Write + OwnedWrite + CloseWrite + Close> Close for CompressWriter<W> {
+    /// Ends the compressed stream, and closes the underlying stream once the
+    /// rest of the data has been written.
+    fn close(&mut self) -> future::Close {
+        self.close_after_end(W::close)
+    }
+}
+
+#[cfg(feature = "deflate")]
+fn deflate(
+    c: &mut CompressorOxide,
+    mut data: &[u8],
+    out: &mut Vec<u8>,
+    flush: MZFlush,
+) -> io::Result<()> {
+    loop {
+        let len = out.len();
+        let space = data.len() / 2 + 64;
+        out.resize(len + space, 0);
+
+        let res = miniz_oxide::deflate::stream::deflate(c, data, &mut out[len..], flush);
+        out.truncate(len + res.bytes_written);
+        data = &data[res.bytes_consumed..];
+
+        match res.status {
+            Ok(MZStatus::StreamEnd) => return Ok(()),
+            Ok(_) => {}
+            Err(MZError::Buf) => {}
+            Err(e) => return Err(io::Error::other(format!("deflate error: {:?}", e))),
+        }
+
+        // Output space was left over, so everything has been processed.
+        if data.is_empty() && res.bytes_written < space {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(feature = "zstd")]
+fn write_frame(out: &mut Vec<u8>, data: &[u8]) {
+    use ruzstd::encoding::{compress_to_vec, CompressionLevel};
+
+    let frame = compress_to_vec(data, CompressionLevel::Fastest);
+    out.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+    out.extend_from_slice(&frame);
+}
+
+enum Decompressor {
+    #[cfg(feature = "deflate")]
+    Deflate {
+        state: Box<InflateState>,
+        boundary: Option<i32>, // Input has been consumed up to a note.
+        need: usize,
+    },
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+/// Decompressing reader.
+pub struct DecompressReader<R = ReadStream> {
+    inner: R,
+    decompressor: Decompressor,
+    out: Vec<u8>,
+    pos: usize,
+    notes: VecDeque<(usize, i32)>, // Positions in out.
+    end: bool,
+}
+
+impl<R: buf::Read> DecompressReader<R> {
+    /// Decompress data read from a buffered stream.
+    pub fn new(inner: R, encoding: Encoding) -> Self {
+        let decompressor = match encoding {
+            #[cfg(feature = "deflate")]
+            Encoding::Deflate => Decompressor::Deflate {
+                state: InflateState::new_boxed(DataFormat::Raw),
+                boundary: None,
+                need: 1,
+            },
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => Decompressor::Zstd,
+        };
+
+        Self {
+            inner,
+            decompressor,
+            out: Vec::new(),
+            pos: 0,
+            notes: VecDeque::new(),
+            end: false,
+        }
+    }
+
+    /// Get a reference to the underlying stream.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the underlying stream.  Reading from it
+    /// directly corrupts the compressed stream.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Unwrap the underlying stream.  Decompressed data which hasn't been
+    /// read is discarded.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Read some decompressed bytes into a slice.  Zero is returned at the
+    /// end of the stream.
+    ///
+    /// Fails with `ErrorKind::UnexpectedEof` if the stream ends in the middle
+    /// of compressed data, and with `ErrorKind::InvalidData` if the data is
+    /// invalid.
+    pub async fn read(&mut self, dest: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.read_note(dest).await? {
+                (0, 0) => return Ok(0),
+                (0, _) => {}
+                (n, _) => return Ok(n),
+            }
+        }
+    }
+
+    /// Read some decompressed bytes into a slice, stopping at a note.  The
+    /// note is returned along with the byte count if all data written before
+    /// it has been read, otherwise it's zero.  The byte count may be zero if
+    /// a note was attached to empty data.
+    ///
+    /// `(0, 0)` is returned at the end of the stream.
+    pub async fn read_note(&mut self, dest: &mut [u8]) -> io::Result<(usize, i32)> {
+        if dest.is_empty() {
+            return Ok((0, 0));
+        }
+
+        loop {
+            let limit = match self.notes.front() {
+                Some(&(pos, _)) => pos,
+                None => self.out.len(),
+            };
+
+            if self.pos < limit || !self.notes.is_empty() {
+                let n = dest.len().min(limit - self.pos);
+                dest[..n].copy_from_slice(&self.out[self.pos..self.pos + n]);
+                self.pos += n;
+
+                let mut note = 0;
+                if self.pos == limit {
+                    if let Some((_, x)) = self.notes.pop_front() {
+                        note = x;
+                    }
+                }
+
+                if self.pos == self.out.len() {
+                    self.out.clear();
+                    for (pos, _) in self.notes.iter_mut() {
+                        *pos -= self.pos;
+                    }
+                    self.pos = 0;
+                }
+
+                return Ok((n, note));
+            }
+
+            if self.end || !self.fill().await? {
+                return Ok((0, 0));
+            }
+        }
+    }
+
+    /// Decompress until at least `min_read` bytes are available or the stream
+    /// ends, and pass them to the receptor.  Notes are included in the buffer.
+    /// The result is None if the stream has ended.
+    async fn buf_read<T>(
+        &mut self,
+        min_read: usize,
+        receptor: impl FnOnce(&mut Buf) -> T,
+    ) -> io::Result<Option<T>> {
+        while self.out.len() - self.pos < min_read && !self.end && self.fill().await? {}
+
+        if self.pos == self.out.len() {
+            return Ok(None);
+        }
+
+        self.out.drain(..self.pos);
+        let mut data = take(&mut self.out);
+        let mut notes = self
+            .notes
+            .drain(..)
+            .map(|(pos, note)| (pos - self.pos, note))
+            .collect();
+        self.pos = 0;
+
+        let mut buf = Buf::new(data.len());
+        buf.append(&mut data, &mut notes);
+        let result = receptor(&mut buf);
+        (self.out, self.notes) = buf.into_parts();
+        Ok(Some(result))
+    }
+
+    /// Decompress more data.  The result is false if the stream has ended.
+    async fn fill(&mut self) -> io::Result<bool> {
+        match &mut self.decompressor {
+            #[cfg(feature = "deflate")]
+            Decompressor::Deflate {
+                state,
+                boundary,
+                need,
+            } => {
+                let out = &mut self.out;
+
+                if let Some(note) = *boundary {
+                    let (_, full) = inflate(state, &[], out, &mut self.end)?;
+                    if !full {
+                        *boundary = None;
+                        self.notes.push_back((out.len(), note));
+                    }
+                    return Ok(true);
+                }
+
+                let end = &mut self.end;
+                let min_read = *need;
+                let res = self
+                    .inner
+                    .buf_read(min_read, |buf: &mut Buf| {
+                        let eof = buf.len() < min_read;
+                        let (limit, note) = match buf.note() {
+                            Some((n, note)) => (n, Some(note)),
+                            None => (buf.len(), None),
+                        };
+
+                        let (consumed, full) =
+                            match inflate(state, &buf.as_slice()[..limit], out, end) {
+                                Ok(x) => x,
+                                Err(e) => return Some(Err(e)),
+                            };
+                        buf.consume(consumed);
+
+                        if consumed == limit {
+                            Some(Ok((1, note, full)))
+                        } else if full || *end {
+                            Some(Ok((1, None, full)))
+                        } else if eof {
+                            Some(Err(truncated()))
+                        } else {
+                            // Nothing more can be processed without more data.
+                            Some(Ok((buf.len() + 1, None, false)))
+                        }
+                    })
+                    .await?;
+
+                match res {
+                    None => Err(truncated()),
+                    Some(Err(e)) => Err(e),
+                    Some(Ok((min_read, note, full))) => {
+                        *need = min_read;
+
+                        if let Some(note) = note {
+                            if full {
+                                *boundary = Some(note);
+                            } else {
+                                self.notes.push_back((self.out.len(), note));
+                            }
+                        }
+                        Ok(true)
+                    }
+                }
+            }
+
+            #[cfg(feature = "zstd")]
+            Decompressor::Zstd => {
+                let len = match self
+                    .inner
+                    .buf_read(4, |buf: &mut Buf| {
+                        let b = buf.as_slice();
+                        if b.len() < 4 {
+                            return Some(None);
+                        }
+                        Some(Some(u32::from_le_bytes(b[..4].try_into().unwrap()) as usize))
+                    })
+                    .await?
+                {
+                    None => return Ok(false),
+                    Some(None) => return Err(truncated()),
+                    Some(Some(n)) => n,
+                };
+
+                if len > 2 * SEGMENT_LEN {
+                    return Err(invalid("zstd frame is too long"));
+                }
+
+                let out = &mut self.out;
+                let res = self
+                    .inner
+                    .buf_read(4 + len, |buf: &mut Buf| {
+                        if buf.len() < 4 + len {
+                            return Some(Err(truncated()));
+                        }
+
+                        let note = match buf.note() {
+                            Some((n, note)) if n <= 4 + len => Some(note),
+                            _ => None,
+                        };
+
+                        let res = decode_frame(&buf.as_slice()[4..4 + len], out);
+                        buf.consume(4 + len);
+                        Some(res.map(|_| note))
+                    })
+                    .await?;
+
+                match res {
+                    None => Err(truncated()),
+                    Some(Err(e)) => Err(e),
+                    Some(Ok(note)) => {
+                        if let Some(note) = note {
+                            self.notes.push_back((self.out.len(), note));
+                        }
+                        Ok(true)
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<R: buf::Read> DynRead for DecompressReader<R> {
+    fn dyn_read<'a>(&'a mut self, dest: &'a mut [u8]) -> LocalBoxFuture<'a, io::Result<usize>> {
+        Box::pin(self.read(dest))
+    }
+
+    fn dyn_buf_read<'a>(
+        &'a mut self,
+        min_read: usize,
+        receptor: &'a mut dyn FnMut(&mut Buf),
+    ) -> LocalBoxFuture<'a, io::Result<()>> {
+        if min_read == 0 {
+            panic!("minimum read length is zero");
+        }
+
+        Box::pin(async move {
+            self.buf_read(min_read, receptor).await?;
+            Ok(())
+        })
+    }
+
+    fn dyn_read_exact<'a>(&'a mut self, dest: &'a mut [u8]) -> LocalBoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let mut filled = 0;
+            while filled < dest.len() {
+                match self.read(&mut dest[filled..]).await? {
+                    0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                    n => filled += n,
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn dyn_read_to_end<'a>(
+        &'a mut self,
+        dest: &'a mut Vec<u8>,
+        limit: usize,
+    ) -> LocalBoxFuture<'a, io::Result<usize>> {
+        Box::pin(async move {
+            let mut count = 0;
+
+            while let Some(n) = self
+                .buf_read(1, |buf: &mut Buf| {
+                    let n = buf.len();
+                    if count + n <= limit {
+                        dest.extend_from_slice(buf.as_slice());
+                        buf.consume_all();
+                    }
+                    n
+                })
+                .await?
+            {
+                count += n;
+                if count > limit {
+                    return Err(invalid("stream data exceeds limit"));
+                }
+            }
+
+            Ok(count)
+        })
+    }
+
+    fn dyn_read_line<'a>(
+        &'a mut self,
+        dest: &'a mut String,
+        max_len: usize,
+    ) -> LocalBoxFuture<'a, io::Result<usize>> {
+        Box::pin(async move {
+            let mut line = Vec::new();
+            let mut overlong = false;
+
+            while let Some(found) = self
+                .buf_read(1, |buf: &mut Buf| {
+                    let data = buf.as_slice();
+                    let (n, found) = match data.iter().position(|&b| b == b'\n') {
+                        Some(i) => (i + 1, true),
+                        None => (data.len(), false),
+                    };
+
+                    // An overlong line is discarded up to and including the
+                    // newline.
+                    overlong |= line.len() + n > max_len;
+                    if !overlong {
+                        line.extend_from_slice(&data[..n]);
+                    }
+                    buf.consume(n);
+                    found
+                })
+                .await?
+            {
+                if found {
+                    break;
+                }
+            }
+
+            if overlong {
+                return Err(invalid("line too long"));
+            }
+
+            match String::from_utf8(line) {
+                Ok(s) => {
+                    dest.push_str(&s);
+                    Ok(s.len())
+                }
+                Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+            }
+        })
+    }
+}
+
+/// Inflate into the output vector.  Returns the number of bytes consumed, and
+/// whether the output space was filled (so there may be more output pending).
+#[cfg(feature = "deflate")]
+fn inflate(
+    state: &mut InflateState,
+    data: &[u8],
+    out: &mut Vec<u8>,
+    end: &mut bool,
+) -> io::Result<(usize, bool)> {
+    let len = out.len();
+    out.resize(len + SEGMENT_LEN, 0);
+
+    let res = miniz_oxide::inflate::stream::inflate(state, data, &mut out[len..], MZFlush::None);
+    out.truncate(len + res.bytes_written);
+
+    match res.status {
+        Ok(MZStatus::StreamEnd) => *end = true,
+        Ok(_) | Err(MZError::Buf) => {}
+        Err(_) => return Err(invalid("invalid deflate data")),
+    }
+
+    Ok((res.bytes_consumed, res.bytes_written == SEGMENT_LEN))
+}
+
+#[cfg(feature = "zstd")]
+fn decode_frame(frame: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+    use std::io::Read;
+
+    let mut source = frame;
+    let decoder = ruzstd::decoding::StreamingDecoder::new(&mut source)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let len = out.len();
+    let n = decoder
+        .take(SEGMENT_LEN as u64 + 1)
+        .read_to_end(out)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if n > SEGMENT_LEN {
+        out.truncate(len);
+        return Err(invalid("zstd frame content is too long"));
+    }
+
+    Ok(())
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "compressed stream ended early",
+    )
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::future::join;
+
+    use super::*;
+    use crate::stream::buf::ReadWriteStream;
+    use crate::stream::{duplex, DynClose, DynWrite};
+    use crate::task::test_block_on;
+
+    fn pair(
+        encoding: Encoding,
+    ) -> (
+        CompressWriter<ReadWriteStream>,
+        DecompressReader<ReadWriteStream>,
+    ) {
+        let (a, b) = duplex(1000);
+        (
+            CompressWriter::new(a, encoding),
+            DecompressReader::new(b, encoding),
+        )
+    }
+
+    #[test]
+    fn round_trip() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i / 7 % 251) as u8).collect();
+
+        for &encoding in Encoding::ALL {
+            test_block_on(async {
+                let (mut w, mut r) = pair(encoding);
+
+                let send = async {
+                    Write::write_all(&mut w, &data[..1000]).await.unwrap();
+                    w.dyn_write_all(&data[1000..]).await.unwrap();
+                    CloseWrite::close_write(&mut w, 0).await;
+                };
+
+                let receive = async {
+                    let mut received = Vec::new();
+                    r.dyn_read_to_end(&mut received, usize::MAX).await.unwrap();
+                    received
+                };
+
+                let (_, received) = join(send, receive).await;
+                assert!(received == data, "{encoding}");
+            })
+        }
+    }
+
+    #[test]
+    fn write_note_flushes() {
+        for &encoding in Encoding::ALL {
+            test_block_on(async {
+                let (mut w, mut r) = pair(encoding);
+
+                w.write(b"hello, ").await.unwrap();
+                Write::write_note(&mut w, b"world\n", 7).await.unwrap();
+
+                // Readable without closing.
+                let mut buf = [0; 64];
+                assert_eq!(r.read_note(&mut buf).await.unwrap(), (13, 7), "{encoding}");
+                assert_eq!(&buf[..13], b"hello, world\n");
+
+                w.write(b"line\n").await.unwrap();
+                w.flush().await.unwrap();
+
+                let mut line = String::new();
+                r.dyn_read_line(&mut line, 100).await.unwrap();
+                assert_eq!(line, "line\n");
+            })
+        }
+    }
+
+    #[test]
+    fn note_in_buf_read() {
+        for &encoding in Encoding::ALL {
+            test_block_on(async {
+                let (mut w, mut r) = pair(encoding);
+
+                w.write_note(b"abc", 5).await.unwrap();
+                w.write_note(b"de", 6).await.unwrap();
+
+                let mut seen = Vec::new();
+                r.dyn_buf_read(5, &mut |buf: &mut Buf| {
+                    seen.push((buf.as_slice().to_vec(), buf.note()));
+                    buf.consume(3);
+                })
+                .await
+                .unwrap();
+                assert_eq!(seen, vec![(b"abcde".to_vec(), Some((3, 5)))], "{encoding}");
+
+                let mut buf = [0; 8];
+                assert_eq!(r.read_note(&mut buf).await.unwrap(), (2, 6));
+                assert_eq!(&buf[..2], b"de");
+            })
+        }
+    }
+
+    #[test]
+    fn close_ends_stream() {
+        for &encoding in Encoding::ALL {
+            test_block_on(async {
+                let (mut w, mut r) = pair(encoding);
+
+                w.write(b"unflushed").await.unwrap();
+
+                let receive = async {
+                    let mut received = Vec::new();
+                    r.dyn_read_to_end(&mut received, 100).await.unwrap();
+                    drop(r);
+                    received
+                };
+
+                let (_, received) = join(w.dyn_close(), receive).await;
+                assert_eq!(received, b"unflushed", "{encoding}");
+            })
+        }
+    }
+
+    #[test]
+    fn truncated_stream() {
+        for &encoding in Encoding::ALL {
+            test_block_on(async {
+                let (mut w, mut r) = pair(encoding);
+
+                w.write(b"unflushed").await.unwrap();
+                w.flush().await.unwrap();
+                w.into_inner().close_write(0).await;
+
+                let mut received = Vec::new();
+                let result = r.dyn_read_to_end(&mut received, 100).await;
+                match encoding {
+                    // The DEFLATE stream lacks the final block.
+                    #[cfg(feature = "deflate")]
+                    Encoding::Deflate => {
+                        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::UnexpectedEof)
+                    }
+                    // Zstandard data is complete at frame boundaries.
+                    #[cfg(feature = "zstd")]
+                    Encoding::Zstd => assert_eq!(result.unwrap(), 9),
+                }
+            })
+        }
+    }
+}
@@ -0,0 +1,162 @@
+// Copyright (c) 2026 Timo Savola.
+// Use of this source code is governed by the MIT
+// license that can be found in the LICENSE file.
+
+use std::io;
+
+use futures_util::future::join;
+
+use crate::stream::buf::{self, Buf, ReadWriteStream};
+use crate::stream::{CloseWrite, ErrorCode, Write};
+
+/// Copy data from a reader to a writer until the reader reaches the end of
+/// its stream.  The writer's output direction is closed afterwards.  Returns
+/// the number of bytes copied.
+///
+/// Reading proceeds only as fast as the writer is granted flow credit.  If
+/// the input stream was closed with an error code, the output direction is
+/// closed with the same code as its note, and the read error is returned.
+/// A write error is returned as is, without closing anything.
+pub async fn copy<R, W>(reader: &mut R, writer: &mut W) -> io::Result<u64>
+where
+    R: buf::Read,
+    W: Write + CloseWrite,
+{
+    let mut total = 0;
+
+    loop {
+        // Takes the buffered data without copying it.
+        let data = match reader
+            .buf_read(1, |buf: &mut Buf| buf.take(buf.len()))
+            .await
+        {
+            Ok(data) if data.is_empty() => break,
+            Ok(data) => data,
+            Err(e) => {
+                if let Some(code) = error_code(&e) {
+                    writer.abort(code).await;
+                }
+                return Err(e);
+            }
+        };
+
+        writer.write_all(&data).await?;
+        total += data.len() as u64;
+    }
+
+    writer.close_write(0).await;
+    Ok(total)
+}
+
+/// Copy data in both directions between two streams until both input
+/// directions have ended.  Each output direction is closed when the opposite
+/// input direction ends, as with [`copy`].  Returns the number of bytes copied
+/// from `a` to `b` and from `b` to `a`.
+///
+/// If either direction fails, the first error is returned after both
+/// directions have finished.
+pub async fn splice(a: &mut ReadWriteStream, b: &mut ReadWriteStream) -> io::Result<(u64, u64)> {
+    let (a_to_b, b_to_a) = join(copy(&mut a.r, &mut b.w), copy(&mut b.r, &mut a.w)).await;
+    Ok((a_to_b?, b_to_a?))
+}
+
+fn error_code(e: &io::Error) -> Option<ErrorCode> {
+    e.get_ref()?.downcast_ref::<ErrorCode>().copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::future::join;
+
+    use super::*;
+    use crate::stream::buf::ReadExt;
+    use crate::stream::{duplex, ErrorKind};
+    use crate::task::test_block_on;
+
+    #[test]
+    fn copy_closes_at_eof() {
+        test_block_on(async {
+            let (mut a, mut b) = duplex(16);
+            let (mut c, mut d) = duplex(16);
+
+            let data: Vec<u8> = (0..1000u32).map(|x| x as u8).collect();
+
+            let ((), (copied, received)) = join(
+                async {
+                    a.write_all(&data).await.unwrap();
+                    a.close_write(0).await;
+                },
+                join(copy(&mut b, &mut c), async {
+                    let mut received = Vec::new();
+                    d.read_to_end(&mut received, usize::MAX).await.unwrap();
+                    received
+                }),
+            )
+            .await;
+
+            assert_eq!(copied.unwrap(), data.len() as u64);
+            assert_eq!(received, data);
+        })
+    }
+
+    #[test]
+    fn copy_forwards_error_code() {
+        test_block_on(async {
+            let (mut a, mut b) = duplex(16);
+            let (mut c, mut d) = duplex(16);
+            let code = ErrorKind::Reset.code().unwrap();
+
+            let ((), (copied, received)) = join(
+                async {
+                    a.write_all(b"partial").await.unwrap();
+                    a.abort(code).await;
+                },
+                join(copy(&mut b, &mut c), async {
+                    let mut received = Vec::new();
+                    let e = d.read_to_end(&mut received, usize::MAX).await.unwrap_err();
+                    (e, received)
+                }),
+            )
+            .await;
+
+            let e = copied.unwrap_err();
+            assert_eq!(error_code(&e), Some(code));
+
+            let (e, received) = received;
+            assert_eq!(error_code(&e), Some(code));
+            assert_eq!(e.kind(), io::ErrorKind::ConnectionReset);
+            assert_eq!(received, b"partial");
+        })
+    }
+
+    #[test]
+    fn splice_both_directions() {
+        test_block_on(async {
+            let (mut a, mut b) = duplex(16);
+            let (mut c, mut d) = duplex(16);
+
+            let (((), ()), spliced) = join(
+                join(
+                    async {
+                        a.write_all(b"ping").await.unwrap();
+                        a.close_write(0).await;
+                        let mut received = Vec::new();
+                        a.read_to_end(&mut received, 100).await.unwrap();
+                        assert_eq!(received, b"pong!");
+                    },
+                    async {
+                        d.write_all(b"pong!").await.unwrap();
+                        d.close_write(0).await;
+                        let mut received = Vec::new();
+                        d.read_to_end(&mut received, 100).await.unwrap();
+                        assert_eq!(received, b"ping");
+                    },
+                ),
+                splice(&mut b, &mut c),
+            )
+            .await;
+
+            assert_eq!(spliced.unwrap(), (4, 5));
+        })
+    }
+}
@@ -0,0 +1,125 @@
+// Copyright (c) 2026 Timo Savola.
+// Use of this source code is governed by the MIT
+// license that can be found in the LICENSE file.
+
+use std::io;
+
+use futures_util::future::LocalBoxFuture;
+
+use crate::stream::buf::{Buf, Read, ReadExt};
+use crate::stream::{Close, Write};
+
+/// Object-safe companion of [`buf::Read`](Read).
+///
+/// Implemented for all buffered readers, so that different kinds of streams
+/// can be stored as `Box<dyn DynRead>` or `Rc<RefCell<dyn DynRead>>`.
+pub trait DynRead {
+    /// Read some bytes into a slice.  See [`Read::read`].
+    fn dyn_read<'a>(&'a mut self, dest: &'a mut [u8]) -> LocalBoxFuture<'a, io::Result<usize>>;
+
+    /// Read buffered data.  See [`Read::buf_read`].
+    ///
+    /// The receptor is not called if the stream has been closed.
+    fn dyn_buf_read<'a>(
+        &'a mut self,
+        min_read: usize,
+        receptor: &'a mut dyn FnMut(&mut Buf),
+    ) -> LocalBoxFuture<'a, io::Result<()>>;
+
+    /// Fill a slice completely.  See [`ReadExt::read_exact`].
+    fn dyn_read_exact<'a>(&'a mut self, dest: &'a mut [u8]) -> LocalBoxFuture<'a, io::Result<()>>;
+
+    /// Read until the end of the stream.  See [`ReadExt::read_to_end`].
+    fn dyn_read_to_end<'a>(
+        &'a mut self,
+        dest: &'a mut Vec<u8>,
+        limit: usize,
+    ) -> LocalBoxFuture<'a, io::Result<usize>>;
+
+    /// Read a UTF-8 line.  See [`ReadExt::read_line`].
+    fn dyn_read_line<'a>(
+        &'a mut self,
+        dest: &'a mut String,
+        max_len: usize,
+    ) -> LocalBoxFuture<'a, io::Result<usize>>;
+}
+
+impl<T: Read> DynRead for T {
+    fn dyn_read<'a>(&'a mut self, dest: &'a mut [u8]) -> LocalBoxFuture<'a, io::Result<usize>> {
+        Box::pin(self.read(dest))
+    }
+
+    fn dyn_buf_read<'a>(
+        &'a mut self,
+        min_read: usize,
+        receptor: &'a mut dyn FnMut(&mut Buf),
+    ) -> LocalBoxFuture<'a, io::Result<()>> {
+        Box::pin(self.buf_read(min_read, move |b: &mut Buf| receptor(b)))
+    }
+
+    fn dyn_read_exact<'a>(&'a mut self, dest: &'a mut [u8]) -> LocalBoxFuture<'a, io::Result<()>> {
+        Box::pin(self.read_exact(dest))
+    }
+
+    fn dyn_read_to_end<'a>(
+        &'a mut self,
+        dest: &'a mut Vec<u8>,
+        limit: usize,
+    ) -> LocalBoxFuture<'a, io::Result<usize>> {
+        Box::pin(self.read_to_end(dest, limit))
+    }
+
+    fn dyn_read_line<'a>(
+        &'a mut self,
+        dest: &'a mut String,
+        max_len: usize,
+    ) -> LocalBoxFuture<'a, io::Result<usize>> {
+        Box::pin(self.read_line(dest, max_len))
+    }
+}
+
+/// Object-safe companion of [`Write`].
+pub trait DynWrite {
+    /// Write part of a byte slice.  See [`Write::write`].
+    fn dyn_write<'a>(&'a mut self, data: &'a [u8]) -> LocalBoxFuture<'a, io::Result<usize>>;
+
+    /// Write part of a byte slice.  See [`Write::write_note`].
+    fn dyn_write_note<'a>(
+        &'a mut self,
+        data: &'a [u8],
+        note: i32,
+    ) -> LocalBoxFuture<'a, io::Result<usize>>;
+
+    /// Write a whole byte slice.  See [`Write::write_all`].
+    fn dyn_write_all<'a>(&'a mut self, data: &'a [u8]) -> LocalBoxFuture<'a, io::Result<()>>;
+}
+
+impl<T: Write> DynWrite for T {
+    fn dyn_write<'a>(&'a mut self, data: &'a [u8]) -> LocalBoxFuture<'a, io::Result<usize>> {
+        Box::pin(self.write(data))
+    }
+
+    fn dyn_write_note<'a>(
+        &'a mut self,
+        data: &'a [u8],
+        note: i32,
+    ) -> LocalBoxFuture<'a, io::Result<usize>> {
+        Box::pin(self.write_note(data, note))
+    }
+
+    fn dyn_write_all<'a>(&'a mut self, data: &'a [u8]) -> LocalBoxFuture<'a, io::Result<()>> {
+        Box::pin(self.write_all(data))
+    }
+}
+
+/// Object-safe companion of [`Close`].
+pub trait DynClose {
+    /// Close a stream.  See [`Close::close`].
+    fn dyn_close(&mut self) -> LocalBoxFuture<'static, ()>;
+}
+
+impl<T: Close> DynClose for T {
+    fn dyn_close(&mut self) -> LocalBoxFuture<'static, ()> {
+        Box::pin(self.close())
+    }
+}
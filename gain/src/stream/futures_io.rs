@@ -0,0 +1,180 @@
+// Copyright (c) 2026 Timo Savola.
+// Use of this source code is governed by the MIT
+// license that can be found in the LICENSE file.
+
+//! Implementations of the futures-io traits.
+//!
+//! Writes accept as much data as the peer has granted flow credit for.  The
+//! data is copied and sent in the background; flushing waits until it has
+//! been sent.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_io::{AsyncBufRead, AsyncRead, AsyncWrite};
+
+use crate::core;
+use crate::stream::buf::{ReadStream, ReadWriteStream, Reader};
+use crate::stream::WriteStream;
+
+/// Wait for buffered data.  The read deadline is restarted when a new read
+/// operation starts.
+fn poll_buf(r: &mut Reader, cx: &mut Context) -> Poll<io::Result<bool>> {
+    r.begin_poll();
+    let result = r.poll_buf(cx, 1);
+    if result.is_ready() {
+        r.end_poll();
+    }
+    result
+}
+
+fn poll_read(r: &mut Reader, cx: &mut Context, dest: &mut [u8]) -> Poll<io::Result<usize>> {
+    match poll_buf(r, cx) {
+        Poll::Ready(Ok(true)) => Poll::Ready(io::Read::read(&mut r.buf, dest)),
+        Poll::Ready(Ok(false)) => Poll::Ready(Ok(0)),
+        Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+        Poll::Pending => Poll::Pending,
+    }
+}
+
+fn poll_fill_buf<'a>(r: &'a mut Reader, cx: &mut Context) -> Poll<io::Result<&'a [u8]>> {
+    match poll_buf(r, cx) {
+        Poll::Ready(Ok(true)) => Poll::Ready(Ok(r.buf.as_slice())),
+        Poll::Ready(Ok(false)) => Poll::Ready(Ok(&[])),
+        Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+        Poll::Pending => Poll::Pending,
+    }
+}
+
+fn poll_flush(s: &Option<core::Stream>, cx: &mut Context) -> Poll<io::Result<()>> {
+    core::poll_flush_stream(s, cx).map(Ok)
+}
+
+impl AsyncRead for ReadStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        dest: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        poll_read(&mut self.get_mut().r, cx, dest)
+    }
+}
+
+impl AsyncBufRead for ReadStream {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<&[u8]>> {
+        poll_fill_buf(&mut self.get_mut().r, cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.get_mut().r.buf.consume(amt)
+    }
+}
+
+impl AsyncRead for ReadWriteStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        dest: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        poll_read(&mut self.get_mut().r.r, cx, dest)
+    }
+}
+
+impl AsyncBufRead for ReadWriteStream {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<&[u8]>> {
+        poll_fill_buf(&mut self.get_mut().r.r, cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.get_mut().r.r.buf.consume(amt)
+    }
+}
+
+impl AsyncWrite for ReadWriteStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, data: &[u8]) -> Poll<io::Result<usize>> {
+        core::poll_write_stream(&self.w.s, cx, data, 0)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        poll_flush(&self.w.s, cx)
+    }
+
+    /// Closes both directions, like [`Close`](crate::stream::Close).
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if poll_flush(&this.w.s, cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        let closer = &mut this.r.closer;
+        let wait = closer.how << 2; // STREAM_SELF -> STREAM_PEER
+        core::poll_close_stream(&mut closer.s, closer.how, wait, cx).map(Ok)
+    }
+}
+
+impl AsyncWrite for WriteStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, data: &[u8]) -> Poll<io::Result<usize>> {
+        core::poll_write_stream(&self.s, cx, data, 0)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        poll_flush(&self.s, cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if poll_flush(&this.s, cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        core::poll_close_stream(
+            &mut this.s,
+            core::STREAM_SELF_DATA,
+            core::STREAM_PEER_FLOW,
+            cx,
+        )
+        .map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::time::Duration;
+
+    use futures_util::future::poll_fn;
+    use futures_util::task::noop_waker;
+
+    use super::*;
+    use crate::stream::buf::Read;
+    use crate::stream::duplex;
+    use crate::task::{sleep, test_block_on};
+
+    #[test]
+    fn poll_read_restarts_deadline() {
+        test_block_on(async {
+            let (_a, mut b) = duplex(16);
+            b.set_read_timeout(Some(Duration::from_millis(50)));
+            let mut dest = [0; 16];
+
+            // Abandon a read which armed the deadline.
+            let waker = noop_waker();
+            let mut cx = Context::from_waker(&waker);
+            let mut f = b.read(&mut dest);
+            assert!(Pin::new(&mut f).poll(&mut cx).is_pending());
+            drop(f);
+
+            sleep(Duration::from_millis(60)).await;
+
+            let mut b = Pin::new(&mut b);
+            assert!(b.as_mut().poll_read(&mut cx, &mut dest).is_pending());
+            assert!(b.as_mut().poll_fill_buf(&mut cx).is_pending());
+
+            let e = poll_fn(|cx| b.as_mut().poll_read(cx, &mut dest))
+                .await
+                .unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+        })
+    }
+}
@@ -0,0 +1,236 @@
+// Copyright (c) 2026 Timo Savola.
+// Use of this source code is governed by the MIT
+// license that can be found in the LICENSE file.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::task::Waker;
+use std::time::Duration;
+
+use crate::gate;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+// Waiting tasks are also woken by their deadlines, so the list may retain
+// tasks which are no longer waiting.  It's flushed when it gets this long;
+// spurious wake-ups are harmless.
+const MAX_WAITERS: usize = 64;
+
+struct Bucket {
+    rate: u64,
+    burst: u64,
+    level: u128, // Tokens multiplied by NANOS_PER_SEC.
+    updated: u64,
+    waiters: Vec<Waker>,
+}
+
+impl Bucket {
+    fn refill(&mut self) {
+        let now = gate::clock_monotonic();
+        let elapsed = now.saturating_sub(self.updated) as u128;
+        self.updated = now;
+
+        let max = self.burst as u128 * NANOS_PER_SEC;
+        self.level = std::cmp::min(self.level + elapsed * self.rate as u128, max);
+    }
+
+    fn wake_all(&mut self) {
+        for w in self.waiters.drain(..) {
+            w.wake();
+        }
+    }
+}
+
+/// Token bucket rate limiter for stream writes.
+///
+/// Each written byte consumes a token.  Tokens accumulate at a constant rate,
+/// up to the burst size; writes are delayed while the bucket is empty.  The
+/// bucket starts full.
+///
+/// A limiter can be attached to any number of streams (see
+/// `WriteStream::set_rate_limiter`) and services (see
+/// [`Service::set_rate_limiter`](crate::service::Service::set_rate_limiter)),
+/// in which case they share the rate.  A write is limited by both its
+/// stream's and its service's limiter.  Clones refer to the same bucket.
+#[derive(Clone)]
+pub struct RateLimiter(Rc<RefCell<Bucket>>);
+
+impl RateLimiter {
+    /// Allow `rate` bytes per second on average, and bursts of up to `burst`
+    /// bytes.  The burst size is at least one byte.
+    pub fn new(rate: u64, burst: u64) -> Self {
+        let burst = std::cmp::max(burst, 1);
+
+        Self(Rc::new(RefCell::new(Bucket {
+            rate,
+            burst,
+            level: burst as u128 * NANOS_PER_SEC,
+            updated: gate::clock_monotonic(),
+            waiters: Vec::new(),
+        })))
+    }
+
+    /// Bytes per second.
+    pub fn rate(&self) -> u64 {
+        self.0.borrow().rate
+    }
+
+    /// Maximum number of bytes which can be written without delay.
+    pub fn burst(&self) -> u64 {
+        self.0.borrow().burst
+    }
+
+    /// Change the rate.  Zero rate stops writes once the bucket is empty.
+    /// Waiting writers are woken up so that they observe the new rate.
+    pub fn set_rate(&self, rate: u64) {
+        let mut b = self.0.borrow_mut();
+        b.refill();
+        b.rate = rate;
+        b.wake_all();
+    }
+
+    /// Change the burst size.  Accumulated tokens in excess of it are
+    /// discarded.  Waiting writers are woken up.
+    pub fn set_burst(&self, burst: u64) {
+        let mut b = self.0.borrow_mut();
+        b.burst = std::cmp::max(burst, 1);
+        b.refill();
+        b.wake_all();
+    }
+
+    /// Number of bytes which can be written without delay at the moment.
+    pub fn available(&self) -> u64 {
+        let mut b = self.0.borrow_mut();
+        b.refill();
+        (b.level / NANOS_PER_SEC) as u64
+    }
+
+    /// Time until a token becomes available, or `None` if the rate is zero.
+    pub(crate) fn delay(&self) -> Option<Duration> {
+        let b = self.0.borrow();
+        if b.rate == 0 {
+            return None;
+        }
+
+        let missing = NANOS_PER_SEC - b.level % NANOS_PER_SEC;
+        let nanos = missing.div_ceil(b.rate as u128);
+        Some(Duration::from_nanos(
+            std::cmp::min(nanos, u64::MAX as u128) as u64
+        ))
+    }
+
+    /// Consume tokens.  The caller must have checked availability.
+    pub(crate) fn take(&self, n: u64) {
+        let mut b = self.0.borrow_mut();
+        b.level = b.level.saturating_sub(n as u128 * NANOS_PER_SEC);
+    }
+
+    /// Wake the tasks which are waiting for an adjustment.
+    pub(crate) fn wake(&self) {
+        self.0.borrow_mut().wake_all();
+    }
+
+    /// Wake a task when the limiter is adjusted, so that a deadline based on
+    /// the old rate is recomputed.
+    pub(crate) fn wait(&self, waker: &Waker) {
+        let mut b = self.0.borrow_mut();
+        if !b.waiters.iter().any(|w| w.will_wake(waker)) {
+            if b.waiters.len() >= MAX_WAITERS {
+                b.wake_all();
+            }
+            b.waiters.push(waker.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use futures_util::task::{waker, ArcWake};
+
+    use super::*;
+
+    struct Counter(AtomicUsize);
+
+    impl ArcWake for Counter {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn set_level(limiter: &RateLimiter, tokens: u128, nanos: u128) {
+        let mut b = limiter.0.borrow_mut();
+        b.level = tokens * NANOS_PER_SEC + nanos;
+        b.updated = gate::clock_monotonic();
+    }
+
+    #[test]
+    fn take() {
+        let limiter = RateLimiter::new(0, 10);
+        assert_eq!(limiter.available(), 10);
+
+        limiter.take(3);
+        assert_eq!(limiter.available(), 7);
+
+        limiter.take(100);
+        assert_eq!(limiter.available(), 0);
+    }
+
+    #[test]
+    fn refill() {
+        let limiter = RateLimiter::new(1000, 1000);
+        set_level(&limiter, 0, 0);
+        std::thread::sleep(Duration::from_millis(10));
+
+        let n = limiter.available();
+        assert!((10..1000).contains(&n), "{n}");
+
+        // Capped at the burst size.
+        limiter.set_burst(5);
+        assert_eq!(limiter.available(), 5);
+
+        // Zero rate doesn't refill.
+        limiter.set_rate(0);
+        limiter.take(5);
+        std::thread::sleep(Duration::from_millis(2));
+        assert_eq!(limiter.available(), 0);
+    }
+
+    #[test]
+    fn delay() {
+        let limiter = RateLimiter::new(1000, 10);
+
+        set_level(&limiter, 0, 0);
+        assert_eq!(limiter.delay(), Some(Duration::from_millis(1)));
+
+        set_level(&limiter, 0, NANOS_PER_SEC / 2);
+        assert_eq!(limiter.delay(), Some(Duration::from_micros(500)));
+
+        set_level(&limiter, 3, NANOS_PER_SEC - 1000);
+        assert_eq!(limiter.delay(), Some(Duration::from_nanos(1)));
+
+        limiter.set_rate(0);
+        assert_eq!(limiter.delay(), None);
+    }
+
+    #[test]
+    fn adjustment_wakes_waiters() {
+        let counter = Arc::new(Counter(AtomicUsize::new(0)));
+        let w = waker(counter.clone());
+        let limiter = RateLimiter::new(1000, 10);
+
+        limiter.wait(&w);
+        limiter.wait(&w);
+        limiter.set_rate(2000);
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+
+        limiter.set_rate(3000);
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+
+        limiter.wait(&w);
+        limiter.set_burst(20);
+        assert_eq!(counter.0.load(Ordering::SeqCst), 2);
+    }
+}
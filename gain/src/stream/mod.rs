@@ -3,12 +3,102 @@
 // license that can be found in the LICENSE file.
 
 //! I/O streams.
+//!
+//! Writes can be time-limited using `set_write_timeout`, and their rate can be
+//! limited using `set_rate_limiter`.  Reception is driven
+//! by flow credit subscriptions, so [`Recv`] has no timeout; the buffered
+//! streams of the [`buf`] module support `set_read_timeout`.
+//!
+//! If the `futures-io` feature is enabled, the `AsyncRead`, `AsyncBufRead` and
+//! `AsyncWrite` traits of the `futures-io` crate are implemented by
+//! [`buf::ReadStream`], [`buf::ReadWriteStream`] and [`WriteStream`].
+//!
+//! The `compress` module is available if the `deflate` or `zstd` feature is
+//! enabled.
+//!
+//! [`OwnedWrite::write_owned`] takes ownership of the data, so the returned
+//! future can be spawned.  If the `bytes` feature is enabled,
+//! `OwnedWrite::write_bytes` does the same for `bytes::Bytes`.
+
+use std::io;
+use std::ops::ControlFlow;
+use std::rc::Rc;
+use std::time::Duration;
 
 use crate::core::{self, Stream, StreamFlags};
 
 pub use crate::core::StreamErrorCode as ErrorCode;
+pub use crate::core::StreamErrorKind as ErrorKind;
+pub use crate::core::StreamStats as Stats;
+pub use chunks::Chunks;
+pub use copy::{copy, splice};
+pub use dynamic::{DynClose, DynRead, DynWrite};
+pub use limit::RateLimiter;
+pub use pipe::{duplex, pipe};
+
+/// Implements the peer state accessors for a type.  The expression yields
+/// the `Option<Stream>` handle which is inspected.
+macro_rules! impl_peer_state {
+    ($t:ty, $this:ident => $s:expr) => {
+        impl $t {
+            /// Returns `true` if the peer has finished sending data, or the
+            /// stream has been closed.
+            pub fn peer_data_closed(&self) -> bool {
+                let $this = self;
+                $crate::core::peer_closed($s, $crate::core::STREAM_PEER_DATA)
+            }
+
+            /// Returns `true` if the peer will not grant more flow credit, or
+            /// the stream has been closed.
+            pub fn peer_flow_closed(&self) -> bool {
+                let $this = self;
+                $crate::core::peer_closed($s, $crate::core::STREAM_PEER_FLOW)
+            }
+
+            /// Returns the number of bytes which can be written without
+            /// waiting for flow credit.
+            pub fn writable_credit(&self) -> usize {
+                let $this = self;
+                $crate::core::writable_credit($s)
+            }
+
+            /// Returns the traffic counters of the stream.
+            pub fn stats(&self) -> $crate::stream::Stats {
+                let $this = self;
+                $crate::core::stream_stats($s)
+            }
+
+            /// Returns the error code conveyed by the peer, if any.  It's
+            /// either the note of the peer's closing data packet, or the
+            /// peer's negative flow increment.
+            pub fn error(&self) -> Option<$crate::stream::ErrorCode> {
+                let $this = self;
+                $crate::core::stream_error($s)
+            }
+
+            /// Wait until the peer has closed both directions of the stream.
+            /// Returns a future.  Doesn't close anything by itself.
+            pub fn closed(&self) -> $crate::stream::future::Closed<'_> {
+                let $this = self;
+                $crate::stream::future::Closed::new($s)
+            }
+        }
+    };
+}
 
 pub mod buf;
+mod chunks;
+pub mod codec;
+#[cfg(any(feature = "deflate", feature = "zstd"))]
+pub mod compress;
+mod copy;
+mod dynamic;
+#[cfg(feature = "futures-io")]
+mod futures_io;
+mod limit;
+pub mod mux;
+mod pipe;
+mod receiver;
 
 /// Data subscriber and receiver.
 pub trait Recv {
@@ -26,7 +116,22 @@ pub trait Recv {
     /// drops to zero.
     fn recv<R>(&mut self, capacity: usize, receptor: R) -> future::Recv<R>
     where
-        R: Fn(&[u8], i32) -> usize + Unpin;
+        R: FnMut(&[u8], i32) -> usize + Unpin;
+
+    /// Receive data packets repeatedly until the receptor breaks the loop.
+    /// Returns a future.
+    ///
+    /// Works like [`recv`](Self::recv), but the receptor returns either the
+    /// capacity to add or a value which stops the reception.  The value is
+    /// passed through as `ControlFlow::Break`; otherwise the result of the
+    /// reception is returned as `ControlFlow::Continue`.
+    ///
+    /// Flow credit which has been granted but not used when the reception is
+    /// stopped carries over to the next reception.  Data which the peer sends
+    /// in the meantime is queued for it.
+    fn recv_until<R, B>(&mut self, capacity: usize, receptor: R) -> future::RecvUntil<'_, R, B>
+    where
+        R: FnMut(&[u8], i32) -> ControlFlow<B, usize> + Unpin;
 }
 
 /// Data writer.
@@ -41,17 +146,80 @@ pub trait Write {
     fn write_all<'a>(&'a mut self, data: &'a [u8]) -> future::WriteAll;
 }
 
+/// Writer of owned buffers.
+pub trait OwnedWrite {
+    /// Write a whole buffer, such as a vector.  Returns a future which doesn't
+    /// borrow the stream or the data, so it can be spawned.
+    fn write_owned<B>(&mut self, data: B) -> future::WriteOwned<B>
+    where
+        B: AsRef<[u8]> + 'static;
+
+    /// Write a whole reference-counted buffer.  Returns a future which
+    /// doesn't borrow the stream or the data, so it can be spawned.
+    #[cfg(feature = "bytes")]
+    fn write_bytes(&mut self, data: bytes::Bytes) -> future::WriteOwned<bytes::Bytes> {
+        self.write_owned(data)
+    }
+}
+
+/// Flow credit inspector.
+pub trait WriteCredit {
+    /// Returns the number of bytes which can be written without waiting for
+    /// flow credit.
+    fn writable_credit(&self) -> usize;
+}
+
 /// Stream closer.
 pub trait Close {
     /// Close a stream.  Returns a future.
     fn close(&mut self) -> future::Close;
 }
 
+/// Output direction closer.
+pub trait CloseWrite {
+    /// Close the output direction of a stream, leaving the input direction
+    /// open.  The note is attached to the closing data packet; a non-zero
+    /// value conveys an error to the peer.  Returns a future.
+    ///
+    /// Nothing can be written after this.
+    fn close_write(&mut self, note: i32) -> future::Close;
+
+    /// Close the output direction with an error code.  The peer's reads
+    /// fail with the corresponding error.  Returns a future.
+    fn abort(&mut self, code: ErrorCode) -> future::Close {
+        self.close_write(code.as_i32())
+    }
+}
+
+/// Write a whole byte slice, attaching the note to the packet which completes
+/// it.  The last byte is written separately if there is a note, as a write
+/// may be partial.
+pub(crate) async fn write_all_note<W: Write>(w: &mut W, data: &[u8], note: i32) -> io::Result<()> {
+    let (init, last) = match (note, data.split_last()) {
+        (_, None) => return Ok(()),
+        (0, _) => (data, &[][..]),
+        (_, Some((last, init))) => (init, std::slice::from_ref(last)),
+    };
+
+    if !init.is_empty() {
+        w.write_all(init).await?;
+    }
+
+    if !last.is_empty() && w.write_note(last, note).await? == 0 {
+        return Err(io::ErrorKind::WriteZero.into());
+    }
+
+    Ok(())
+}
+
 pub mod future {
     pub use crate::core::StreamCloseFuture as Close;
+    pub use crate::core::StreamClosedFuture as Closed;
     pub use crate::core::StreamRecvFuture as Recv;
+    pub use crate::core::StreamRecvUntilFuture as RecvUntil;
     pub use crate::core::StreamWriteAllFuture as WriteAll;
     pub use crate::core::StreamWriteFuture as Write;
+    pub use crate::core::StreamWriteOwnedFuture as WriteOwned;
 }
 
 /// Bidirectional stream.
@@ -64,6 +232,26 @@ impl RecvWriteStream {
         Self { s }
     }
 
+    /// Limit the time to wait for flow credit.  A write which times out fails
+    /// with `ErrorKind::TimedOut`.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        core::set_write_timeout(&self.s, timeout)
+    }
+
+    pub fn write_timeout(&self) -> Option<Duration> {
+        core::write_timeout(&self.s)
+    }
+
+    /// Delay writes according to a rate limiter.  The limiter of the
+    /// stream's service (if any) applies in addition to it.
+    pub fn set_rate_limiter(&mut self, limiter: Option<RateLimiter>) {
+        core::set_rate_limiter(&self.s, limiter)
+    }
+
+    pub fn rate_limiter(&self) -> Option<RateLimiter> {
+        core::rate_limiter(&self.s)
+    }
+
     /// Split the stream into unidirectional parts.
     pub fn split(mut self) -> (RecvStream, WriteStream) {
         let s = self.s.take();
@@ -80,6 +268,47 @@ impl RecvWriteStream {
             CloseStream::new(s, core::STREAM_SELF_FLOW | core::STREAM_SELF_DATA),
         )
     }
+
+    /// Recombine parts returned by `split`.  The parts are returned back if
+    /// they don't belong to the same stream.
+    pub fn reunite(
+        mut r: RecvStream,
+        mut w: WriteStream,
+    ) -> Result<Self, (RecvStream, WriteStream)> {
+        if !same_stream(&r.s, &w.s) {
+            return Err((r, w));
+        }
+
+        w.s.take();
+        Ok(Self::new(r.s.take()))
+    }
+
+    /// Recombine parts returned by `split3`.  The parts are returned back if
+    /// they don't belong to the same stream.
+    pub fn reunite3(
+        mut r: RecvOnlyStream,
+        mut w: WriteOnlyStream,
+        mut c: CloseStream,
+    ) -> Result<Self, (RecvOnlyStream, WriteOnlyStream, CloseStream)> {
+        if !same_stream(&r.s, &w.s)
+            || !same_stream(&r.s, &c.s)
+            || c.how != core::STREAM_SELF_FLOW | core::STREAM_SELF_DATA
+        {
+            return Err((r, w, c));
+        }
+
+        w.s.take();
+        c.s.take();
+        Ok(Self::new(r.s.take()))
+    }
+}
+
+impl_peer_state!(RecvWriteStream, this => &this.s);
+
+impl WriteCredit for RecvWriteStream {
+    fn writable_credit(&self) -> usize {
+        core::writable_credit(&self.s)
+    }
 }
 
 impl Default for RecvWriteStream {
@@ -91,10 +320,17 @@ impl Default for RecvWriteStream {
 impl Recv for RecvWriteStream {
     fn recv<R>(&mut self, capacity: usize, receptor: R) -> future::Recv<R>
     where
-        R: Fn(&[u8], i32) -> usize + Unpin,
+        R: FnMut(&[u8], i32) -> usize + Unpin,
     {
         future::Recv::new(&self.s, capacity, receptor)
     }
+
+    fn recv_until<R, B>(&mut self, capacity: usize, receptor: R) -> future::RecvUntil<'_, R, B>
+    where
+        R: FnMut(&[u8], i32) -> ControlFlow<B, usize> + Unpin,
+    {
+        future::RecvUntil::new(&self.s, capacity, receptor)
+    }
 }
 
 impl Write for RecvWriteStream {
@@ -111,6 +347,15 @@ impl Write for RecvWriteStream {
     }
 }
 
+impl OwnedWrite for RecvWriteStream {
+    fn write_owned<B>(&mut self, data: B) -> future::WriteOwned<B>
+    where
+        B: AsRef<[u8]> + 'static,
+    {
+        future::WriteOwned::new(&self.s, data)
+    }
+}
+
 impl Close for RecvWriteStream {
     fn close(&mut self) -> future::Close {
         future::Close::new(
@@ -148,8 +393,50 @@ impl RecvStream {
             CloseStream::new(s, core::STREAM_SELF_FLOW),
         )
     }
+
+    /// Convert the stream into a `futures::Stream` of data packets and their
+    /// notes.  It ends when the peer closes the stream, possibly with an
+    /// error.
+    ///
+    /// The capacity is the amount of flow credit which is granted to the peer.
+    /// Packets are received in the background, and credit is replenished as
+    /// they are yielded.
+    pub fn chunks(self, capacity: usize) -> Chunks {
+        if capacity == 0 {
+            panic!("chunk stream capacity is zero");
+        }
+
+        Chunks::new(capacity, self)
+    }
+
+    /// Close the stream with an error code.  A negative code is conveyed to
+    /// the peer's writes.  Returns a future.
+    pub fn abort(&mut self, code: ErrorCode) -> future::Close {
+        future::Close::with_note(
+            self.s.take(),
+            core::STREAM_SELF_FLOW,
+            core::STREAM_PEER_DATA,
+            code.as_i32(),
+        )
+    }
+
+    /// Recombine parts returned by `split`.  The parts are returned back if
+    /// they don't belong to the same stream.
+    pub fn reunite(
+        mut r: RecvOnlyStream,
+        mut c: CloseStream,
+    ) -> Result<Self, (RecvOnlyStream, CloseStream)> {
+        if !same_stream(&r.s, &c.s) || c.how != core::STREAM_SELF_FLOW {
+            return Err((r, c));
+        }
+
+        c.s.take();
+        Ok(Self::new(r.s.take()))
+    }
 }
 
+impl_peer_state!(RecvStream, this => &this.s);
+
 impl Default for RecvStream {
     fn default() -> Self {
         Self::new(Default::default())
@@ -166,10 +453,17 @@ impl From<RecvWriteStream> for RecvStream {
 impl Recv for RecvStream {
     fn recv<R>(&mut self, capacity: usize, receptor: R) -> future::Recv<R>
     where
-        R: Fn(&[u8], i32) -> usize + Unpin,
+        R: FnMut(&[u8], i32) -> usize + Unpin,
     {
         future::Recv::new(&self.s, capacity, receptor)
     }
+
+    fn recv_until<R, B>(&mut self, capacity: usize, receptor: R) -> future::RecvUntil<'_, R, B>
+    where
+        R: FnMut(&[u8], i32) -> ControlFlow<B, usize> + Unpin,
+    {
+        future::RecvUntil::new(&self.s, capacity, receptor)
+    }
 }
 
 impl Close for RecvStream {
@@ -199,6 +493,8 @@ impl RecvOnlyStream {
     }
 }
 
+impl_peer_state!(RecvOnlyStream, this => &this.s);
+
 impl Default for RecvOnlyStream {
     fn default() -> Self {
         Self::new(Default::default())
@@ -208,10 +504,17 @@ impl Default for RecvOnlyStream {
 impl Recv for RecvOnlyStream {
     fn recv<R>(&mut self, capacity: usize, receptor: R) -> future::Recv<R>
     where
-        R: Fn(&[u8], i32) -> usize + Unpin,
+        R: FnMut(&[u8], i32) -> usize + Unpin,
     {
         future::Recv::new(&self.s, capacity, receptor)
     }
+
+    fn recv_until<R, B>(&mut self, capacity: usize, receptor: R) -> future::RecvUntil<'_, R, B>
+    where
+        R: FnMut(&[u8], i32) -> ControlFlow<B, usize> + Unpin,
+    {
+        future::RecvUntil::new(&self.s, capacity, receptor)
+    }
 }
 
 impl Drop for RecvOnlyStream {
@@ -230,6 +533,26 @@ impl WriteStream {
         Self { s }
     }
 
+    /// Limit the time to wait for flow credit.  A write which times out fails
+    /// with `ErrorKind::TimedOut`.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        core::set_write_timeout(&self.s, timeout)
+    }
+
+    pub fn write_timeout(&self) -> Option<Duration> {
+        core::write_timeout(&self.s)
+    }
+
+    /// Delay writes according to a rate limiter.  The limiter of the
+    /// stream's service (if any) applies in addition to it.
+    pub fn set_rate_limiter(&mut self, limiter: Option<RateLimiter>) {
+        core::set_rate_limiter(&self.s, limiter)
+    }
+
+    pub fn rate_limiter(&self) -> Option<RateLimiter> {
+        core::rate_limiter(&self.s)
+    }
+
     /// Detach the closing functionality.
     pub fn split(mut self) -> (WriteOnlyStream, CloseStream) {
         let s = self.s.take();
@@ -238,6 +561,28 @@ impl WriteStream {
             CloseStream::new(s, core::STREAM_SELF_DATA),
         )
     }
+
+    /// Recombine parts returned by `split`.  The parts are returned back if
+    /// they don't belong to the same stream.
+    pub fn reunite(
+        mut w: WriteOnlyStream,
+        mut c: CloseStream,
+    ) -> Result<Self, (WriteOnlyStream, CloseStream)> {
+        if !same_stream(&w.s, &c.s) || c.how != core::STREAM_SELF_DATA {
+            return Err((w, c));
+        }
+
+        c.s.take();
+        Ok(Self::new(w.s.take()))
+    }
+}
+
+impl_peer_state!(WriteStream, this => &this.s);
+
+impl WriteCredit for WriteStream {
+    fn writable_credit(&self) -> usize {
+        core::writable_credit(&self.s)
+    }
 }
 
 impl Default for WriteStream {
@@ -267,6 +612,15 @@ impl Write for WriteStream {
     }
 }
 
+impl OwnedWrite for WriteStream {
+    fn write_owned<B>(&mut self, data: B) -> future::WriteOwned<B>
+    where
+        B: AsRef<[u8]> + 'static,
+    {
+        future::WriteOwned::new(&self.s, data)
+    }
+}
+
 impl Close for WriteStream {
     fn close(&mut self) -> future::Close {
         future::Close::new(
@@ -277,6 +631,17 @@ impl Close for WriteStream {
     }
 }
 
+impl CloseWrite for WriteStream {
+    fn close_write(&mut self, note: i32) -> future::Close {
+        future::Close::with_note(
+            self.s.take(),
+            core::STREAM_SELF_DATA,
+            core::STREAM_PEER_FLOW,
+            note,
+        )
+    }
+}
+
 impl Drop for WriteStream {
     fn drop(&mut self) {
         core::drop_stream(self.s.take(), core::STREAM_SELF_DATA)
@@ -285,13 +650,41 @@ impl Drop for WriteStream {
 
 /// Output stream which can be closed asynchronously.
 pub struct WriteOnlyStream {
-    s: Option<Stream>,
+    pub(crate) s: Option<Stream>,
 }
 
 impl WriteOnlyStream {
     fn new(s: Option<Stream>) -> Self {
         Self { s }
     }
+
+    /// Limit the time to wait for flow credit.  A write which times out fails
+    /// with `ErrorKind::TimedOut`.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        core::set_write_timeout(&self.s, timeout)
+    }
+
+    pub fn write_timeout(&self) -> Option<Duration> {
+        core::write_timeout(&self.s)
+    }
+
+    /// Delay writes according to a rate limiter.  The limiter of the
+    /// stream's service (if any) applies in addition to it.
+    pub fn set_rate_limiter(&mut self, limiter: Option<RateLimiter>) {
+        core::set_rate_limiter(&self.s, limiter)
+    }
+
+    pub fn rate_limiter(&self) -> Option<RateLimiter> {
+        core::rate_limiter(&self.s)
+    }
+}
+
+impl_peer_state!(WriteOnlyStream, this => &this.s);
+
+impl WriteCredit for WriteOnlyStream {
+    fn writable_credit(&self) -> usize {
+        core::writable_credit(&self.s)
+    }
 }
 
 impl Default for WriteOnlyStream {
@@ -314,6 +707,23 @@ impl Write for WriteOnlyStream {
     }
 }
 
+impl OwnedWrite for WriteOnlyStream {
+    fn write_owned<B>(&mut self, data: B) -> future::WriteOwned<B>
+    where
+        B: AsRef<[u8]> + 'static,
+    {
+        future::WriteOwned::new(&self.s, data)
+    }
+}
+
+impl CloseWrite for WriteOnlyStream {
+    /// Doesn't wait for the peer; the stream is closed completely by the
+    /// associated `CloseStream`.
+    fn close_write(&mut self, note: i32) -> future::Close {
+        future::Close::with_note(self.s.take(), core::STREAM_SELF_DATA, 0, note)
+    }
+}
+
 impl Drop for WriteOnlyStream {
     fn drop(&mut self) {
         core::drop_stream(self.s.take(), core::STREAM_SELF_DATA)
@@ -322,14 +732,22 @@ impl Drop for WriteOnlyStream {
 
 /// Used to close associated `RecvOnlyStream` and/or `WriteOnlyStream`.
 pub struct CloseStream {
-    s: Option<Stream>,
-    how: StreamFlags,
+    pub(crate) s: Option<Stream>,
+    pub(crate) how: StreamFlags,
 }
 
 impl CloseStream {
     fn new(s: Option<Stream>, how: StreamFlags) -> Self {
         Self { s, how }
     }
+
+    /// Close the stream with an error code.  The code is attached to the
+    /// closing data packet, and a negative code is also conveyed to the
+    /// peer's writes.  Returns a future.
+    pub fn abort(&mut self, code: ErrorCode) -> future::Close {
+        let wait = self.how << 2; // STREAM_SELF -> STREAM_PEER
+        future::Close::with_note(self.s.take(), self.how, wait, code.as_i32())
+    }
 }
 
 impl Default for CloseStream {
@@ -350,3 +768,13 @@ impl Drop for CloseStream {
         core::drop_stream(self.s.take(), self.how)
     }
 }
+
+/// Checks if two handles refer to the same stream state (or neither refers to
+/// any).
+fn same_stream(a: &Option<Stream>, b: &Option<Stream>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
@@ -0,0 +1,538 @@
+// Copyright (c) 2026 Timo Savola.
+// Use of this source code is governed by the MIT
+// license that can be found in the LICENSE file.
+
+//! Stream multiplexing.
+//!
+//! A [`Mux`] carries any number of sub-streams over a single bidirectional
+//! stream, such as a peer connection.  Sub-streams are ordinary
+//! [`RecvWriteStream`]s: each has its own flow credit, so a sub-stream whose
+//! receiver is slow doesn't stall the others.
+//!
+//! Both sides can open sub-streams.  The sides must be created in different
+//! [`Mode`]s so that they allocate distinct sub-stream ids.
+//!
+//! ```ignore
+//! let mux = Mux::new(conn, Mode::Client);
+//! let mut control = ReadWriteStream::new(mux.open());
+//! while let Some(stream) = mux.accept().await {
+//!     spawn_local(serve(stream));
+//! }
+//! ```
+//!
+//! If the underlying stream ends or fails, the open sub-streams fail with
+//! [`ErrorKind::Reset`](super::ErrorKind::Reset).
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::mem::take;
+use std::rc::Rc;
+use std::task::{Poll, Waker};
+
+use futures_util::future::poll_fn;
+
+use crate::core::{self, LocalPeer, Stream, StreamErrorKind};
+use crate::packet::StreamId;
+use crate::stream::buf::{Read, ReadStream};
+use crate::stream::{RecvStream, RecvWriteStream, Write, WriteStream};
+use crate::task::spawn_local;
+
+// Frame header: kind (u8), padding (3 bytes), sub-stream id (i32),
+// value (i32), payload length (u32).
+const HEADER_SIZE: usize = 16;
+const MAX_PAYLOAD_SIZE: usize = 65536;
+
+const KIND_OPEN: u8 = 0;
+const KIND_DATA: u8 = 1; // Value is note.  Empty payload closes.
+const KIND_FLOW: u8 = 2; // Value is increment.  Zero closes.
+
+/// Which side of the connection a multiplexer is on.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mode {
+    /// Allocates odd sub-stream ids.
+    Client,
+    /// Allocates even sub-stream ids.
+    Server,
+}
+
+impl Mode {
+    fn first_id(self) -> StreamId {
+        match self {
+            Mode::Client => 1,
+            Mode::Server => 2,
+        }
+    }
+
+    fn is_remote_id(self, id: StreamId) -> bool {
+        id > 0 && (id & 1) != (self.first_id() & 1)
+    }
+}
+
+struct Shared {
+    mode: Mode,
+    next_id: Cell<StreamId>,
+    streams: RefCell<HashMap<StreamId, Stream>>,
+    incoming: RefCell<VecDeque<Stream>>,
+    acceptor: RefCell<Option<Waker>>,
+    output: RefCell<Vec<u8>>,
+    sender: RefCell<Option<Waker>>,
+    ended: Cell<bool>,   // Underlying stream can't be read anymore.
+    dropped: Cell<bool>, // Mux handle is gone.
+}
+
+impl Shared {
+    fn push_frame(&self, kind: u8, id: StreamId, value: i32, payload: &[u8]) {
+        let mut output = self.output.borrow_mut();
+        output.push(kind);
+        output.extend_from_slice(&[0; 3]);
+        output.extend_from_slice(&id.to_le_bytes());
+        output.extend_from_slice(&value.to_le_bytes());
+        output.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        output.extend_from_slice(payload);
+        drop(output);
+
+        if let Some(w) = self.sender.borrow_mut().take() {
+            w.wake();
+        }
+    }
+
+    fn lookup(&self, id: StreamId) -> Option<Stream> {
+        self.streams.borrow().get(&id).cloned()
+    }
+
+    /// The sender is done when nothing remains to be sent.
+    fn is_done(&self) -> bool {
+        self.dropped.get() && self.streams.borrow().is_empty() && self.output.borrow().is_empty()
+    }
+
+    fn wake_acceptor(&self) {
+        if let Some(w) = self.acceptor.borrow_mut().take() {
+            w.wake();
+        }
+    }
+}
+
+impl LocalPeer for Shared {
+    fn send_data(&self, id: StreamId, data: &[u8], note: i32) {
+        if data.is_empty() {
+            self.push_frame(KIND_DATA, id, note, &[]);
+            return;
+        }
+
+        let mut chunks = data.chunks(MAX_PAYLOAD_SIZE).peekable();
+        while let Some(chunk) = chunks.next() {
+            let note = if chunks.peek().is_none() { note } else { 0 };
+            self.push_frame(KIND_DATA, id, note, chunk);
+        }
+    }
+
+    fn send_flow(&self, id: StreamId, increment: i32) {
+        self.push_frame(KIND_FLOW, id, increment, &[]);
+    }
+
+    fn closed(&self, id: StreamId) {
+        let removed = self.streams.borrow_mut().remove(&id);
+        drop(removed);
+
+        if let Some(w) = self.sender.borrow_mut().take() {
+            w.wake();
+        }
+    }
+}
+
+/// Stream multiplexer.
+///
+/// Dropping the multiplexer closes sub-streams which haven't been accepted.
+/// The underlying stream is closed once all sub-streams have been closed.
+pub struct Mux {
+    shared: Rc<Shared>,
+}
+
+impl Mux {
+    /// Start multiplexing over a stream.  Background tasks are spawned for
+    /// reading and writing it.
+    pub fn new(stream: RecvWriteStream, mode: Mode) -> Self {
+        let shared = Rc::new(Shared {
+            mode,
+            next_id: Cell::new(mode.first_id()),
+            streams: Default::default(),
+            incoming: Default::default(),
+            acceptor: Default::default(),
+            output: Default::default(),
+            sender: Default::default(),
+            ended: Cell::new(false),
+            dropped: Cell::new(false),
+        });
+
+        let (r, w) = stream.split();
+        spawn_local(receive(shared.clone(), r));
+        spawn_local(send(shared.clone(), w));
+
+        Self { shared }
+    }
+
+    /// Open a new sub-stream.  The peer is notified immediately.
+    pub fn open(&self) -> RecvWriteStream {
+        let id = self.shared.next_id.get();
+        self.shared
+            .next_id
+            .set(id.checked_add(2).expect("sub-stream ids exhausted"));
+
+        let s = core::init_local_stream(self.shared.clone(), id);
+
+        if self.shared.ended.get() {
+            reset(&s);
+        } else {
+            self.shared.streams.borrow_mut().insert(id, s.clone());
+            self.shared.push_frame(KIND_OPEN, id, 0, &[]);
+        }
+
+        RecvWriteStream::new(Some(s))
+    }
+
+    /// Wait for a sub-stream opened by the peer.  Returns `None` after the
+    /// underlying stream has ended.
+    pub async fn accept(&self) -> Option<RecvWriteStream> {
+        poll_fn(|cx| {
+            if let Some(s) = self.shared.incoming.borrow_mut().pop_front() {
+                return Poll::Ready(Some(RecvWriteStream::new(Some(s))));
+            }
+
+            if self.shared.ended.get() {
+                return Poll::Ready(None);
+            }
+
+            *self.shared.acceptor.borrow_mut() = Some(cx.waker().clone());
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Number of sub-streams which haven't been closed completely.
+    pub fn len(&self) -> usize {
+        self.shared.streams.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Drop for Mux {
+    fn drop(&mut self) {
+        self.shared.dropped.set(true);
+
+        let incoming = take(&mut *self.shared.incoming.borrow_mut());
+        for s in incoming {
+            drop(RecvWriteStream::new(Some(s)));
+        }
+
+        if let Some(w) = self.shared.sender.borrow_mut().take() {
+            w.wake();
+        }
+    }
+}
+
+fn reset_code() -> i32 {
+    StreamErrorKind::Reset.code().unwrap().as_i32()
+}
+
+/// Fail both directions of a sub-stream.
+fn reset(s: &Stream) {
+    core::receive_local_flow(s, reset_code());
+    core::receive_local_flow(s, 0);
+    core::receive_local_data(s, &[], reset_code());
+}
+
+/// Fail both directions of the peer's end of a sub-stream.  Frames which the
+/// local end sends later are ignored by the peer.
+fn reset_peer(shared: &Shared, id: StreamId) {
+    shared.push_frame(KIND_FLOW, id, reset_code(), &[]);
+    shared.push_frame(KIND_FLOW, id, 0, &[]);
+    shared.push_frame(KIND_DATA, id, reset_code(), &[]);
+}
+
+/// Read frames from the underlying stream and dispatch them.
+async fn receive(shared: Rc<Shared>, stream: RecvStream) {
+    let mut r = ReadStream::with_capacity(HEADER_SIZE + MAX_PAYLOAD_SIZE, stream);
+
+    loop {
+        let header = r
+            .buf_read(HEADER_SIZE, |buf| {
+                if buf.len() < HEADER_SIZE {
+                    return None;
+                }
+                let b = buf.take(HEADER_SIZE);
+                let kind = b[0];
+                let id = StreamId::from_le_bytes(b[4..8].try_into().unwrap());
+                let value = i32::from_le_bytes(b[8..12].try_into().unwrap());
+                let len = u32::from_le_bytes(b[12..16].try_into().unwrap()) as usize;
+                Some((kind, id, value, len))
+            })
+            .await;
+
+        let (kind, id, value, len) = match header {
+            Ok(Some(x)) if x.3 <= MAX_PAYLOAD_SIZE => x,
+            _ => break,
+        };
+
+        let payload = if len > 0 {
+            let payload = r
+                .buf_read(len, |buf| {
+                    if buf.len() < len {
+                        return None;
+                    }
+                    Some(buf.take(len))
+                })
+                .await;
+
+            match payload {
+                Ok(Some(data)) => data,
+                _ => break,
+            }
+        } else {
+            Vec::new()
+        };
+
+        match kind {
+            KIND_OPEN => {
+                if !shared.mode.is_remote_id(id) || shared.lookup(id).is_some() {
+                    break;
+                }
+
+                let s = core::init_local_stream(shared.clone(), id);
+                shared.streams.borrow_mut().insert(id, s.clone());
+
+                if shared.dropped.get() {
+                    drop(RecvWriteStream::new(Some(s)));
+                } else {
+                    shared.incoming.borrow_mut().push_back(s);
+                    shared.wake_acceptor();
+                }
+            }
+
+            KIND_DATA => {
+                if let Some(s) = shared.lookup(id) {
+                    if !core::receive_local_data(&s, &payload, value) {
+                        // Flow credit exceeded.
+                        reset(&s);
+                        reset_peer(&shared, id);
+                    }
+                }
+            }
+
+            KIND_FLOW => {
+                if let Some(s) = shared.lookup(id) {
+                    core::receive_local_flow(&s, value);
+                }
+            }
+
+            _ => break,
+        }
+    }
+
+    shared.ended.set(true);
+    shared.wake_acceptor();
+
+    let streams: Vec<Stream> = shared.streams.borrow().values().cloned().collect();
+    for s in streams {
+        reset(&s);
+    }
+}
+
+/// Write queued frames to the underlying stream.
+async fn send(shared: Rc<Shared>, mut w: WriteStream) {
+    let mut failed = false;
+
+    loop {
+        let data = poll_fn(|cx| {
+            let data = take(&mut *shared.output.borrow_mut());
+            if !data.is_empty() {
+                return Poll::Ready(Some(data));
+            }
+
+            if shared.is_done() {
+                return Poll::Ready(None);
+            }
+
+            *shared.sender.borrow_mut() = Some(cx.waker().clone());
+            Poll::Pending
+        })
+        .await;
+
+        match data {
+            Some(data) => {
+                if !failed && w.write_all(&data).await.is_err() {
+                    failed = true;
+                }
+            }
+            None => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use futures_util::future::join;
+
+    use super::*;
+    use crate::stream::buf::{ReadExt, ReadWriteStream};
+    use crate::stream::{pipe, CloseWrite};
+    use crate::task::test_block_on;
+
+    fn pair() -> (Mux, Mux) {
+        let (a, b) = pipe();
+        (Mux::new(a, Mode::Client), Mux::new(b, Mode::Server))
+    }
+
+    fn frame(kind: u8, id: StreamId, value: i32, payload: &[u8]) -> Vec<u8> {
+        let mut b = vec![kind, 0, 0, 0];
+        b.extend_from_slice(&id.to_le_bytes());
+        b.extend_from_slice(&value.to_le_bytes());
+        b.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        b.extend_from_slice(payload);
+        b
+    }
+
+    /// Read frames until one matches.
+    async fn expect_frame(r: &mut ReadWriteStream, kind: u8, id: StreamId, value: i32) {
+        loop {
+            let mut header = [0; HEADER_SIZE];
+            r.read_exact(&mut header).await.unwrap();
+            let len = u32::from_le_bytes(header[12..16].try_into().unwrap()) as usize;
+            r.read_exact(&mut vec![0; len]).await.unwrap();
+
+            if header[0] == kind
+                && StreamId::from_le_bytes(header[4..8].try_into().unwrap()) == id
+                && i32::from_le_bytes(header[8..12].try_into().unwrap()) == value
+            {
+                return;
+            }
+        }
+    }
+
+    #[test]
+    fn open_and_accept() {
+        test_block_on(async {
+            let (a, b) = pair();
+
+            let mut s = ReadWriteStream::new(a.open());
+            let mut t = ReadWriteStream::new(b.accept().await.unwrap());
+            let mut u = ReadWriteStream::new(b.open());
+            let mut v = ReadWriteStream::new(a.accept().await.unwrap());
+
+            s.write_all(b"ping").await.unwrap();
+            u.write_all(b"pong").await.unwrap();
+
+            let mut buf = [0; 4];
+            t.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"ping");
+            v.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"pong");
+
+            assert_eq!(a.len(), 2);
+            assert_eq!(b.len(), 2);
+        })
+    }
+
+    #[test]
+    fn credit_per_sub_stream() {
+        test_block_on(async {
+            let (a, b) = pair();
+
+            let mut s1 = a.open();
+            let mut s2 = a.open();
+            let mut t1 = ReadWriteStream::with_read_capacity(16, b.accept().await.unwrap());
+            let mut t2 = ReadWriteStream::with_read_capacity(16, b.accept().await.unwrap());
+
+            // Exhaust the credit of the first sub-stream without reading.
+            assert_eq!(s1.write(&[1; 100]).await.unwrap(), 16);
+            assert_eq!(s1.writable_credit(), 0);
+
+            // The second sub-stream isn't affected.
+            s2.write_all(b"hello").await.unwrap();
+            let mut buf = [0; 5];
+            t2.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"hello");
+
+            let mut buf = [0; 16];
+            t1.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf, [1; 16]);
+        })
+    }
+
+    #[test]
+    fn half_close() {
+        test_block_on(async {
+            let (a, b) = pair();
+
+            let mut s = ReadWriteStream::new(a.open());
+            let mut t = ReadWriteStream::new(b.accept().await.unwrap());
+
+            s.write_all(b"request").await.unwrap();
+            s.close_write(0).await;
+
+            let mut request = Vec::new();
+            t.read_to_end(&mut request, 100).await.unwrap();
+            assert_eq!(request, b"request");
+
+            t.write_all(b"response").await.unwrap();
+            t.close_write(0).await;
+
+            let mut response = Vec::new();
+            s.read_to_end(&mut response, 100).await.unwrap();
+            assert_eq!(response, b"response");
+        })
+    }
+
+    #[test]
+    fn credit_overflow_resets() {
+        test_block_on(async {
+            let (x, y) = pipe();
+            let a = Mux::new(x, Mode::Client);
+            let mut y = ReadWriteStream::new(y);
+
+            // The peer ignores flow credit.
+            y.write_all(&frame(KIND_OPEN, 2, 0, &[])).await.unwrap();
+            let mut s = ReadWriteStream::with_read_capacity(16, a.accept().await.unwrap());
+            y.write_all(&frame(KIND_DATA, 2, 0, &[1; 32]))
+                .await
+                .unwrap();
+
+            let e = s.read(&mut [0; 1]).await.unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::ConnectionReset);
+
+            // The peer is told.
+            expect_frame(&mut y, KIND_FLOW, 2, reset_code()).await;
+            expect_frame(&mut y, KIND_FLOW, 2, 0).await;
+            expect_frame(&mut y, KIND_DATA, 2, reset_code()).await;
+        })
+    }
+
+    #[test]
+    fn underlying_eof_resets_all() {
+        test_block_on(async {
+            let (x, y) = pipe();
+            let a = Mux::new(x, Mode::Client);
+            let mut y = ReadWriteStream::new(y);
+
+            let mut s1 = ReadWriteStream::new(a.open());
+            let mut s2 = a.open();
+
+            let (_, accepted) = join(y.close_write(0), a.accept()).await;
+            assert!(accepted.is_none());
+
+            let e = s1.read(&mut [0; 1]).await.unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::ConnectionReset);
+            let e = s2.write(b"x").await.unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::ConnectionReset);
+
+            // Sub-streams opened afterwards fail too.
+            let mut s3 = a.open();
+            let e = s3.write(b"x").await.unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::ConnectionReset);
+        })
+    }
+}
@@ -0,0 +1,178 @@
+// Copyright (c) 2026 Timo Savola.
+// Use of this source code is governed by the MIT
+// license that can be found in the LICENSE file.
+
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+
+use crate::core::{self, LocalPeer, StreamState};
+use crate::packet::StreamId;
+use crate::stream::buf::ReadWriteStream;
+use crate::stream::RecvWriteStream;
+
+/// Forwards packets to the other end of a pipe, if it still exists.
+#[derive(Default)]
+struct PipeEnd {
+    peer: RefCell<Weak<RefCell<StreamState>>>,
+}
+
+impl LocalPeer for PipeEnd {
+    fn send_data(&self, _: StreamId, data: &[u8], note: i32) {
+        if let Some(s) = self.peer.borrow().upgrade() {
+            // Writes are limited by the credit granted by the other end.
+            core::receive_local_data(&s, data, note);
+        }
+    }
+
+    fn send_flow(&self, _: StreamId, increment: i32) {
+        if let Some(s) = self.peer.borrow().upgrade() {
+            core::receive_local_flow(&s, increment);
+        }
+    }
+
+    fn closed(&self, _: StreamId) {}
+}
+
+/// Create a pair of connected in-memory streams.  Data written to one is
+/// received from the other.
+///
+/// The streams behave like streams provided by services: data can be written
+/// only as fast as the other end subscribes to it, notes are conveyed, and
+/// the directions can be closed independently.  They can be used to connect
+/// tasks within the program, or in place of service streams in tests.
+pub fn pipe() -> (RecvWriteStream, RecvWriteStream) {
+    let a_end = Rc::new(PipeEnd::default());
+    let b_end = Rc::new(PipeEnd::default());
+    let a = core::init_local_stream(a_end.clone(), 0);
+    let b = core::init_local_stream(b_end.clone(), 0);
+    *a_end.peer.borrow_mut() = Rc::downgrade(&b);
+    *b_end.peer.borrow_mut() = Rc::downgrade(&a);
+    (RecvWriteStream::new(Some(a)), RecvWriteStream::new(Some(b)))
+}
+
+/// Create a pair of connected in-memory streams with input buffering.  See
+/// [`pipe`].
+pub fn duplex(capacity: usize) -> (ReadWriteStream, ReadWriteStream) {
+    let (a, b) = pipe();
+    (
+        ReadWriteStream::with_read_capacity(capacity, a),
+        ReadWriteStream::with_read_capacity(capacity, b),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use futures_util::future::join;
+    use futures_util::StreamExt;
+
+    use super::*;
+    use crate::stream::buf::ReadExt;
+    use crate::stream::{CloseWrite, ErrorKind, Recv, RecvStream, Write};
+    use crate::task::test_block_on;
+
+    #[test]
+    fn pipe_packets_and_notes() {
+        test_block_on(async {
+            let (mut a, b) = pipe();
+            let mut chunks = RecvStream::from(b).chunks(16);
+
+            let send = async {
+                a.write_note(b"hello", 7).await.unwrap();
+                a.write_all(b"world").await.unwrap();
+                drop(a);
+            };
+
+            let receive = async {
+                let mut received = Vec::new();
+                while let Some(chunk) = chunks.next().await {
+                    received.push(chunk.unwrap());
+                }
+                received
+            };
+
+            let (_, received) = join(send, receive).await;
+            assert_eq!(
+                received,
+                vec![(b"hello".to_vec(), 7), (b"world".to_vec(), 0)]
+            );
+        })
+    }
+
+    #[test]
+    fn pipe_flow_credit() {
+        test_block_on(async {
+            let (mut a, mut b) = pipe();
+
+            assert_eq!(a.writable_credit(), 0);
+
+            let mut received = Vec::new();
+            let (n, result) = join(a.write(&[1; 100]), async {
+                b.recv(30, |data: &[u8], _| {
+                    received.extend_from_slice(data);
+                    0
+                })
+                .await
+            })
+            .await;
+
+            assert_eq!(n.unwrap(), 30);
+            assert_eq!(result, None); // Kept open.
+            assert_eq!(received, vec![1; 30]);
+        })
+    }
+
+    #[test]
+    fn duplex_round_trip() {
+        test_block_on(async {
+            let (mut a, mut b) = duplex(16);
+
+            a.write_all(b"ping").await.unwrap();
+            let mut buf = [0; 4];
+            b.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"ping");
+
+            b.write_all(b"pong").await.unwrap();
+            a.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"pong");
+        })
+    }
+
+    #[test]
+    fn duplex_exceeds_capacity() {
+        test_block_on(async {
+            let (mut a, mut b) = duplex(1000);
+            let data: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+
+            let send = async {
+                a.write_all(&data).await.unwrap();
+                a.close_write(0).await;
+            };
+
+            let receive = async {
+                let mut received = Vec::new();
+                b.read_to_end(&mut received, usize::MAX).await.unwrap();
+                received
+            };
+
+            let (_, received) = join(send, receive).await;
+            assert_eq!(received, data);
+        })
+    }
+
+    #[test]
+    fn duplex_abort() {
+        test_block_on(async {
+            let (mut a, mut b) = duplex(16);
+
+            a.write_all(b"partial").await.unwrap();
+            a.abort(ErrorKind::Reset.code().unwrap()).await;
+
+            let mut received = Vec::new();
+            let e = b.read_to_end(&mut received, usize::MAX).await.unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::ConnectionReset);
+            assert_eq!(received, b"partial");
+        })
+    }
+}
@@ -0,0 +1,135 @@
+// Copyright (c) 2026 Timo Savola.
+// Use of this source code is governed by the MIT
+// license that can be found in the LICENSE file.
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::io;
+use std::mem::take;
+use std::num::NonZeroI32;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+use crate::stream::{ErrorCode, Recv, RecvOnlyStream};
+
+/// Storage for data packets received in the background.
+pub(crate) trait Sink {
+    /// Store the contents of a data packet.  The note is zero if the packet
+    /// didn't have one.
+    fn push(&mut self, data: &[u8], note: i32);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Status {
+    Receiving,
+    Eof,
+    Err(ErrorCode),
+}
+
+/// State shared between a consumer and its background receiver.
+pub(crate) struct Shared<T> {
+    pub(crate) sink: T,
+    pub(crate) status: Status,
+    waker: Option<Waker>,
+    released: usize, // Flow credit to be granted by the receiver.
+    receiver: Option<Waker>,
+}
+
+pub(crate) type SharedRc<T> = Rc<RefCell<Shared<T>>>;
+
+impl<T> Shared<T> {
+    pub(crate) fn new(sink: T, status: Status) -> SharedRc<T> {
+        Rc::new(RefCell::new(Self {
+            sink,
+            status,
+            waker: None,
+            released: 0,
+            receiver: None,
+        }))
+    }
+
+    /// Grant flow credit to the peer for space freed by the consumer.
+    pub(crate) fn release(&mut self, n: usize) {
+        if n > 0 {
+            self.released += n;
+            if let Some(w) = self.receiver.take() {
+                w.wake();
+            }
+        }
+    }
+
+    /// Wait until more data is received or the stream ends.  The result is
+    /// false if the stream has ended.
+    pub(crate) fn poll_status(&mut self, cx: &mut Context) -> Poll<io::Result<bool>> {
+        match self.status {
+            Status::Receiving => {
+                self.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            Status::Eof => Poll::Ready(Ok(false)),
+            Status::Err(e) => Poll::Ready(Err(e.into())),
+        }
+    }
+
+    fn wake(&mut self) {
+        if let Some(w) = self.waker.take() {
+            w.wake();
+        }
+    }
+}
+
+/// Receive data into the sink in the background until the stream ends.  The
+/// peer is initially granted `capacity` bytes of flow credit, and more as it
+/// is released by the consumer.
+pub(crate) fn spawn<T: Sink + 'static>(
+    shared: &SharedRc<T>,
+    stream: RecvOnlyStream,
+    capacity: usize,
+) {
+    crate::task::spawn_local(receive(shared.clone(), stream, capacity));
+}
+
+async fn receive<T: Sink>(shared: SharedRc<T>, mut stream: RecvOnlyStream, capacity: usize) {
+    let mut capacity = capacity;
+
+    let note = loop {
+        let result = stream
+            .recv(capacity, |data: &[u8], note: i32| {
+                let mut shared = shared.borrow_mut();
+                shared.sink.push(data, note);
+                shared.wake();
+                take(&mut shared.released)
+            })
+            .await;
+
+        match result {
+            Some(note) => break note,
+            None => capacity = Released(&shared).await,
+        }
+    };
+
+    let mut shared = shared.borrow_mut();
+    shared.status = match NonZeroI32::new(note) {
+        None => Status::Eof,
+        Some(n) => Status::Err(ErrorCode(n)),
+    };
+    shared.wake();
+}
+
+/// Wait until the consumer has released flow credit.
+struct Released<'a, T>(&'a SharedRc<T>);
+
+impl<T> Future for Released<'_, T> {
+    type Output = usize;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<usize> {
+        let mut shared = self.0.borrow_mut();
+        if shared.released > 0 {
+            Poll::Ready(take(&mut shared.released))
+        } else {
+            shared.receiver = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
@@ -0,0 +1,292 @@
+// Copyright (c) 2026 Timo Savola.
+// Use of this source code is governed by the MIT
+// license that can be found in the LICENSE file.
+
+//! Executor instrumentation.
+//!
+//! Hooks are invoked synchronously by the executor, so they should be cheap.
+//! Poll durations can be measured by taking timestamps in
+//! [`Hooks::poll_start`] and [`Hooks::poll_end`].
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::threadunsafe::ThreadUnsafeRefCell;
+
+lazy_static! {
+    static ref HOOKS: ThreadUnsafeRefCell<Option<Box<dyn Hooks>>> = Default::default();
+    static ref NEXT_ID: ThreadUnsafeRefCell<u64> = Default::default();
+}
+
+/// Identifies a task for the lifetime of the program.
+///
+/// Each `block_on` invocation is also assigned an id.
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct TaskId(u64);
+
+impl TaskId {
+    /// The numeric value of the id.  Ids are assigned in spawning order.
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        self.0.fmt(f)
+    }
+}
+
+/// Task event receiver.  All methods have empty default implementations.
+pub trait Hooks {
+    /// A task was spawned.  The name is set if the task was spawned using
+    /// `spawn_named` or `spawn_local_named`.  The top-level future of
+    /// `block_on` is named "block_on".
+    fn spawn(&self, _id: TaskId, _name: Option<&str>) {}
+
+    /// The executor is about to poll a task.
+    fn poll_start(&self, _id: TaskId) {}
+
+    /// The executor polled a task.
+    fn poll_end(&self, _id: TaskId) {}
+
+    /// A task was woken up.
+    fn wake(&self, _id: TaskId) {}
+
+    /// A task finished, was cancelled or panicked.  It will not be polled
+    /// again.
+    fn complete(&self, _id: TaskId) {}
+}
+
+/// Install task event hooks, replacing previous ones.  Must not be called by
+/// a hook.
+pub fn set_hooks(hooks: Box<dyn Hooks>) {
+    *HOOKS.borrow_mut() = Some(hooks);
+}
+
+/// Uninstall task event hooks.  Must not be called by a hook.
+pub fn clear_hooks() -> Option<Box<dyn Hooks>> {
+    HOOKS.borrow_mut().take()
+}
+
+fn with_hooks<F: FnOnce(&dyn Hooks)>(f: F) {
+    if let Some(hooks) = HOOKS.borrow().as_ref() {
+        f(hooks.as_ref());
+    }
+}
+
+pub(crate) fn spawned(name: Option<&str>) -> TaskId {
+    let id = {
+        let mut next = NEXT_ID.borrow_mut();
+        *next += 1;
+        TaskId(*next - 1)
+    };
+    with_hooks(|h| h.spawn(id, name));
+    id
+}
+
+pub(crate) fn woken(id: TaskId) {
+    with_hooks(|h| h.wake(id));
+}
+
+/// Future wrapper which reports polling.  Completion is reported also if the
+/// future is dropped before it's ready.
+pub(crate) struct Instrumented<F> {
+    id: TaskId,
+    inner: F,
+    complete: bool,
+}
+
+impl<F> Instrumented<F> {
+    pub(crate) fn new(id: TaskId, inner: F) -> Self {
+        Self {
+            id,
+            inner,
+            complete: false,
+        }
+    }
+}
+
+impl<F, T> Future for Instrumented<F>
+where
+    F: Future<Output = T>,
+{
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+        let this = unsafe { self.get_unchecked_mut() };
+        let id = this.id;
+        let inner = unsafe { Pin::new_unchecked(&mut this.inner) };
+
+        with_hooks(|h| h.poll_start(id));
+        let result = inner.poll(cx);
+        with_hooks(|h| h.poll_end(id));
+
+        if result.is_ready() {
+            this.complete = true;
+            with_hooks(|h| h.complete(id));
+        }
+
+        result
+    }
+}
+
+impl<F> Drop for Instrumented<F> {
+    fn drop(&mut self) {
+        if !self.complete {
+            let id = self.id;
+            with_hooks(|h| h.complete(id));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::rc::Rc;
+
+    use futures_util::future::{pending, poll_fn};
+
+    use super::*;
+    use crate::task::{spawn_named, test_block_on};
+
+    #[derive(Debug, Eq, PartialEq)]
+    enum Event {
+        Spawn(Option<String>),
+        PollStart,
+        PollEnd,
+        Wake,
+        Complete,
+    }
+
+    type Events = Rc<RefCell<Vec<(u64, Event)>>>;
+
+    struct Recorder(Events);
+
+    impl Hooks for Recorder {
+        fn spawn(&self, id: TaskId, name: Option<&str>) {
+            let name = name.map(str::to_string);
+            self.0.borrow_mut().push((id.as_u64(), Event::Spawn(name)));
+        }
+
+        fn poll_start(&self, id: TaskId) {
+            self.0.borrow_mut().push((id.as_u64(), Event::PollStart));
+        }
+
+        fn poll_end(&self, id: TaskId) {
+            self.0.borrow_mut().push((id.as_u64(), Event::PollEnd));
+        }
+
+        fn wake(&self, id: TaskId) {
+            self.0.borrow_mut().push((id.as_u64(), Event::Wake));
+        }
+
+        fn complete(&self, id: TaskId) {
+            self.0.borrow_mut().push((id.as_u64(), Event::Complete));
+        }
+    }
+
+    /// Run a future with a recorder installed.  The hooks are global, so
+    /// they are installed while holding the test lock.
+    fn record<F: Future<Output = ()>>(f: F) -> Vec<(u64, Event)> {
+        let events = Events::default();
+
+        test_block_on(async {
+            set_hooks(Box::new(Recorder(events.clone())));
+            f.await;
+            clear_hooks();
+        });
+
+        events.take()
+    }
+
+    /// Events of the first task which was spawned while recording.
+    fn first_task(events: &[(u64, Event)]) -> Vec<&Event> {
+        let id = events
+            .iter()
+            .find(|(_, e)| matches!(e, Event::Spawn(_)))
+            .unwrap()
+            .0;
+
+        events
+            .iter()
+            .filter(|(x, _)| *x == id)
+            .map(|(_, e)| e)
+            .collect()
+    }
+
+    /// Yield to the executor.  (`yield_now` needs the runtime.)
+    async fn yield_once() {
+        let mut yielded = false;
+        poll_fn(|cx| {
+            if yielded {
+                Poll::Ready(())
+            } else {
+                yielded = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    #[test]
+    fn named_task() {
+        let events = record(async {
+            let handle = spawn_named("worker", async {
+                yield_once().await;
+                7
+            });
+            assert_eq!(handle.await, Some(7));
+        });
+
+        assert_eq!(
+            first_task(&events),
+            [
+                &Event::Spawn(Some("worker".to_string())),
+                &Event::PollStart,
+                &Event::PollEnd,
+                &Event::Wake,
+                &Event::PollStart,
+                &Event::PollEnd,
+                &Event::Complete,
+            ]
+        );
+    }
+
+    #[test]
+    fn cancelled_task() {
+        let events = record(async {
+            let handle = spawn_named("stuck", pending::<()>());
+            yield_once().await;
+            handle.cancel();
+            assert_eq!(handle.await, None);
+            yield_once().await; // The executor drops the future.
+        });
+
+        assert_eq!(first_task(&events).last(), Some(&&Event::Complete));
+    }
+
+    #[test]
+    fn panicked_future() {
+        let events = record(async {
+            let id = spawned(None);
+            let mut f = Box::pin(Instrumented::new(id, async { panic!("oops") }));
+
+            let result = catch_unwind(AssertUnwindSafe(|| {
+                let waker = futures_util::task::noop_waker();
+                let _ = f.as_mut().poll(&mut Context::from_waker(&waker));
+            }));
+            assert!(result.is_err());
+            drop(f);
+        });
+
+        assert_eq!(
+            first_task(&events),
+            [&Event::Spawn(None), &Event::PollStart, &Event::Complete]
+        );
+    }
+}
@@ -0,0 +1,219 @@
+// Copyright (c) 2020 Timo Savola.
+// Use of this source code is governed by the MIT
+// license that can be found in the LICENSE file.
+
+//! Types and traits for working with asynchronous tasks.
+//!
+//! Executor instrumentation hooks are provided by the `instrument` module if
+//! the `instrument` feature is enabled.
+
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use async_task::Task;
+use futures_util::future::poll_fn;
+
+use crate::core;
+use crate::core::{Deadline, YieldFuture};
+use crate::threadunsafe::{ThreadUnsafeCell, ThreadUnsafeFuture, ThreadUnsafeRefCell};
+
+#[cfg(feature = "instrument")]
+pub mod instrument;
+
+#[cfg(feature = "instrument")]
+use instrument::Instrumented;
+
+lazy_static! {
+    static ref TASKS: ThreadUnsafeRefCell<VecDeque<Task<()>>> = Default::default();
+}
+
+/// A handle that awaits the result of a task.
+pub type JoinHandle<T> = async_task::JoinHandle<T, ()>;
+
+/// Spawn a task and block the program on its result.
+#[inline(always)]
+pub fn block_on<F, T>(future: F) -> T
+where
+    F: Future<Output = T>,
+{
+    block_on_boxed(Box::pin(future))
+}
+
+fn block_on_boxed<F, T>(future: Pin<Box<F>>) -> T
+where
+    F: Future<Output = T>,
+{
+    #[cfg(feature = "instrument")]
+    let id = instrument::spawned(Some("block_on"));
+    #[cfg(feature = "instrument")]
+    let mut future = Box::pin(Instrumented::new(id, future));
+    #[cfg(not(feature = "instrument"))]
+    let mut future = future;
+
+    let rerun = Arc::new(ThreadUnsafeCell::new(false));
+    let wakerun = rerun.clone();
+    let waker = async_task::waker_fn(move || {
+        #[cfg(feature = "instrument")]
+        instrument::woken(id);
+        wakerun.set(true)
+    });
+    let cx = &mut Context::from_waker(&waker);
+
+    loop {
+        if let Poll::Ready(result) = future.as_mut().poll(cx) {
+            return result;
+        }
+
+        loop {
+            // Can't access TASKS in the same for/if with run().
+            let task = TASKS.borrow_mut().pop_front();
+            if let Some(task) = task {
+                task.run();
+            } else {
+                break;
+            }
+        }
+
+        if !rerun.replace(false) {
+            core::io();
+        }
+    }
+}
+
+/// Run a future to completion in a host test.  The runtime state isn't
+/// thread-local, so tests are serialized.
+#[cfg(test)]
+pub(crate) fn test_block_on<F, T>(future: F) -> T
+where
+    F: Future<Output = T>,
+{
+    static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    block_on(future)
+}
+
+/// Spawn a new task.
+pub fn spawn<F, T>(future: F) -> JoinHandle<T>
+where
+    F: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_task(None, future)
+}
+
+/// Spawn a new task with a name.  The name is visible to instrumentation
+/// hooks (see the `instrument` feature).
+pub fn spawn_named<F, T>(name: &str, future: F) -> JoinHandle<T>
+where
+    F: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_task(Some(name), future)
+}
+
+#[cfg(not(feature = "instrument"))]
+fn spawn_task<F, T>(_name: Option<&str>, future: F) -> JoinHandle<T>
+where
+    F: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    let (task, handle) = async_task::spawn(future, |task| TASKS.borrow_mut().push_back(task), ());
+    task.schedule();
+    handle
+}
+
+#[cfg(feature = "instrument")]
+fn spawn_task<F, T>(name: Option<&str>, future: F) -> JoinHandle<T>
+where
+    F: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    let id = instrument::spawned(name);
+    let (task, handle) = async_task::spawn(
+        Instrumented::new(id, future),
+        move |task| {
+            instrument::woken(id);
+            TASKS.borrow_mut().push_back(task)
+        },
+        (),
+    );
+    TASKS.borrow_mut().push_back(task); // Initial scheduling is not a wakeup.
+    handle
+}
+
+/// Spawn a new local task.
+///
+/// ```
+/// // TODO: T shouldn't require Send trait.
+/// ```
+#[inline(always)]
+pub fn spawn_local<F, T>(future: F) -> JoinHandle<T>
+where
+    F: Future<Output = T> + 'static,
+    T: Send + 'static,
+{
+    spawn(ThreadUnsafeFuture(future))
+}
+
+/// Spawn a new local task with a name.  The name is visible to
+/// instrumentation hooks (see the `instrument` feature).
+#[inline(always)]
+pub fn spawn_local_named<F, T>(name: &str, future: F) -> JoinHandle<T>
+where
+    F: Future<Output = T> + 'static,
+    T: Send + 'static,
+{
+    spawn_named(name, ThreadUnsafeFuture(future))
+}
+
+/// Yield execution back to the runtime.
+pub async fn yield_now() {
+    YieldFuture::new().await;
+}
+
+/// Wait until a duration has elapsed.
+pub async fn sleep(duration: Duration) {
+    let mut deadline = Deadline::after(duration);
+    poll_fn(|cx| deadline.poll(cx)).await
+}
+
+/// Conversion of a task result into a program return code.
+///
+/// Used by the `gain::main` and `gain::export` attribute macros.
+pub trait ReturnCode {
+    /// Zero means success.
+    fn return_code(self) -> i32;
+}
+
+impl ReturnCode for () {
+    fn return_code(self) -> i32 {
+        0
+    }
+}
+
+impl ReturnCode for i32 {
+    fn return_code(self) -> i32 {
+        self
+    }
+}
+
+impl<T, E> ReturnCode for Result<T, E>
+where
+    T: ReturnCode,
+    E: Debug,
+{
+    fn return_code(self) -> i32 {
+        match self {
+            Ok(x) => x.return_code(),
+            Err(e) => {
+                eprintln!("Error: {:?}", e);
+                1
+            }
+        }
+    }
+}